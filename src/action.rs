@@ -1,15 +1,14 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
-use anyhow::bail;
-use libp2p::gossipsub;
+use anyhow::{anyhow, bail};
 
-use crate::network::Client;
+use decent_share::{Client, ClientError, PeerId, Receipt, Reputation, SignedReceipt, TradeId};
 
 pub(crate) async fn handle_send(
     message: &str,
     network_client: &mut Client,
-) -> Result<(), gossipsub::PublishError> {
+) -> Result<(), ClientError> {
     network_client.send_message(message.to_string()).await
 }
 pub(crate) async fn handle_trade(
@@ -19,7 +18,7 @@ pub(crate) async fn handle_trade(
     requested_file_name: &str,
     requested_file_path_string: &str,
     network_client: &mut Client,
-) -> Result<(), anyhow::Error> {
+) -> Result<TradeId, anyhow::Error> {
     let offered_file_path = PathBuf::from_str(offered_file_path_string)?;
     if !offered_file_path.is_file() {
         bail!("'{offered_file_path_string}' does not point to a file!");
@@ -31,7 +30,7 @@ pub(crate) async fn handle_trade(
 
     let offered_file_bytes = tokio::fs::read(offered_file_path).await?;
 
-    network_client
+    let trade_id = network_client
         .offer_trade(
             offered_file_name.to_owned(),
             offered_file_bytes,
@@ -41,17 +40,16 @@ pub(crate) async fn handle_trade(
         )
         .await?;
 
-    Ok(())
+    Ok(trade_id)
 }
 
 pub(crate) async fn handle_accept_trade(
-    username: &str,
-    offered_file_name: &str,
+    trade_id: &str,
     offered_file_path_string: &str,
-    requested_file_name: &str,
     requested_file_path_string: &str,
     network_client: &mut Client,
 ) -> Result<(), anyhow::Error> {
+    let trade_id = parse_trade_id(trade_id)?;
     let requested_file_path = PathBuf::from_str(requested_file_path_string)?;
     if !requested_file_path.is_file() {
         bail!("'{requested_file_path_string}' does not point to a file!");
@@ -63,24 +61,121 @@ pub(crate) async fn handle_accept_trade(
     }
 
     let requested_file_bytes = tokio::fs::read(requested_file_path).await?;
-    let offered_file_bytes = network_client
-        .accept_trade(
-            username.to_owned(),
-            requested_file_name.to_owned(),
-            offered_file_name.to_owned(),
-            requested_file_bytes,
-        )
+    network_client
+        .accept_trade(trade_id, requested_file_bytes, offered_file_path)
         .await?;
 
-    if let Some(parent_directory) = offered_file_path.parent() {
-        tokio::fs::create_dir_all(parent_directory)
+    Ok(())
+}
+
+pub(crate) async fn handle_decline_trade(
+    trade_id: &str,
+    network_client: &mut Client,
+) -> Result<(), anyhow::Error> {
+    let trade_id = parse_trade_id(trade_id)?;
+    network_client.decline_trade(trade_id).await?;
+
+    Ok(())
+}
+
+fn parse_trade_id(trade_id: &str) -> Result<TradeId, anyhow::Error> {
+    trade_id
+        .parse()
+        .map_err(|_| anyhow!("'{trade_id}' is not a trade ID, see the IDs listed by `trades`"))
+}
+
+/// Blocking or muting a user, or undoing either.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PeerAction {
+    Block,
+    Unblock,
+    Mute,
+    Unmute,
+}
+
+impl PeerAction {
+    pub(crate) fn parse(action: &str) -> Option<Self> {
+        match action {
+            "block" => Some(Self::Block),
+            "unblock" => Some(Self::Unblock),
+            "mute" => Some(Self::Mute),
+            "unmute" => Some(Self::Unmute),
+            _ => None,
+        }
+    }
+
+    /// What has become of the user, e.g. "blocked".
+    pub(crate) fn outcome(self) -> &'static str {
+        match self {
+            Self::Block => "blocked",
+            Self::Unblock => "unblocked",
+            Self::Mute => "muted",
+            Self::Unmute => "unmuted",
+        }
+    }
+}
+
+pub(crate) async fn handle_peer_action(
+    action: PeerAction,
+    username: &str,
+    network_client: &mut Client,
+) -> Result<(), ClientError> {
+    let peer_id = network_client.get_peer_id(username.to_owned()).await?;
+    match action {
+        PeerAction::Block => network_client.block_peer(peer_id).await,
+        PeerAction::Unblock => network_client.unblock_peer(peer_id).await,
+        PeerAction::Mute => network_client.mute_peer(peer_id).await,
+        PeerAction::Unmute => network_client.unmute_peer(peer_id).await,
+    }
+}
+
+/// The usernames of the blocked and muted users, or the peer IDs of those
+/// whose username can't be found.
+pub(crate) async fn handle_peer_list(
+    network_client: &mut Client,
+) -> Result<(Vec<String>, Vec<String>), ClientError> {
+    let peer_list = network_client.peer_list().await?;
+    let blocked = names(peer_list.blocked, network_client).await;
+    let muted = names(peer_list.muted, network_client).await;
+    Ok((blocked, muted))
+}
+
+async fn names(
+    peer_ids: impl IntoIterator<Item = PeerId>,
+    network_client: &mut Client,
+) -> Vec<String> {
+    let mut names = Vec::new();
+    for peer_id in peer_ids {
+        let name = network_client
+            .get_username(peer_id)
             .await
-            .expect("Failed to create parent directories");
+            .unwrap_or_else(|_| peer_id.to_string());
+        names.push(name);
     }
-    tokio::fs::write(offered_file_path, offered_file_bytes).await?;
-    println!(
-        "{username}'s '{offered_file_name}' file is now available at '{offered_file_path_string}'"
-    );
+    names
+}
 
-    Ok(())
+/// The reputation of `username`, as recorded by our node or, when `from` is
+/// given, as attested by that user.
+pub(crate) async fn handle_reputation(
+    username: &str,
+    from: Option<&str>,
+    network_client: &mut Client,
+) -> Result<Reputation, ClientError> {
+    let peer_id = network_client.get_peer_id(username.to_owned()).await?;
+    match from {
+        Some(from) => {
+            let issuer = network_client.get_peer_id(from.to_owned()).await?;
+            let attestation = network_client.query_reputation(issuer, peer_id).await?;
+            Ok(attestation.reputation)
+        }
+        None => network_client.reputation(peer_id).await,
+    }
+}
+
+/// The receipt saved at `path`, if it is signed by both parties to the trade.
+pub(crate) fn handle_verify_receipt(path: impl AsRef<Path>) -> Result<Receipt, anyhow::Error> {
+    let contents = std::fs::read(path)?;
+    let signed: SignedReceipt = serde_json::from_slice(&contents)?;
+    Ok(signed.verify()?)
 }
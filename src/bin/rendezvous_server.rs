@@ -18,13 +18,14 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use std::error::Error;
+use std::{error::Error, path::PathBuf, time::Duration};
 
+use clap::Parser;
+use decent_share::{build_transport, read_swarm_key, RENDEZVOUS_POINT_PORT_NUMBER};
 use futures::StreamExt;
 use libp2p::{
-    identify, noise, rendezvous,
+    identify, kad, rendezvous,
     swarm::{NetworkBehaviour, SwarmEvent},
-    tcp, yamux,
 };
 use tracing_subscriber::EnvFilter;
 
@@ -34,27 +35,59 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .with_env_filter(EnvFilter::from_default_env())
         .try_init();
 
+    let arguments = Arguments::parse();
+
+    let pre_shared_key = arguments
+        .swarm_key
+        .as_deref()
+        .map(read_swarm_key)
+        .transpose()?;
+    if let Some(pre_shared_key) = pre_shared_key {
+        tracing::info!(
+            "Running private network with key fingerprint {}",
+            pre_shared_key.fingerprint()
+        );
+    }
+
     // Results in PeerID 12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN which is
     // used as the rendezvous point by the other peer examples.
     let keypair = libp2p::identity::Keypair::ed25519_from_bytes([0; 32]).unwrap();
 
     let mut swarm = libp2p::SwarmBuilder::with_existing_identity(keypair)
         .with_tokio()
-        .with_tcp(
-            tcp::Config::default(),
-            noise::Config::new,
-            yamux::Config::default,
-        )?
-        .with_behaviour(|keypair| RendezvousServerBehaviour {
-            rendezvous: rendezvous::server::Behaviour::new(rendezvous::server::Config::default()),
-            identify: identify::Behaviour::new(identify::Config::new(
-                "rendezvous-identify/1.0.0".to_string(),
-                keypair.public(),
-            )),
+        .with_other_transport(|keypair| build_transport(keypair, pre_shared_key, false, true))?
+        .with_behaviour(|keypair| {
+            let peer_id = keypair.public().to_peer_id();
+            RendezvousServerBehaviour {
+                rendezvous: rendezvous::server::Behaviour::new(
+                    rendezvous::server::Config::default(),
+                ),
+                identify: identify::Behaviour::new(identify::Config::new(
+                    "rendezvous-identify/1.0.0".to_string(),
+                    keypair.public(),
+                )),
+                kademlia: kad::Behaviour::new(peer_id, kad::store::MemoryStore::new(peer_id)),
+            }
         })?
+        // Peers stay connected between their periodic discovery requests, so
+        // that a dropped connection signals the need to reconnect
+        .with_swarm_config(|config| config.with_idle_connection_timeout(Duration::from_mins(2)))
         .build();
 
-    let _ = swarm.listen_on("/ip4/0.0.0.0/tcp/62649".parse().unwrap());
+    // Act as a DHT bootstrap node for peers using this rendezvous point
+    swarm
+        .behaviour_mut()
+        .kademlia
+        .set_mode(Some(kad::Mode::Server));
+
+    let _ = swarm.listen_on(
+        format!("/ip4/0.0.0.0/tcp/{RENDEZVOUS_POINT_PORT_NUMBER}")
+            .parse()
+            .unwrap(),
+    );
+    if let Some(websocket_port) = arguments.websocket_port {
+        swarm.listen_on(format!("/ip4/0.0.0.0/tcp/{websocket_port}/ws").parse()?)?;
+    }
 
     while let Some(event) = swarm.next().await {
         match event {
@@ -85,6 +118,19 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     registrations.len()
                 );
             }
+            SwarmEvent::Behaviour(RendezvousServerBehaviourEvent::Identify(
+                identify::Event::Received { peer_id, info, .. },
+            )) => {
+                // Make DHT peers known to others querying through this node
+                if info.protocols.contains(&kad::PROTOCOL_NAME) {
+                    for address in info.listen_addrs {
+                        swarm
+                            .behaviour_mut()
+                            .kademlia
+                            .add_address(&peer_id, address);
+                    }
+                }
+            }
             other => {
                 tracing::debug!("Unhandled {:?}", other);
             }
@@ -94,8 +140,23 @@ async fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[derive(Parser, Debug)]
+#[command(name = "decent-share: Rendezvous server")]
+struct Arguments {
+    /// Additionally accept WebSocket connections on this port, for peers that
+    /// can only reach the server over HTTP ports (e.g. 80).
+    #[arg(long, short)]
+    websocket_port: Option<u16>,
+
+    /// Path to a swarm key file. When given, only peers holding the same key
+    /// are able to connect.
+    #[arg(long, short)]
+    swarm_key: Option<PathBuf>,
+}
+
 #[derive(NetworkBehaviour)]
 struct RendezvousServerBehaviour {
     rendezvous: rendezvous::server::Behaviour,
     identify: identify::Behaviour,
+    kademlia: kad::Behaviour<kad::store::MemoryStore>,
 }
@@ -0,0 +1,51 @@
+use std::path::{Component, Path, PathBuf};
+
+use decent_share::NodeConfig;
+use serde::Deserialize;
+
+use crate::{hooks::HooksConfig, policy::TradePolicyConfig};
+
+/// Settings of the command line application itself, kept in the same config
+/// file as the node's.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct AppConfig {
+    pub hooks: HooksConfig,
+    pub trade_policy: TradePolicyConfig,
+}
+
+impl AppConfig {
+    /// The top level tables of the config file which belong to the
+    /// application rather than the node.
+    const KEYS: [&str; 2] = ["hooks", "trade_policy"];
+}
+
+/// Reads a config file holding both the node's settings and the
+/// application's.
+pub(crate) fn read_config(path: &Path) -> Result<(NodeConfig, AppConfig), anyhow::Error> {
+    let mut node_table: toml::Table = std::fs::read_to_string(path)?.parse()?;
+    let mut app_table = toml::Table::new();
+    for key in AppConfig::KEYS {
+        if let Some(value) = node_table.remove(key) {
+            app_table.insert(key.to_owned(), value);
+        }
+    }
+    Ok((node_table.try_into()?, app_table.try_into()?))
+}
+
+/// Where the node keeps its state when the config doesn't say: a folder named
+/// after the user in `$XDG_DATA_HOME/decent-share`, or in
+/// `~/.local/share/decent-share` or `%APPDATA%\decent-share` when that isn't
+/// set. Users running several nodes on one machine thus keep them apart.
+pub(crate) fn default_data_dir(username: &str) -> Option<PathBuf> {
+    let username = username.to_lowercase();
+    let mut components = Path::new(&username).components();
+    let (Some(Component::Normal(_)), None) = (components.next(), components.next()) else {
+        return None;
+    };
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(data_home.join("decent-share").join(username))
+}
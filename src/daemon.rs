@@ -0,0 +1,368 @@
+use std::{
+    fs::Permissions,
+    io::ErrorKind,
+    os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use decent_share::{Client, Event};
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    signal::unix::{signal, SignalKind},
+    sync::broadcast,
+};
+
+use crate::{
+    http::{self, HttpOptions},
+    interface::{describe_peer_list, describe_reputation, describe_trade},
+    json::{self, Command, JsonEvent, JsonEvents, JsonPeer, JsonTrade, Output, Request},
+};
+
+/// How many events may be waiting to be written to a slow `events` or HTTP
+/// client before the oldest are dropped.
+const EVENT_BUFFER_SIZE: usize = 256;
+
+/// The socket to use when none is given: `decent-share.sock` in the user's
+/// runtime directory, or in the temporary directory when there is none.
+pub(crate) fn default_socket_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime_dir) => PathBuf::from(runtime_dir).join("decent-share.sock"),
+        None => std::env::temp_dir().join(format!(
+            "decent-share-{}.sock",
+            rustix::process::getuid().as_raw()
+        )),
+    }
+}
+
+/// A request for a stream of events rather than a command to carry out.
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case", deny_unknown_fields)]
+enum Subscription {
+    Events,
+}
+
+/// Runs the node without any terminal attached, carrying out commands sent to
+/// `socket_path` until the process is interrupted or terminated.
+///
+/// Clients send the same JSON commands as in `--json` mode, one per line, and
+/// get back a result for each, in order. A client sending
+/// `{"command": "events"}` instead gets every network event from then on.
+/// Events are also written to stdout, as a log of the node's activity.
+///
+/// The HTTP API is served alongside when asked for in `http`.
+pub(crate) async fn run(
+    socket_path: &Path,
+    http: HttpOptions,
+    network_client: Client,
+    network_events: impl Stream<Item = Event> + Unpin,
+) -> Result<(), anyhow::Error> {
+    let listener = bind(socket_path)?;
+    tracing::info!("Listening for commands on {}", socket_path.display());
+    let result = serve(listener, http, network_client, network_events).await;
+    let _ = std::fs::remove_file(socket_path);
+    result
+}
+
+async fn serve(
+    listener: UnixListener,
+    http: HttpOptions,
+    network_client: Client,
+    mut network_events: impl Stream<Item = Event> + Unpin,
+) -> Result<(), anyhow::Error> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let (events_sender, _) = broadcast::channel(EVENT_BUFFER_SIZE);
+    http::spawn(http, network_client.clone(), events_sender.clone()).await?;
+    let mut json_events = JsonEvents::new(network_client.clone());
+    loop {
+        tokio::select! {
+            connection = listener.accept() => match connection {
+                Ok((stream, _)) => {
+                    tokio::task::spawn(handle_connection(
+                        stream,
+                        network_client.clone(),
+                        events_sender.clone(),
+                    ));
+                }
+                Err(error) => tracing::warn!("Failed to accept connection: {error}"),
+            },
+            event = network_events.next() => match event {
+                Some(event) => {
+                    if let Some(event) = json_events.convert(event) {
+                        publish(&events_sender, event);
+                    }
+                }
+                None => break,
+            },
+            registration = json_events.registration() => match registration {
+                Ok(event) => publish(&events_sender, event),
+                Err(event) => {
+                    publish(&events_sender, event);
+                    anyhow::bail!("The username is already registered on the network, try using a different one");
+                }
+            },
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+        }
+    }
+    Ok(())
+}
+
+/// Logs `event` to stdout and sends it to the subscribed clients.
+fn publish(events_sender: &broadcast::Sender<JsonEvent>, event: JsonEvent) {
+    json::print(&Output::Event(event.clone()));
+    // There is nobody to send to when no client is subscribed
+    let _ = events_sender.send(event);
+}
+
+/// Listens on `socket_path`, replacing any socket left behind by a daemon
+/// which is no longer running. Only the current user may connect.
+fn bind(socket_path: &Path) -> Result<UnixListener, anyhow::Error> {
+    match std::fs::symlink_metadata(socket_path) {
+        Ok(metadata) => {
+            if !metadata.file_type().is_socket() {
+                anyhow::bail!(
+                    "{} already exists and is not a socket",
+                    socket_path.display()
+                );
+            }
+            if std::os::unix::net::UnixStream::connect(socket_path).is_ok() {
+                anyhow::bail!("A daemon is already listening on {}", socket_path.display());
+            }
+            std::fs::remove_file(socket_path).with_context(|| {
+                format!("Failed to remove stale socket {}", socket_path.display())
+            })?;
+        }
+        Err(error) if error.kind() == ErrorKind::NotFound => {}
+        Err(error) => {
+            return Err(error)
+                .with_context(|| format!("Failed to check {}", socket_path.display()));
+        }
+    }
+
+    // The socket is created inside a directory only we can enter, and only
+    // moved into place once nobody else can connect to it
+    let private_dir = socket_path.with_file_name(format!(".decent-share-{}", std::process::id()));
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private_dir)
+        .with_context(|| format!("Failed to create {}", private_dir.display()))?;
+    let private_path = private_dir.join("socket");
+    let listener = UnixListener::bind(&private_path)
+        .and_then(|listener| {
+            std::fs::set_permissions(&private_path, Permissions::from_mode(0o600))?;
+            std::fs::rename(&private_path, socket_path)?;
+            Ok(listener)
+        })
+        .with_context(|| format!("Failed to listen on {}", socket_path.display()));
+    let _ = std::fs::remove_file(&private_path);
+    let _ = std::fs::remove_dir(&private_dir);
+    listener
+}
+
+async fn handle_connection(
+    stream: UnixStream,
+    mut network_client: Client,
+    events_sender: broadcast::Sender<JsonEvent>,
+) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if serde_json::from_str::<Subscription>(&line).is_ok() {
+            let mut events = events_sender.subscribe();
+            loop {
+                match events.recv().await {
+                    Ok(event) => {
+                        if write(&mut writer, &Output::Event(event)).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        tracing::warn!("Dropped {missed} events for a slow client");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        }
+
+        let output = match serde_json::from_str::<Request>(&line) {
+            Ok(Request { id, command }) => {
+                let result = json::execute(command, &mut network_client).await;
+                Output::result(id, result.map_err(|error| error.message))
+            }
+            Err(error) => Output::result(None, Err(format!("Invalid command: {error}"))),
+        };
+        if write(&mut writer, &output).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn write(
+    writer: &mut tokio::net::unix::OwnedWriteHalf,
+    output: &Output,
+) -> Result<(), std::io::Error> {
+    let mut line = serde_json::to_string(output)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await
+}
+
+/// A command's result, as read back from the daemon.
+#[derive(Deserialize)]
+struct Response {
+    ok: bool,
+    result: Option<Value>,
+    error: Option<String>,
+}
+
+/// Sends `command` to the daemon listening on `socket_path`, and prints its
+/// result.
+pub(crate) async fn send_command(
+    socket_path: &Path,
+    mut command: Command,
+) -> Result<(), anyhow::Error> {
+    // Paths are resolved by the daemon, which may be running in another
+    // directory
+    match &mut command {
+        Command::Trade {
+            offered_file_path,
+            requested_file_path,
+            ..
+        }
+        | Command::Accept {
+            offered_file_path,
+            requested_file_path,
+            ..
+        } => {
+            *offered_file_path = absolute(offered_file_path)?;
+            *requested_file_path = absolute(requested_file_path)?;
+        }
+        _ => {}
+    }
+    let request = serde_json::to_value(&command)?;
+
+    let mut lines = connect(socket_path, &request).await?;
+    let line = lines
+        .next_line()
+        .await?
+        .context("The daemon closed the connection without responding")?;
+    let response: Response = serde_json::from_str(&line)?;
+    if !response.ok {
+        anyhow::bail!("{}", response.error.unwrap_or_default());
+    }
+    let result = response.result.unwrap_or_default();
+
+    match command {
+        Command::Trade { .. } => println!("Offered trade {}", result["trade_id"]),
+        Command::Trades => {
+            let trades: Vec<JsonTrade> = serde_json::from_value(result["trades"].clone())?;
+            if trades.is_empty() {
+                println!("No pending trades");
+            }
+            for JsonTrade { trade, username } in trades {
+                let username = username.unwrap_or_else(|| trade.peer_id.to_string());
+                println!("{}", describe_trade(&trade, &username));
+            }
+        }
+        Command::GetPeerId { .. } => println!("{}", result["peer_id"].as_str().unwrap_or_default()),
+        Command::GetUsername { .. } => {
+            println!("{}", result["username"].as_str().unwrap_or_default());
+        }
+        Command::Blocked => {
+            let names = |peers: &Value| -> Result<Vec<String>, serde_json::Error> {
+                let peers: Vec<JsonPeer> = serde_json::from_value(peers.clone())?;
+                Ok(peers
+                    .into_iter()
+                    .map(|peer| peer.username.unwrap_or_else(|| peer.peer_id.to_string()))
+                    .collect())
+            };
+            let (blocked, muted) = (names(&result["blocked"])?, names(&result["muted"])?);
+            for line in describe_peer_list(&blocked, &muted) {
+                println!("{line}");
+            }
+        }
+        Command::Reputation { username, from } => {
+            let reputation = serde_json::from_value(result["reputation"].clone())?;
+            println!(
+                "{}",
+                describe_reputation(&username, from.as_deref(), &reputation)
+            );
+        }
+        Command::Send { .. }
+        | Command::Dm { .. }
+        | Command::Accept { .. }
+        | Command::Decline { .. }
+        | Command::Block { .. }
+        | Command::Unblock { .. }
+        | Command::Mute { .. }
+        | Command::Unmute { .. } => {}
+    }
+    Ok(())
+}
+
+/// Prints the events of the daemon listening on `socket_path` as they happen,
+/// one JSON object per line.
+pub(crate) async fn print_events(socket_path: &Path) -> Result<(), anyhow::Error> {
+    let mut lines = connect(socket_path, &serde_json::json!({ "command": "events" })).await?;
+    while let Some(line) = lines.next_line().await? {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+async fn connect(
+    socket_path: &Path,
+    request: &Value,
+) -> Result<tokio::io::Lines<BufReader<UnixStream>>, anyhow::Error> {
+    let mut stream = UnixStream::connect(socket_path).await.with_context(|| {
+        format!(
+            "Failed to connect to a daemon on {}, is one running?",
+            socket_path.display()
+        )
+    })?;
+    let mut line = request.to_string();
+    line.push('\n');
+    stream.write_all(line.as_bytes()).await?;
+    Ok(BufReader::new(stream).lines())
+}
+
+fn absolute(path: &str) -> Result<String, anyhow::Error> {
+    Ok(std::path::absolute(path)?.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn only_stale_sockets_are_replaced() {
+        let dir =
+            std::env::temp_dir().join(format!("decent-share-daemon-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket_path = dir.join("daemon.sock");
+
+        let file_path = dir.join("file");
+        std::fs::write(&file_path, "not a socket").unwrap();
+        assert!(bind(&file_path).is_err());
+        assert!(file_path.exists());
+
+        let listener = bind(&socket_path).unwrap();
+        let permissions = std::fs::metadata(&socket_path).unwrap().permissions();
+        assert_eq!(permissions.mode() & 0o777, 0o600);
+        assert!(bind(&socket_path).is_err());
+
+        drop(listener);
+        let _listener = bind(&socket_path).unwrap();
+        let entries: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(entries.len(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,220 @@
+use std::{process::Stdio, sync::Arc, time::Duration};
+
+use decent_share::{Client, Event};
+use futures::{channel::mpsc, SinkExt, Stream, StreamExt};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::{io::AsyncWriteExt, process::Command, sync::Semaphore};
+
+use crate::json::JsonEvent;
+
+/// How many events may wait for their hooks to run before the hooks of
+/// further events are skipped.
+const MAX_QUEUED_EVENTS: usize = 64;
+
+/// Commands to run as events happen, as given in the `hooks` table of the
+/// config file.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct HooksConfig {
+    /// How many seconds a hook may run for before it is killed.
+    pub timeout: u64,
+    /// How many hooks may run at once. Further hooks wait for one to finish.
+    pub max_concurrent: usize,
+    pub commands: Vec<HookConfig>,
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            timeout: 30,
+            max_concurrent: 4,
+            commands: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct HookConfig {
+    pub event: HookEvent,
+    /// Run by the shell, so may use pipes and the event's environment
+    /// variables.
+    pub command: String,
+}
+
+/// The events hooks can be run for, named as in `--json` mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum HookEvent {
+    InboundTradeOffer,
+    InboundTradeResponse,
+    TradeCompleted,
+    ReceiptSaved,
+    InboundDirectMessage,
+    InboundChat,
+    RendezvousStatus,
+    PeerConnected,
+    PeerDisconnected,
+    Error,
+}
+
+/// Runs the configured hooks for each event from `network_events`, which are
+/// then passed on through the returned stream. Hooks run in the background,
+/// so never hold up events. Should too many events be waiting for their hooks
+/// to run, the hooks of further events are skipped.
+pub(crate) fn spawn(
+    config: HooksConfig,
+    network_client: Client,
+    mut network_events: impl Stream<Item = Event> + Unpin + Send + 'static,
+) -> mpsc::Receiver<Event> {
+    let (mut event_sender, event_receiver) = mpsc::channel(0);
+    let (mut hook_sender, hook_receiver) = mpsc::channel(MAX_QUEUED_EVENTS);
+    let config = Arc::new(config);
+    tokio::task::spawn(run_queued_hooks(
+        config.clone(),
+        network_client,
+        hook_receiver,
+    ));
+    tokio::task::spawn(async move {
+        while let Some(event) = network_events.next().await {
+            let json_event = JsonEvent::new(&event).filter(|json_event| {
+                hook_event(json_event)
+                    .is_some_and(|event| config.commands.iter().any(|hook| hook.event == event))
+            });
+            if let Some(json_event) = json_event {
+                if hook_sender.try_send(json_event).is_err() {
+                    tracing::warn!(
+                        "Too many events are waiting for hooks, skipping an event's hooks"
+                    );
+                }
+            }
+            if event_sender.send(event).await.is_err() {
+                break;
+            }
+        }
+    });
+    event_receiver
+}
+
+/// Runs the hooks of the queued events, handling at most `max_concurrent`
+/// events at once.
+async fn run_queued_hooks(
+    config: Arc<HooksConfig>,
+    network_client: Client,
+    hook_receiver: mpsc::Receiver<JsonEvent>,
+) {
+    let max_concurrent = config.max_concurrent.max(1);
+    let permits = Arc::new(Semaphore::new(max_concurrent));
+    hook_receiver
+        .for_each_concurrent(max_concurrent, |json_event| {
+            run_hooks(
+                config.clone(),
+                permits.clone(),
+                json_event,
+                network_client.clone(),
+            )
+        })
+        .await;
+}
+
+/// The event `json_event` is, as named in the hooks config.
+fn hook_event(json_event: &JsonEvent) -> Option<HookEvent> {
+    let Ok(Value::Object(fields)) = serde_json::to_value(json_event) else {
+        return None;
+    };
+    serde_json::from_value(fields["event"].clone()).ok()
+}
+
+async fn run_hooks(
+    config: Arc<HooksConfig>,
+    permits: Arc<Semaphore>,
+    mut json_event: JsonEvent,
+    mut network_client: Client,
+) {
+    let Some(event) = hook_event(&json_event) else {
+        return;
+    };
+    let commands: Vec<&String> = config
+        .commands
+        .iter()
+        .filter(|hook| hook.event == event)
+        .map(|hook| &hook.command)
+        .collect();
+
+    json_event.look_up_username(&mut network_client).await;
+    let Ok(Value::Object(fields)) = serde_json::to_value(&json_event) else {
+        return;
+    };
+    let timeout = Duration::from_secs(config.timeout);
+    let hooks = commands.into_iter().map(|command| {
+        let permits = permits.clone();
+        let fields = &fields;
+        async move {
+            // The semaphore is never closed
+            let Ok(_permit) = permits.acquire().await else {
+                return;
+            };
+            if let Err(error) = run_hook(command, fields, timeout).await {
+                tracing::warn!("Hook `{command}` failed: {error}");
+            }
+        }
+    });
+    futures::future::join_all(hooks).await;
+}
+
+/// Runs `command` in the shell, with the event's fields given both as JSON on
+/// stdin and as `DECENT_SHARE_*` environment variables.
+async fn run_hook(
+    command: &str,
+    fields: &serde_json::Map<String, Value>,
+    timeout: Duration,
+) -> Result<(), anyhow::Error> {
+    #[cfg(unix)]
+    let mut shell = Command::new("sh");
+    #[cfg(unix)]
+    shell.arg("-c");
+    #[cfg(windows)]
+    let mut shell = Command::new("cmd");
+    #[cfg(windows)]
+    shell.arg("/C");
+
+    let environment = fields.iter().filter_map(|(name, value)| {
+        let value = match value {
+            Value::Null => return None,
+            Value::String(value) => value.clone(),
+            value => value.to_string(),
+        };
+        Some((format!("DECENT_SHARE_{}", name.to_uppercase()), value))
+    });
+    let mut child = shell
+        .arg(command)
+        .envs(environment)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    // Written separately, as the hook may never read it
+    if let Some(mut stdin) = child.stdin.take() {
+        let mut input = serde_json::to_vec(fields)?;
+        input.push(b'\n');
+        tokio::task::spawn(async move {
+            let _ = stdin.write_all(&input).await;
+        });
+    }
+
+    let Ok(output) = tokio::time::timeout(timeout, child.wait_with_output()).await else {
+        anyhow::bail!("Timed out after {} seconds", timeout.as_secs());
+    };
+    let output = output?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "{}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,376 @@
+use std::{fmt::Write, net::Ipv4Addr, sync::Arc};
+
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header::AUTHORIZATION, StatusCode},
+    middleware::{self, Next},
+    response::{
+        sse::{self, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post},
+    Json, Router,
+};
+use decent_share::{Client, ClientError, NetworkError, PeerId, TradeId};
+use futures::Stream;
+use libp2p::gossipsub;
+use rand::RngCore;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::{net::TcpListener, sync::broadcast};
+
+use crate::json::{self, Command, CommandError, JsonEvent};
+
+/// Options for serving the HTTP API alongside a daemon.
+#[derive(clap::Args, Debug)]
+pub(crate) struct HttpOptions {
+    /// Serve an HTTP API for the node on this port of localhost. See the
+    /// README for its endpoints.
+    #[arg(long)]
+    pub http_port: Option<u16>,
+
+    /// The bearer token HTTP clients must present. A random token is
+    /// generated and printed when none is given.
+    #[arg(
+        long,
+        env = "DECENT_SHARE_HTTP_TOKEN",
+        hide_env_values = true,
+        requires = "http_port"
+    )]
+    pub http_token: Option<String>,
+}
+
+#[derive(Clone)]
+struct AppState {
+    network_client: Client,
+    events_sender: broadcast::Sender<JsonEvent>,
+    token: Arc<str>,
+}
+
+/// Starts serving the HTTP API on `port` of localhost, streaming events sent
+/// through `events_sender` to clients of `/events`.
+///
+/// # Errors
+///
+/// Fails if the port can not be listened on.
+pub(crate) async fn spawn(
+    options: HttpOptions,
+    network_client: Client,
+    events_sender: broadcast::Sender<JsonEvent>,
+) -> Result<(), anyhow::Error> {
+    let Some(port) = options.http_port else {
+        return Ok(());
+    };
+    let token = options.http_token.unwrap_or_else(generate_token);
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await?;
+    eprintln!(
+        "Serving the HTTP API on http://{}/ with token {token}",
+        listener.local_addr()?
+    );
+
+    let state = AppState {
+        network_client,
+        events_sender,
+        token: token.into(),
+    };
+    let app = Router::new()
+        .route("/chat", post(send_chat))
+        .route("/direct-messages", post(send_direct_message))
+        .route("/trades", get(list_trades).post(offer_trade))
+        .route("/trades/{trade_id}/accept", post(accept_trade))
+        .route("/trades/{trade_id}/decline", post(decline_trade))
+        .route("/users/{username}", get(get_peer_id))
+        .route(
+            "/users/{username}/block",
+            post(block_user).delete(unblock_user),
+        )
+        .route(
+            "/users/{username}/mute",
+            post(mute_user).delete(unmute_user),
+        )
+        .route("/blocked", get(list_blocked))
+        .route("/users/{username}/reputation", get(get_reputation))
+        .route("/peers/{peer_id}", get(get_username))
+        .route("/events", get(stream_events))
+        .layer(middleware::from_fn_with_state(state.clone(), authorize))
+        .with_state(state);
+
+    tokio::task::spawn(async move {
+        if let Err(error) = axum::serve(listener, app).await {
+            eprintln!("HTTP API stopped: {error}");
+        }
+    });
+    Ok(())
+}
+
+/// 32 random bytes, hex encoded.
+fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().fold(String::new(), |mut token, byte| {
+        let _ = write!(token, "{byte:02x}");
+        token
+    })
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    access_token: Option<String>,
+}
+
+/// Rejects requests without the token, given either as an
+/// `Authorization: Bearer` header or, for browsers' `EventSource` which can't
+/// set headers, an `access_token` query parameter.
+async fn authorize(
+    State(state): State<AppState>,
+    Query(query): Query<TokenQuery>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or(query.access_token.as_deref());
+    match token {
+        Some(token) if tokens_match(token, &state.token) => next.run(request).await,
+        _ => error(StatusCode::UNAUTHORIZED, "Missing or incorrect token"),
+    }
+}
+
+/// Compares tokens in constant time, so as not to reveal how much of a
+/// guess was correct.
+fn tokens_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+/// Carries out `command`, responding with its result as in `--json` mode, or
+/// with an `error` and a status saying why it failed, see [`error_status`].
+async fn execute(mut network_client: Client, command: Command) -> Response {
+    match json::execute(command, &mut network_client).await {
+        Ok(result) => Json(result).into_response(),
+        Err(CommandError { message, source }) => {
+            let status = source
+                .as_ref()
+                .map_or(StatusCode::BAD_REQUEST, error_status);
+            error(status, &message)
+        }
+    }
+}
+
+/// The status of a failed request: `404 Not Found` for unknown users, peers
+/// and trades, `504 Gateway Timeout` when the network took too long,
+/// `502 Bad Gateway` when a peer or the DHT failed us, `500 Internal Server
+/// Error` when the node itself failed, and `400 Bad Request` otherwise.
+fn error_status(error: &ClientError) -> StatusCode {
+    match error {
+        ClientError::UnknownUser(_)
+        | ClientError::UnknownPeer(_)
+        | ClientError::TradeNotFound { .. } => StatusCode::NOT_FOUND,
+        ClientError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        ClientError::Publish(gossipsub::PublishError::MessageTooLarge)
+        | ClientError::Network(NetworkError::SelfRequest) => StatusCode::BAD_REQUEST,
+        ClientError::Stopped | ClientError::Network(NetworkError::Storage(_)) => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+        ClientError::PeerUnreachable { .. }
+        | ClientError::Rejected { .. }
+        | ClientError::Publish(_)
+        | ClientError::Network(_) => StatusCode::BAD_GATEWAY,
+    }
+}
+
+#[derive(Deserialize)]
+struct ChatMessage {
+    message: String,
+}
+
+async fn send_chat(State(state): State<AppState>, Json(body): Json<ChatMessage>) -> Response {
+    execute(
+        state.network_client,
+        Command::Send {
+            message: body.message,
+        },
+    )
+    .await
+}
+
+#[derive(Deserialize)]
+struct DirectMessage {
+    username: String,
+    message: String,
+}
+
+async fn send_direct_message(
+    State(state): State<AppState>,
+    Json(body): Json<DirectMessage>,
+) -> Response {
+    execute(
+        state.network_client,
+        Command::Dm {
+            username: body.username,
+            message: body.message,
+        },
+    )
+    .await
+}
+
+async fn list_trades(State(state): State<AppState>) -> Response {
+    execute(state.network_client, Command::Trades).await
+}
+
+#[derive(Deserialize)]
+struct TradeOffer {
+    offered_file_name: String,
+    offered_file_path: String,
+    username: String,
+    requested_file_name: String,
+    requested_file_path: String,
+}
+
+async fn offer_trade(State(state): State<AppState>, Json(body): Json<TradeOffer>) -> Response {
+    execute(
+        state.network_client,
+        Command::Trade {
+            offered_file_name: body.offered_file_name,
+            offered_file_path: body.offered_file_path,
+            username: body.username,
+            requested_file_name: body.requested_file_name,
+            requested_file_path: body.requested_file_path,
+        },
+    )
+    .await
+}
+
+#[derive(Deserialize)]
+struct TradeAcceptance {
+    offered_file_path: String,
+    requested_file_path: String,
+}
+
+async fn accept_trade(
+    State(state): State<AppState>,
+    Path(trade_id): Path<TradeId>,
+    Json(body): Json<TradeAcceptance>,
+) -> Response {
+    execute(
+        state.network_client,
+        Command::Accept {
+            trade_id,
+            offered_file_path: body.offered_file_path,
+            requested_file_path: body.requested_file_path,
+        },
+    )
+    .await
+}
+
+async fn decline_trade(State(state): State<AppState>, Path(trade_id): Path<TradeId>) -> Response {
+    execute(state.network_client, Command::Decline { trade_id }).await
+}
+
+async fn get_peer_id(State(state): State<AppState>, Path(username): Path<String>) -> Response {
+    execute(state.network_client, Command::GetPeerId { username }).await
+}
+
+async fn block_user(State(state): State<AppState>, Path(username): Path<String>) -> Response {
+    execute(state.network_client, Command::Block { username }).await
+}
+
+async fn unblock_user(State(state): State<AppState>, Path(username): Path<String>) -> Response {
+    execute(state.network_client, Command::Unblock { username }).await
+}
+
+async fn mute_user(State(state): State<AppState>, Path(username): Path<String>) -> Response {
+    execute(state.network_client, Command::Mute { username }).await
+}
+
+async fn unmute_user(State(state): State<AppState>, Path(username): Path<String>) -> Response {
+    execute(state.network_client, Command::Unmute { username }).await
+}
+
+async fn list_blocked(State(state): State<AppState>) -> Response {
+    execute(state.network_client, Command::Blocked).await
+}
+
+#[derive(Deserialize)]
+struct ReputationQuery {
+    from: Option<String>,
+}
+
+async fn get_reputation(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    Query(query): Query<ReputationQuery>,
+) -> Response {
+    let command = Command::Reputation {
+        username,
+        from: query.from,
+    };
+    execute(state.network_client, command).await
+}
+
+async fn get_username(State(state): State<AppState>, Path(peer_id): Path<PeerId>) -> Response {
+    execute(state.network_client, Command::GetUsername { peer_id }).await
+}
+
+/// Streams network events as server-sent events, with each event's data being
+/// its JSON form.
+async fn stream_events(
+    State(state): State<AppState>,
+) -> Sse<impl Stream<Item = Result<sse::Event, axum::Error>>> {
+    let events = state.events_sender.subscribe();
+    let stream = futures::stream::unfold(events, |mut events| async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => return Some((sse::Event::default().json_data(event), events)),
+                // Slow clients miss out on the oldest events
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_are_given_a_status_by_their_cause() {
+        let peer_id = PeerId::random();
+        let cases = [
+            (
+                ClientError::UnknownUser("bob".to_owned()),
+                StatusCode::NOT_FOUND,
+            ),
+            (ClientError::Timeout, StatusCode::GATEWAY_TIMEOUT),
+            (
+                ClientError::PeerUnreachable { peer_id },
+                StatusCode::BAD_GATEWAY,
+            ),
+            (
+                ClientError::Network(NetworkError::RecordNotFound),
+                StatusCode::BAD_GATEWAY,
+            ),
+            (ClientError::Stopped, StatusCode::INTERNAL_SERVER_ERROR),
+            (
+                ClientError::Network(NetworkError::SelfRequest),
+                StatusCode::BAD_REQUEST,
+            ),
+        ];
+        for (error, status) in cases {
+            assert_eq!(error_status(&error), status, "{error}");
+        }
+    }
+}
@@ -1,30 +1,35 @@
+use std::collections::HashMap;
+
 use libp2p::gossipsub;
 
+use decent_share::{
+    Client, ClientError, Event, PeerId, Receipt, RendezvousStatus, Reputation, Trade,
+    TradeDirection, TradeParty, TradeStatus,
+};
+use futures::{channel::mpsc, Stream, StreamExt};
+
 use crate::{
-    action::{handle_accept_trade, handle_send, handle_trade},
-    network::{Client, Event},
+    action::{
+        handle_accept_trade, handle_decline_trade, handle_peer_action, handle_peer_list,
+        handle_reputation, handle_send, handle_trade, handle_verify_receipt, PeerAction,
+    },
+    jobs::Jobs,
 };
 
-const TRADE_USAGE: &str = "Usage: trade <name_of_offered_file> <path_to_offered_file> <recipient_username> <name_of_requested_file> <path_to_put_requested_file>";
-const SEND_USAGE: &str = "Usage: send <message_to_broadcast>";
-const DM_USAGE: &str = "Usage: dm <username> <message>";
-const ACCEPT_USAGE: &str = "Usage: accept <offerer_username> <name_of_offered_file> <path_to_place_received_file> <name_of_requested_file> <path_to_source_requested_file>";
-const DECLINE_USAGE: &str =
-    "Usage: decline <offerer_username> <name_of_offered_file> <name_of_requested_file>";
+pub(crate) const TRADE_USAGE: &str = "Usage: trade <name_of_offered_file> <path_to_offered_file> <recipient_username> <name_of_requested_file> <path_to_put_requested_file>";
+pub(crate) const SEND_USAGE: &str = "Usage: send <message_to_broadcast>";
+pub(crate) const DM_USAGE: &str = "Usage: dm <username> <message>";
+pub(crate) const ACCEPT_USAGE: &str =
+    "Usage: accept <trade_id> <path_to_place_received_file> <path_to_source_requested_file>";
+pub(crate) const DECLINE_USAGE: &str = "Usage: decline <trade_id>";
+pub(crate) const PEER_ACTION_USAGE: &str = "Usage: block|unblock|mute|unmute <username>";
+pub(crate) const REPUTATION_USAGE: &str = "Usage: reputation <username> [<username_to_ask>]";
+pub(crate) const VERIFY_RECEIPT_USAGE: &str = "Usage: verify-receipt <path_to_receipt>";
 
 #[allow(clippy::too_many_lines)]
-pub(crate) async fn handle_std_in(
-    command: Result<Option<String>, std::io::Error>,
-    network_client: &mut Client,
-) {
-    let command = match command {
-        Ok(Some(command)) => command,
-        Ok(None) => return,
-        Err(error) => {
-            eprintln!("Error reading command: {error:?}");
-            return;
-        }
-    };
+pub(crate) async fn handle_std_in(command: String, mut network_client: Client, jobs: Jobs) {
+    let network_client = &mut network_client;
+    let job = jobs.start(&command);
 
     let arguments = split_string(&command);
     let Some(action) = arguments.first() else {
@@ -40,13 +45,13 @@ pub(crate) async fn handle_std_in(
             };
             if let Err(error) = handle_send(message, network_client).await {
                 match error {
-                    gossipsub::PublishError::InsufficientPeers => {
+                    ClientError::Publish(gossipsub::PublishError::InsufficientPeers) => {
                         eprintln!("No peers are connected, unable to publish chat!");
                     }
-                    gossipsub::PublishError::MessageTooLarge => {
+                    ClientError::Publish(gossipsub::PublishError::MessageTooLarge) => {
                         eprintln!("Message was too large. Please use less characters");
                     }
-                    _ => eprintln!("Error sending chat: {error:?}"),
+                    _ => eprintln!("Error sending chat: {error}"),
                 }
             }
         }
@@ -72,7 +77,11 @@ pub(crate) async fn handle_std_in(
                 return;
             };
 
-            if let Err(error) = handle_trade(
+            println!(
+                "[job {}] Offering '{offered_file_name}' to {username}...",
+                job.id
+            );
+            match handle_trade(
                 offered_file_name,
                 offered_file_path,
                 username,
@@ -82,7 +91,10 @@ pub(crate) async fn handle_std_in(
             )
             .await
             {
-                eprintln!("Error offering trade: {error:?}");
+                Ok(trade_id) => {
+                    println!("[job {}] Trade offer {trade_id} sent to {username}", job.id);
+                }
+                Err(error) => eprintln!("[job {}] Error offering trade: {error:?}", job.id),
             }
         }
         "dm" => {
@@ -98,65 +110,120 @@ pub(crate) async fn handle_std_in(
                 .direct_message(username.to_owned(), message.to_owned())
                 .await
             {
-                eprintln!("Error sending direct message: {error:?}");
+                eprintln!("Error sending direct message: {error}");
             }
         }
         "accept" => {
-            let Some(username) = arguments.get(1) else {
-                println!("{ACCEPT_USAGE}");
-                return;
-            };
-            let Some(offered_file_name) = arguments.get(2) else {
-                println!("{ACCEPT_USAGE}");
-                return;
-            };
-            let Some(offered_file_path) = arguments.get(3) else {
+            let Some(trade_id) = arguments.get(1) else {
                 println!("{ACCEPT_USAGE}");
                 return;
             };
-            let Some(requested_file_name) = arguments.get(4) else {
+            let Some(offered_file_path) = arguments.get(2) else {
                 println!("{ACCEPT_USAGE}");
                 return;
             };
-            let Some(requested_file_path) = arguments.get(5) else {
+            let Some(requested_file_path) = arguments.get(3) else {
                 println!("{ACCEPT_USAGE}");
                 return;
             };
-            if let Err(error) = handle_accept_trade(
-                username,
-                offered_file_name,
+            println!(
+                "[job {}] Accepting trade {trade_id}, waiting for the offered file...",
+                job.id
+            );
+            match handle_accept_trade(
+                trade_id,
                 offered_file_path,
-                requested_file_name,
                 requested_file_path,
                 network_client,
             )
             .await
             {
-                eprintln!("Error accepting trade: {error:?}");
+                Ok(()) => println!(
+                    "[job {}] Trade {trade_id} complete, the offered file is now available at '{offered_file_path}'",
+                    job.id
+                ),
+                Err(error) => eprintln!("[job {}] Error accepting trade: {error:?}", job.id),
             }
         }
         "decline" => {
-            let Some(username) = arguments.get(1) else {
+            let Some(trade_id) = arguments.get(1) else {
                 println!("{DECLINE_USAGE}");
                 return;
             };
-            let Some(offered_file_name) = arguments.get(2) else {
-                println!("{DECLINE_USAGE}");
+            if let Err(error) = handle_decline_trade(trade_id, network_client).await {
+                eprintln!("Error declining trade: {error}");
+            }
+        }
+        "trades" => {
+            let trades = match network_client.trades().await {
+                Ok(trades) => trades,
+                Err(error) => {
+                    eprintln!("Error listing trades: {error}");
+                    return;
+                }
+            };
+            if trades.is_empty() {
+                println!("No pending trades");
+            }
+            for trade in trades {
+                let username = match network_client.get_username(trade.peer_id).await {
+                    Ok(username) => username,
+                    Err(_) => trade.peer_id.to_string(),
+                };
+                println!("{}", describe_trade(&trade, &username));
+            }
+        }
+        "block" | "unblock" | "mute" | "unmute" => {
+            let Some(username) = arguments.get(1) else {
+                println!("{PEER_ACTION_USAGE}");
                 return;
             };
-            let Some(requested_file_name) = arguments.get(3) else {
-                println!("{DECLINE_USAGE}");
+            let Some(peer_action) = PeerAction::parse(&action.to_lowercase()) else {
                 return;
             };
-            if let Err(error) = network_client
-                .decline_trade(
-                    username.to_owned(),
-                    offered_file_name.to_owned(),
-                    requested_file_name.to_owned(),
-                )
-                .await
-            {
-                eprintln!("Error declining trade: {error:?}");
+            match handle_peer_action(peer_action, username, network_client).await {
+                Ok(()) => println!("{username} has been {}", peer_action.outcome()),
+                Err(error) => eprintln!("Error updating {username}: {error}"),
+            }
+        }
+        "blocked" => match handle_peer_list(network_client).await {
+            Ok((blocked, muted)) => {
+                for line in describe_peer_list(&blocked, &muted) {
+                    println!("{line}");
+                }
+            }
+            Err(error) => eprintln!("Error listing blocked users: {error}"),
+        },
+        "reputation" => {
+            let Some(username) = arguments.get(1) else {
+                println!("{REPUTATION_USAGE}");
+                return;
+            };
+            let from = arguments.get(2).map(String::as_str);
+            match handle_reputation(username, from, network_client).await {
+                Ok(reputation) => println!("{}", describe_reputation(username, from, &reputation)),
+                Err(error) => eprintln!("Error fetching the reputation of {username}: {error}"),
+            }
+        }
+        "verify-receipt" => {
+            let Some(path) = arguments.get(1) else {
+                println!("{VERIFY_RECEIPT_USAGE}");
+                return;
+            };
+            match handle_verify_receipt(path) {
+                Ok(receipt) => {
+                    for line in describe_receipt(&receipt) {
+                        println!("{line}");
+                    }
+                }
+                Err(error) => eprintln!("Invalid receipt: {error}"),
+            }
+        }
+        "jobs" => {
+            for (id, command, seconds) in jobs.running() {
+                if id != job.id {
+                    println!("[job {id}] {command} (running for {seconds} seconds)");
+                }
             }
         }
 
@@ -164,71 +231,297 @@ pub(crate) async fn handle_std_in(
     }
 }
 
-pub async fn handle_network_event(event: Option<Event>, network_client: &mut Client) {
-    let event = event.expect("Network event sender was dropped!");
+/// Displays events from the network as they arrive, until the network stops.
+pub(crate) async fn handle_network_events(
+    mut network_events: impl Stream<Item = Event> + Unpin,
+    network_client: Client,
+) {
+    let (username_sender, mut username_receiver) = mpsc::unbounded();
+    let mut usernames = Usernames {
+        known: HashMap::new(),
+        waiting: HashMap::new(),
+        network_client,
+        username_sender,
+    };
+    loop {
+        tokio::select! {
+            event = network_events.next() => match event {
+                Some(event) => handle_network_event(event, &mut usernames),
+                None => break,
+            },
+            Some((peer_id, username)) = username_receiver.next() => {
+                usernames.resolved(peer_id, username);
+            }
+        }
+    }
+}
+
+type Describe = Box<dyn FnOnce(Result<&str, &ClientError>) -> Vec<String> + Send>;
+
+/// The usernames of the peers events have come from. They are looked up in
+/// the background, so that a slow lookup doesn't hold up the events after it.
+/// Only one lookup per peer is made at a time, the events arriving from that
+/// peer in the meantime wait for it in order.
+struct Usernames {
+    known: HashMap<PeerId, String>,
+    waiting: HashMap<PeerId, Vec<Describe>>,
+    network_client: Client,
+    username_sender: mpsc::UnboundedSender<(PeerId, Result<String, ClientError>)>,
+}
+
+impl Usernames {
+    /// Prints the lines given by `describe` once the username of `peer_id`
+    /// is known, or couldn't be found.
+    fn print(
+        &mut self,
+        peer_id: PeerId,
+        describe: impl FnOnce(Result<&str, &ClientError>) -> Vec<String> + Send + 'static,
+    ) {
+        if let Some(username) = self.known.get(&peer_id) {
+            println!("{}", describe(Ok(username)).join("\n"));
+            return;
+        }
+        if let Some(waiting) = self.waiting.get_mut(&peer_id) {
+            waiting.push(Box::new(describe));
+            return;
+        }
+        self.waiting.insert(peer_id, vec![Box::new(describe)]);
+        let mut network_client = self.network_client.clone();
+        let username_sender = self.username_sender.clone();
+        tokio::task::spawn(async move {
+            let username = network_client.get_username(peer_id).await;
+            let _ = username_sender.unbounded_send((peer_id, username));
+        });
+    }
+
+    /// Prints the events which were waiting for the username of `peer_id`.
+    fn resolved(&mut self, peer_id: PeerId, username: Result<String, ClientError>) {
+        for describe in self.waiting.remove(&peer_id).unwrap_or_default() {
+            println!("{}", describe(username.as_deref()).join("\n"));
+        }
+        if let Ok(username) = username {
+            self.known.insert(peer_id, username);
+        }
+    }
+}
 
+#[allow(clippy::too_many_lines)]
+fn handle_network_event(event: Event, usernames: &mut Usernames) {
     match event {
         Event::InboundTradeOffer {
+            trade_id,
             offered_file_name: offered_file,
+            offered_file_size,
             peer_id,
             requested_file_name: requested_file,
-        } => {
-            println!("You have received a trade offer!",);
-            match network_client.get_username(peer_id).await {
-                Ok(username) => println!("From: {username}"),
-                Err(error) => println!("Error fetching username: {error:?}"),
-            }
-            println!("Receive: {offered_file}, Provide: {requested_file}");
-        }
+            reputation,
+        } => usernames.print(peer_id, move |username| {
+            vec![
+                format!("You have received trade offer {trade_id}!"),
+                match username {
+                    Ok(username) => format!("From: {username} ({})", count_trades(&reputation)),
+                    Err(error) => format!("Error fetching username: {error}"),
+                },
+                match offered_file_size {
+                    Some(size) => {
+                        format!("Receive: {offered_file} ({size} bytes), Provide: {requested_file}")
+                    }
+                    None => format!("Receive: {offered_file}, Provide: {requested_file}"),
+                },
+            ]
+        }),
         Event::InboundTradeResponse {
+            trade_id,
             peer_id,
             offered_file_name: offered_file,
             requested_file_name: requested_file,
             was_accepted,
-        } => {
+        } => usernames.print(peer_id, move |username| {
             let response_message = if was_accepted { "accepted" } else { "declined" };
-            let username = match network_client.get_username(peer_id).await {
-                Ok(username) => username,
+            let username = match username {
+                Ok(username) => username.to_owned(),
                 Err(error) => error.to_string(),
             };
-            println!("{username} has {response_message} your trade for {offered_file}.");
+            let mut lines = vec![format!(
+                "{username} has {response_message} your trade {trade_id} for {offered_file}."
+            )];
             if was_accepted {
-                println!("{requested_file} is now available at the path you specified");
+                lines.push(format!(
+                    "{requested_file} is now available at the path you specified"
+                ));
             }
-        }
+            lines
+        }),
         Event::InboundDirectMessage { peer_id, message } => {
-            println!("You have received a direct message!");
-            match network_client.get_username(peer_id).await {
-                Ok(username) => println!("From {username}:"),
-                Err(error) => println!("Error fetching username: {error:?}"),
-            }
-            println!("{message}");
-        }
-        Event::InboundChat { peer_id, message } => {
-            println!("Received new global chat!");
-            match network_client.get_username(peer_id).await {
-                Ok(username) => println!("From {username}:"),
-                Err(error) => println!("Error fetching username: {error:?}"),
-            }
-            println!("{message}");
+            usernames.print(peer_id, move |username| {
+                vec![
+                    "You have received a direct message!".to_owned(),
+                    match username {
+                        Ok(username) => format!("From {username}:"),
+                        Err(error) => format!("Error fetching username: {error}"),
+                    },
+                    message,
+                ]
+            });
         }
+        Event::InboundChat { peer_id, message } => usernames.print(peer_id, move |username| {
+            vec![
+                "Received new global chat!".to_owned(),
+                match username {
+                    Ok(username) => format!("From {username}:"),
+                    Err(error) => format!("Error fetching username: {error}"),
+                },
+                message,
+            ]
+        }),
         Event::RegistrationRequest { username } => {
-            let peer_id = network_client.get_peer_id(username.clone()).await;
-            if peer_id.is_some() {
-                eprintln!("The username '{username}' is already registered on the network, try using a different one");
-                std::process::exit(1);
+            tokio::task::spawn(register(username, usernames.network_client.clone()));
+        }
+        Event::RendezvousStatusChanged(status) => match status {
+            RendezvousStatus::Connecting => println!("Connecting to rendezvous server..."),
+            RendezvousStatus::Registered { ttl } => {
+                println!("Registered with rendezvous server, registration will be refreshed within {ttl} seconds");
             }
-
-            if let Err(error) = network_client.register_username(username.clone()).await {
-                println!("Failed to register username, will try again soon: {error:?}");
-            } else {
-                println!("successfully registered as {username}");
+            RendezvousStatus::RegistrationFailed { error, retry_in } => {
+                eprintln!(
+                    "Rendezvous server rejected registration ({error:?}), retrying in {} seconds",
+                    retry_in.as_secs()
+                );
+            }
+            RendezvousStatus::Disconnected { retry_in } => {
+                eprintln!(
+                    "Lost connection to rendezvous server, reconnecting in {} seconds",
+                    retry_in.as_secs()
+                );
             }
+        },
+        Event::ReceiptSaved { trade_id, path, .. } => {
+            println!(
+                "The receipt for trade {trade_id} was saved to {}",
+                path.display()
+            );
         }
+        Event::Error(error) => eprintln!("Network error: {error}"),
+        // Completion is already reported by the response to our offer, or by
+        // the `accept` action
+        Event::TradeCompleted { .. }
+        | Event::PeerConnected { .. }
+        | Event::PeerDisconnected { .. } => {}
+    }
+}
+
+/// Registers `username` for us, unless another peer has already taken it.
+async fn register(username: String, mut network_client: Client) {
+    match network_client.get_peer_id(username.clone()).await {
+        // We may have registered it ourselves in an earlier run
+        Ok(peer_id) if peer_id != network_client.local_peer_id() => {
+            eprintln!("The username '{username}' is already registered on the network, try using a different one");
+            std::process::exit(1);
+        }
+        Ok(_) | Err(ClientError::UnknownUser(_)) => {}
+        Err(error) => {
+            println!("Failed to check whether the username is taken: {error}");
+            return;
+        }
+    }
+
+    if let Err(error) = network_client.register_username(username.clone()).await {
+        println!("Failed to register username, will try again soon: {error}");
+    } else {
+        println!("successfully registered as {username}");
+    }
+}
+
+/// A line describing a pending trade with the user `username`, as listed by
+/// the `trades` action.
+pub(crate) fn describe_trade(trade: &Trade, username: &str) -> String {
+    let status = match trade.status {
+        TradeStatus::Offered => "awaiting response",
+        TradeStatus::Accepted => "accepted, exchanging files",
+    };
+    match trade.direction {
+        TradeDirection::Inbound => format!(
+            "[trade {}] from {username}: their '{}' for your '{}' ({status})",
+            trade.id, trade.offered_file_name, trade.requested_file_name
+        ),
+        TradeDirection::Outbound => format!(
+            "[trade {}] to {username}: your '{}' for their '{}' ({status})",
+            trade.id, trade.offered_file_name, trade.requested_file_name
+        ),
+    }
+}
+
+/// Lines listing the blocked and muted users, as shown by the `blocked`
+/// action.
+pub(crate) fn describe_peer_list(blocked: &[String], muted: &[String]) -> Vec<String> {
+    if blocked.is_empty() && muted.is_empty() {
+        return vec!["No users are blocked or muted".to_owned()];
+    }
+    let mut lines = Vec::new();
+    if !blocked.is_empty() {
+        lines.push(format!("Blocked: {}", blocked.join(", ")));
+    }
+    if !muted.is_empty() {
+        lines.push(format!("Muted: {}", muted.join(", ")));
+    }
+    lines
+}
+
+/// A line describing the reputation of `username`, as shown by the
+/// `reputation` action. `from` is the user who attested to it, if any.
+pub(crate) fn describe_reputation(
+    username: &str,
+    from: Option<&str>,
+    reputation: &Reputation,
+) -> String {
+    match from {
+        Some(from) => format!(
+            "{username}, according to {from}: {}",
+            count_trades(reputation)
+        ),
+        None => format!("{username}: {}", count_trades(reputation)),
+    }
+}
+
+/// Lines describing a receipt which has been verified, as shown by the
+/// `verify-receipt` action.
+pub(crate) fn describe_receipt(receipt: &Receipt) -> Vec<String> {
+    let describe_party = |party: &TradeParty| {
+        format!(
+            "{} ({}) gave '{}' with SHA-256 hash {}",
+            party.username, party.peer_id, party.file_name, party.file_hash
+        )
+    };
+    vec![
+        format!(
+            "The receipt is signed by both parties, the trade completed at {} seconds since the Unix epoch",
+            receipt.completed_at
+        ),
+        describe_party(&receipt.offerer),
+        describe_party(&receipt.recipient),
+    ]
+}
+
+/// How a user's trades have gone, e.g. "3 completed, 1 declined".
+pub(crate) fn count_trades(reputation: &Reputation) -> String {
+    let counts = [
+        (reputation.completed, "completed"),
+        (reputation.declined, "declined"),
+        (reputation.failed, "failed"),
+        (reputation.verification_failed, "failed verification"),
+    ];
+    let counts: Vec<String> = counts
+        .into_iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, outcome)| format!("{count} {outcome}"))
+        .collect();
+    if counts.is_empty() {
+        return "no trades recorded".to_owned();
     }
+    counts.join(", ")
 }
 
-fn split_string(input: &str) -> Vec<String> {
+pub(crate) fn split_string(input: &str) -> Vec<String> {
     let re = regex::Regex::new(r#""([^"]*)"|\S+"#).unwrap();
     re.captures_iter(input)
         .map(|cap| cap.get(0).unwrap().as_str().to_string())
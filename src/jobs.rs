@@ -0,0 +1,64 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, PoisonError},
+    time::Instant,
+};
+
+/// The user commands currently being carried out. Each command runs as its
+/// own task, so that slow network round-trips don't hold up other commands
+/// or the display of incoming events.
+#[derive(Clone, Default)]
+pub(crate) struct Jobs {
+    inner: Arc<Mutex<JobsInner>>,
+}
+
+#[derive(Default)]
+struct JobsInner {
+    next_id: u64,
+    running: BTreeMap<u64, (String, Instant)>,
+}
+
+impl Jobs {
+    /// Records the start of a command, which is considered finished once the
+    /// returned [`Job`] is dropped.
+    pub(crate) fn start(&self, command: &str) -> Job {
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        inner.next_id += 1;
+        let id = inner.next_id;
+        inner
+            .running
+            .insert(id, (command.to_owned(), Instant::now()));
+        Job {
+            id,
+            jobs: self.clone(),
+        }
+    }
+
+    /// Lists the running commands, with how many seconds they have been
+    /// running for.
+    pub(crate) fn running(&self) -> Vec<(u64, String, u64)> {
+        let inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        inner
+            .running
+            .iter()
+            .map(|(id, (command, started))| (*id, command.clone(), started.elapsed().as_secs()))
+            .collect()
+    }
+}
+
+/// A running command, see [`Jobs::start`].
+pub(crate) struct Job {
+    pub(crate) id: u64,
+    jobs: Jobs,
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        self.jobs
+            .inner
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .running
+            .remove(&self.id);
+    }
+}
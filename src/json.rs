@@ -0,0 +1,678 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+use decent_share::{
+    Client, ClientError, Event, PeerId, RendezvousStatus, Reputation, Trade, TradeDirection,
+    TradeId,
+};
+use futures::{channel::mpsc, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::AsyncBufReadExt;
+
+use crate::action::{handle_accept_trade, handle_peer_action, handle_trade, PeerAction};
+
+/// A command read as a line of JSON, e.g.
+/// `{"id": 1, "command": "dm", "username": "bob", "message": "hi"}`.
+///
+/// The optional `id` is echoed back in the command's result, to match
+/// results to commands as several may run at once.
+#[derive(Debug, Deserialize)]
+pub(crate) struct Request {
+    #[serde(default)]
+    pub id: Option<Value>,
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+/// The actions which can be carried out by a node, as read from JSON or given
+/// as subcommands to be run by a daemon.
+#[derive(Debug, Serialize, Deserialize, clap::Subcommand)]
+#[serde(tag = "command", rename_all = "snake_case", deny_unknown_fields)]
+pub(crate) enum Command {
+    /// Send a message to the global chat.
+    Send { message: String },
+    /// Send a message to a single user.
+    Dm { username: String, message: String },
+    /// Offer a user one of your files in exchange for one of theirs.
+    Trade {
+        offered_file_name: String,
+        /// The path of the file to offer.
+        offered_file_path: String,
+        username: String,
+        requested_file_name: String,
+        /// The path to place the requested file at.
+        requested_file_path: String,
+    },
+    /// Accept a trade offered to you.
+    Accept {
+        trade_id: TradeId,
+        /// The path to place the offered file at.
+        offered_file_path: String,
+        /// The path of the file they requested.
+        requested_file_path: String,
+    },
+    /// Decline a trade offered to you.
+    Decline { trade_id: TradeId },
+    /// List pending trades.
+    Trades,
+    /// Look up the peer ID of a user.
+    GetPeerId { username: String },
+    /// Look up the username of a peer.
+    GetUsername { peer_id: PeerId },
+    /// Block a user, dropping everything they send.
+    Block { username: String },
+    /// Unblock a blocked user.
+    Unblock { username: String },
+    /// Mute a user's chat and direct messages.
+    Mute { username: String },
+    /// Unmute a muted user.
+    Unmute { username: String },
+    /// List blocked and muted users.
+    Blocked,
+    /// Show how your trades with a user have gone, or ask another user how
+    /// theirs have.
+    Reputation {
+        username: String,
+        /// The user to ask, who must share reputations.
+        #[arg(long)]
+        #[serde(default)]
+        from: Option<String>,
+    },
+}
+
+/// A pending trade, along with the username of the peer it is with when it
+/// could be found.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct JsonTrade {
+    #[serde(flatten)]
+    pub trade: Trade,
+    pub username: Option<String>,
+}
+
+/// A blocked or muted peer, along with their username when it could be
+/// found.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct JsonPeer {
+    pub peer_id: PeerId,
+    pub username: Option<String>,
+}
+
+/// A line of output, either the result of a command or an event from the
+/// network.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Output {
+    Result {
+        id: Option<Value>,
+        ok: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        result: Option<Value>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    Event(JsonEvent),
+}
+
+impl Output {
+    pub(crate) fn result(id: Option<Value>, result: Result<Value, String>) -> Self {
+        match result {
+            Ok(result) => Self::Result {
+                id,
+                ok: true,
+                result: Some(result),
+                error: None,
+            },
+            Err(error) => Self::Result {
+                id,
+                ok: false,
+                result: None,
+                error: Some(error),
+            },
+        }
+    }
+}
+
+/// The JSON form of an [`Event`]. Peers are identified by their peer ID,
+/// along with their username when it could be found.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum JsonEvent {
+    InboundTradeOffer {
+        trade_id: TradeId,
+        peer_id: PeerId,
+        username: Option<String>,
+        offered_file_name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        offered_file_size: Option<u64>,
+        requested_file_name: String,
+        reputation: Reputation,
+    },
+    InboundTradeResponse {
+        trade_id: TradeId,
+        peer_id: PeerId,
+        username: Option<String>,
+        offered_file_name: String,
+        requested_file_name: String,
+        accepted: bool,
+    },
+    /// `direction` is `outbound` for trades we offered.
+    TradeCompleted {
+        trade_id: TradeId,
+        peer_id: PeerId,
+        username: Option<String>,
+        direction: TradeDirection,
+        offered_file_name: String,
+        requested_file_name: String,
+        received_file_path: PathBuf,
+    },
+    /// The receipt for a trade has been signed by both parties and saved.
+    ReceiptSaved {
+        trade_id: TradeId,
+        peer_id: PeerId,
+        username: Option<String>,
+        path: PathBuf,
+    },
+    InboundDirectMessage {
+        peer_id: PeerId,
+        username: Option<String>,
+        message: String,
+    },
+    InboundChat {
+        peer_id: PeerId,
+        username: Option<String>,
+        message: String,
+    },
+    Registered {
+        username: String,
+    },
+    RegistrationFailed {
+        username: String,
+        error: String,
+    },
+    /// `retry_in` is in seconds.
+    RendezvousStatus {
+        status: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ttl: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        retry_in: Option<u64>,
+    },
+    PeerConnected {
+        peer_id: PeerId,
+    },
+    PeerDisconnected {
+        peer_id: PeerId,
+    },
+    Error {
+        error: String,
+    },
+}
+
+/// Reads JSON commands from stdin and writes their results and the network's
+/// events to stdout as JSON, one object per line, until stdin is closed.
+///
+/// # Errors
+///
+/// Fails if our username has already been registered by another peer.
+pub(crate) async fn run(
+    network_client: Client,
+    mut network_events: impl Stream<Item = Event> + Unpin,
+) -> Result<(), anyhow::Error> {
+    let mut stdin = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    let mut json_events = JsonEvents::new(network_client.clone());
+    loop {
+        tokio::select! {
+            line = stdin.next_line() => match line {
+                Ok(Some(line)) if line.trim().is_empty() => {}
+                Ok(Some(line)) => match serde_json::from_str::<Request>(&line) {
+                    Ok(Request { id, command }) => {
+                        let mut network_client = network_client.clone();
+                        tokio::task::spawn(async move {
+                            let result = execute(command, &mut network_client).await;
+                            print(&Output::result(id, result.map_err(|error| error.message)));
+                        });
+                    }
+                    Err(error) => print(&Output::result(request_id(&line), Err(format!("Invalid command: {error}")))),
+                },
+                Ok(None) => break,
+                Err(error) => print(&Output::result(None, Err(format!("Error reading command: {error}")))),
+            },
+            event = network_events.next() => match event {
+                Some(event) => {
+                    if let Some(event) = json_events.convert(event) {
+                        print(&Output::Event(event));
+                    }
+                }
+                None => break,
+            },
+            registration = json_events.registration() => match registration {
+                Ok(event) => print(&Output::Event(event)),
+                Err(event) => {
+                    print(&Output::Event(event));
+                    anyhow::bail!("The username is already registered on the network, try using a different one");
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+/// The `id` of a command which could not be parsed, if it has one.
+fn request_id(line: &str) -> Option<Value> {
+    serde_json::from_str::<Value>(line)
+        .ok()?
+        .get_mut("id")
+        .map(Value::take)
+}
+
+pub(crate) fn print(output: &Output) {
+    match serde_json::to_string(output) {
+        Ok(line) => println!("{line}"),
+        Err(error) => eprintln!("Error serialising output: {error}"),
+    }
+}
+
+/// Carries out a command, returning its result as JSON or why it failed.
+#[allow(clippy::too_many_lines)]
+pub(crate) async fn execute(
+    command: Command,
+    network_client: &mut Client,
+) -> Result<Value, CommandError> {
+    match command {
+        Command::Send { message } => {
+            network_client.send_message(message).await?;
+            Ok(json!({}))
+        }
+        Command::Dm { username, message } => {
+            network_client.direct_message(username, message).await?;
+            Ok(json!({}))
+        }
+        Command::Trade {
+            offered_file_name,
+            offered_file_path,
+            username,
+            requested_file_name,
+            requested_file_path,
+        } => {
+            let trade_id = handle_trade(
+                &offered_file_name,
+                &offered_file_path,
+                &username,
+                &requested_file_name,
+                &requested_file_path,
+                network_client,
+            )
+            .await?;
+            Ok(json!({ "trade_id": trade_id }))
+        }
+        Command::Accept {
+            trade_id,
+            offered_file_path,
+            requested_file_path,
+        } => {
+            handle_accept_trade(
+                &trade_id.to_string(),
+                &offered_file_path,
+                &requested_file_path,
+                network_client,
+            )
+            .await?;
+            Ok(json!({}))
+        }
+        Command::Decline { trade_id } => {
+            network_client.decline_trade(trade_id).await?;
+            Ok(json!({}))
+        }
+        Command::Trades => {
+            let trades = network_client.trades().await?;
+            let mut json_trades = Vec::new();
+            for trade in trades {
+                let username = network_client.get_username(trade.peer_id).await.ok();
+                json_trades.push(JsonTrade { trade, username });
+            }
+            Ok(json!({ "trades": json_trades }))
+        }
+        Command::GetPeerId { username } => {
+            let peer_id = network_client.get_peer_id(username).await?;
+            Ok(json!({ "peer_id": peer_id }))
+        }
+        Command::GetUsername { peer_id } => {
+            let username = network_client.get_username(peer_id).await?;
+            Ok(json!({ "username": username }))
+        }
+        Command::Block { username } => {
+            execute_peer_action(PeerAction::Block, &username, network_client).await
+        }
+        Command::Unblock { username } => {
+            execute_peer_action(PeerAction::Unblock, &username, network_client).await
+        }
+        Command::Mute { username } => {
+            execute_peer_action(PeerAction::Mute, &username, network_client).await
+        }
+        Command::Unmute { username } => {
+            execute_peer_action(PeerAction::Unmute, &username, network_client).await
+        }
+        Command::Blocked => {
+            let peer_list = network_client.peer_list().await?;
+            let blocked = json_peers(peer_list.blocked, network_client).await;
+            let muted = json_peers(peer_list.muted, network_client).await;
+            Ok(json!({ "blocked": blocked, "muted": muted }))
+        }
+        Command::Reputation { username, from } => {
+            let peer_id = network_client.get_peer_id(username).await?;
+            let Some(from) = from else {
+                let reputation = network_client.reputation(peer_id).await?;
+                return Ok(json!({ "reputation": reputation }));
+            };
+            let issuer = network_client.get_peer_id(from).await?;
+            let attestation = network_client.query_reputation(issuer, peer_id).await?;
+            Ok(json!({ "reputation": attestation.reputation, "attestation": attestation }))
+        }
+    }
+}
+
+async fn json_peers(
+    peer_ids: impl IntoIterator<Item = PeerId>,
+    network_client: &mut Client,
+) -> Vec<JsonPeer> {
+    let mut peers = Vec::new();
+    for peer_id in peer_ids {
+        let username = network_client.get_username(peer_id).await.ok();
+        peers.push(JsonPeer { peer_id, username });
+    }
+    peers
+}
+
+async fn execute_peer_action(
+    action: PeerAction,
+    username: &str,
+    network_client: &mut Client,
+) -> Result<Value, CommandError> {
+    handle_peer_action(action, username, network_client).await?;
+    Ok(json!({}))
+}
+
+/// Why a command failed, along with the client error behind it, if any.
+#[derive(Debug)]
+pub(crate) struct CommandError {
+    pub message: String,
+    pub source: Option<ClientError>,
+}
+
+impl From<ClientError> for CommandError {
+    fn from(error: ClientError) -> Self {
+        Self {
+            message: error.to_string(),
+            source: Some(error),
+        }
+    }
+}
+
+impl From<anyhow::Error> for CommandError {
+    fn from(error: anyhow::Error) -> Self {
+        Self {
+            message: format!("{error:#}"),
+            source: error.downcast().ok(),
+        }
+    }
+}
+
+/// Converts events from the network into their JSON form without waiting on
+/// the network, so that events are output in order as soon as they arrive.
+/// The username of a peer is looked up in the background when its first event
+/// arrives, which is output with only the peer ID, and is filled into its
+/// later events.
+pub(crate) struct JsonEvents {
+    network_client: Client,
+    usernames: HashMap<PeerId, String>,
+    resolving: HashSet<PeerId>,
+    update_sender: mpsc::UnboundedSender<Update>,
+    update_receiver: mpsc::UnboundedReceiver<Update>,
+}
+
+/// Sent to [`JsonEvents`] by the lookups and registrations it spawns.
+enum Update {
+    Username {
+        peer_id: PeerId,
+        username: Option<String>,
+    },
+    Registration(Result<JsonEvent, JsonEvent>),
+}
+
+impl JsonEvents {
+    pub(crate) fn new(network_client: Client) -> Self {
+        let (update_sender, update_receiver) = mpsc::unbounded();
+        Self {
+            network_client,
+            usernames: HashMap::new(),
+            resolving: HashSet::new(),
+            update_sender,
+            update_receiver,
+        }
+    }
+
+    /// The JSON form of `event`. Registration requests have none, our
+    /// username is registered in the background instead, see
+    /// [`JsonEvents::registration`].
+    pub(crate) fn convert(&mut self, event: Event) -> Option<JsonEvent> {
+        if let Event::RegistrationRequest { username } = event {
+            let network_client = self.network_client.clone();
+            let update_sender = self.update_sender.clone();
+            tokio::task::spawn(async move {
+                let registration = register(username, network_client).await;
+                let _ = update_sender.unbounded_send(Update::Registration(registration));
+            });
+            return None;
+        }
+
+        let mut json_event = JsonEvent::new(&event)?;
+        if let Some((peer_id, username)) = json_event.username_mut() {
+            match self.usernames.get(&peer_id) {
+                Some(known) => *username = Some(known.clone()),
+                None => self.resolve(peer_id),
+            }
+        }
+        Some(json_event)
+    }
+
+    fn resolve(&mut self, peer_id: PeerId) {
+        if !self.resolving.insert(peer_id) {
+            return;
+        }
+        let mut network_client = self.network_client.clone();
+        let update_sender = self.update_sender.clone();
+        tokio::task::spawn(async move {
+            let username = network_client.get_username(peer_id).await.ok();
+            let _ = update_sender.unbounded_send(Update::Username { peer_id, username });
+        });
+    }
+
+    /// Waits for a registration of our username to finish, returning the
+    /// event reporting it. Fails with that event if the username is taken by
+    /// another peer, as the node can't be reached by it.
+    pub(crate) async fn registration(&mut self) -> Result<JsonEvent, JsonEvent> {
+        while let Some(update) = self.update_receiver.next().await {
+            match update {
+                Update::Username { peer_id, username } => {
+                    self.resolving.remove(&peer_id);
+                    if let Some(username) = username {
+                        self.usernames.insert(peer_id, username);
+                    }
+                }
+                Update::Registration(registration) => return registration,
+            }
+        }
+        // A sender is kept in `self`, so the channel is never closed
+        std::future::pending().await
+    }
+}
+
+impl JsonEvent {
+    /// The JSON form of `event`, before the username of the peer it concerns
+    /// has been looked up. Registration requests have none, as they are acted
+    /// on rather than reported.
+    pub(crate) fn new(event: &Event) -> Option<Self> {
+        let json_event = match event {
+            Event::InboundTradeOffer {
+                trade_id,
+                offered_file_name,
+                offered_file_size,
+                peer_id,
+                requested_file_name,
+                reputation,
+            } => Self::InboundTradeOffer {
+                trade_id: *trade_id,
+                peer_id: *peer_id,
+                username: None,
+                offered_file_name: offered_file_name.clone(),
+                offered_file_size: *offered_file_size,
+                requested_file_name: requested_file_name.clone(),
+                reputation: *reputation,
+            },
+            Event::InboundTradeResponse {
+                trade_id,
+                peer_id,
+                offered_file_name,
+                requested_file_name,
+                was_accepted,
+            } => Self::InboundTradeResponse {
+                trade_id: *trade_id,
+                peer_id: *peer_id,
+                username: None,
+                offered_file_name: offered_file_name.clone(),
+                requested_file_name: requested_file_name.clone(),
+                accepted: *was_accepted,
+            },
+            Event::TradeCompleted {
+                trade_id,
+                peer_id,
+                direction,
+                offered_file_name,
+                requested_file_name,
+                received_file_path,
+            } => Self::TradeCompleted {
+                trade_id: *trade_id,
+                peer_id: *peer_id,
+                username: None,
+                direction: *direction,
+                offered_file_name: offered_file_name.clone(),
+                requested_file_name: requested_file_name.clone(),
+                received_file_path: received_file_path.clone(),
+            },
+            Event::ReceiptSaved {
+                trade_id,
+                peer_id,
+                path,
+            } => Self::ReceiptSaved {
+                trade_id: *trade_id,
+                peer_id: *peer_id,
+                username: None,
+                path: path.clone(),
+            },
+            Event::InboundDirectMessage { peer_id, message } => Self::InboundDirectMessage {
+                peer_id: *peer_id,
+                username: None,
+                message: message.clone(),
+            },
+            Event::InboundChat { peer_id, message } => Self::InboundChat {
+                peer_id: *peer_id,
+                username: None,
+                message: message.clone(),
+            },
+            Event::RegistrationRequest { .. } => return None,
+            Event::RendezvousStatusChanged(status) => match status {
+                RendezvousStatus::Connecting => Self::RendezvousStatus {
+                    status: "connecting",
+                    ttl: None,
+                    retry_in: None,
+                },
+                RendezvousStatus::Registered { ttl } => Self::RendezvousStatus {
+                    status: "registered",
+                    ttl: Some(*ttl),
+                    retry_in: None,
+                },
+                RendezvousStatus::RegistrationFailed { retry_in, .. } => Self::RendezvousStatus {
+                    status: "registration_failed",
+                    ttl: None,
+                    retry_in: Some(retry_in.as_secs()),
+                },
+                RendezvousStatus::Disconnected { retry_in } => Self::RendezvousStatus {
+                    status: "disconnected",
+                    ttl: None,
+                    retry_in: Some(retry_in.as_secs()),
+                },
+            },
+            Event::PeerConnected { peer_id } => Self::PeerConnected { peer_id: *peer_id },
+            Event::PeerDisconnected { peer_id } => Self::PeerDisconnected { peer_id: *peer_id },
+            Event::Error(error) => Self::Error {
+                error: error.to_string(),
+            },
+        };
+        Some(json_event)
+    }
+
+    /// Fills in the username of the peer the event concerns, when it can be
+    /// found.
+    pub(crate) async fn look_up_username(&mut self, network_client: &mut Client) {
+        if let Some((peer_id, username)) = self.username_mut() {
+            *username = network_client.get_username(peer_id).await.ok();
+        }
+    }
+
+    /// The peer the event concerns, if any, and the username to fill in.
+    fn username_mut(&mut self) -> Option<(PeerId, &mut Option<String>)> {
+        match self {
+            Self::InboundTradeOffer {
+                peer_id, username, ..
+            }
+            | Self::InboundTradeResponse {
+                peer_id, username, ..
+            }
+            | Self::TradeCompleted {
+                peer_id, username, ..
+            }
+            | Self::ReceiptSaved {
+                peer_id, username, ..
+            }
+            | Self::InboundDirectMessage {
+                peer_id, username, ..
+            }
+            | Self::InboundChat {
+                peer_id, username, ..
+            } => Some((*peer_id, username)),
+            _ => None,
+        }
+    }
+}
+
+/// Registers `username` for us, returning the event reporting how it went.
+/// Fails if another peer has already registered it.
+async fn register(username: String, mut network_client: Client) -> Result<JsonEvent, JsonEvent> {
+    match network_client.get_peer_id(username.clone()).await {
+        // We may have registered it ourselves in an earlier run
+        Ok(peer_id) if peer_id != network_client.local_peer_id() => {
+            return Err(JsonEvent::RegistrationFailed {
+                username,
+                error: "The username is already registered on the network".to_owned(),
+            });
+        }
+        Ok(_) | Err(ClientError::UnknownUser(_)) => {}
+        Err(error) => {
+            return Ok(JsonEvent::RegistrationFailed {
+                username,
+                error: format!("Failed to check whether the username is taken: {error}"),
+            })
+        }
+    }
+
+    match network_client.register_username(username.clone()).await {
+        Ok(()) => Ok(JsonEvent::Registered { username }),
+        Err(error) => Ok(JsonEvent::RegistrationFailed {
+            username,
+            error: error.to_string(),
+        }),
+    }
+}
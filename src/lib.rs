@@ -0,0 +1,19 @@
+//! `decent-share` is a decentralised file swapping network. Peers can chat in
+//! a global chat room, message each other directly, and swap files in a one
+//! for one manner, where to receive someone else's file you must also offer
+//! them a file of your own.
+//!
+//! A node is configured and created with a [`NodeBuilder`]. The resulting
+//! [`Client`] is used to act on the network, while [`Event`]s from the
+//! network are received through the [`EventStream`].
+
+mod network;
+
+pub use libp2p::{self, pnet::PreSharedKey, Multiaddr, PeerId};
+pub use network::{
+    build_transport, read_swarm_key, Attestation, ChatConfig, Client, ClientError, DiscoveryConfig,
+    Event, EventLoop, EventStream, NetworkError, NodeBuilder, NodeConfig, PeerList, Receipt,
+    ReceiptError, RendezvousStatus, Reputation, RequestConfig, SignedReceipt, StorageConfig,
+    StorageError, Trade, TradeDirection, TradeId, TradeParty, TradeStatus, TransferProgress,
+    TransportConfig, RENDEZVOUS_POINT_PEER_ID, RENDEZVOUS_POINT_PORT_NUMBER,
+};
@@ -19,50 +19,297 @@
 // DEALINGS IN THE SOFTWARE.
 
 mod action;
+mod config;
+#[cfg(unix)]
+mod daemon;
+mod hooks;
+mod http;
 mod interface;
-mod network;
+mod jobs;
+mod json;
+mod policy;
+mod prompt;
+mod tui;
+
+use std::{io::IsTerminal, path::PathBuf};
 
 use clap::Parser;
-use futures::StreamExt;
+use decent_share::{read_swarm_key, Multiaddr, NodeBuilder, NodeConfig};
 use tokio::io::AsyncBufReadExt;
 use tracing_subscriber::EnvFilter;
 
-use interface::{handle_network_event, handle_std_in};
+use config::{default_data_dir, read_config, AppConfig};
+use interface::{handle_network_events, handle_std_in};
+use jobs::Jobs;
+use prompt::{read_commands, CommandHelper};
 
 #[tokio::main]
+#[allow(clippy::too_many_lines)]
 async fn main() -> Result<(), anyhow::Error> {
-    // Subscribe to the logging output by libp2p
-    let _ = tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::default())
-        .try_init();
+    let mut arguments = Arguments::parse();
+    let use_tui = arguments.tui;
+    let use_json = arguments.json;
+    let action = arguments.action.take();
+
+    // Receipts are checked without a node
+    if let Some(Action::VerifyReceipt { path }) = &action {
+        let receipt = action::handle_verify_receipt(path)?;
+        for line in interface::describe_receipt(&receipt) {
+            println!("{line}");
+        }
+        return Ok(());
+    }
+
+    // Actions other than running the daemon are carried out by a daemon
+    // which is already running
+    #[cfg(unix)]
+    let socket_path = arguments
+        .socket
+        .take()
+        .unwrap_or_else(daemon::default_socket_path);
+    let daemon = match action {
+        #[cfg(unix)]
+        Some(Action::Events) => return daemon::print_events(&socket_path).await,
+        #[cfg(unix)]
+        Some(Action::Command(command)) => return daemon::send_command(&socket_path, command).await,
+        #[cfg(unix)]
+        Some(Action::Daemon { http }) => Some(http),
+        Some(Action::VerifyReceipt { .. }) | None => None,
+        #[cfg(not(unix))]
+        Some(_) => anyhow::bail!("Daemon mode is only supported on Unix"),
+    };
+    let use_daemon = daemon.is_some();
+
+    // Subscribe to the logging output by libp2p, which would otherwise be
+    // drawn over the terminal UI. In JSON and daemon mode stdout is kept for
+    // JSON alone.
+    if use_json || use_daemon {
+        let _ = tracing_subscriber::fmt()
+            .with_env_filter(EnvFilter::default())
+            .with_writer(std::io::stderr)
+            .try_init();
+    } else if !use_tui {
+        let _ = tracing_subscriber::fmt()
+            .with_env_filter(EnvFilter::default())
+            .try_init();
+    }
+
+    let (mut config, app_config) = match &arguments.config {
+        Some(config_path) => read_config(config_path)?,
+        None => (NodeConfig::default(), AppConfig::default()),
+    };
+    arguments.apply_to(&mut config);
 
-    let arguments = Arguments::parse();
+    let mut node_builder = NodeBuilder::from_config(config.clone());
+    if let Some(swarm_key) = &config.swarm_key {
+        let pre_shared_key = read_swarm_key(swarm_key)?;
+        if !use_json && !use_daemon {
+            println!(
+                "Joining private network with key fingerprint {}",
+                pre_shared_key.fingerprint()
+            );
+        }
+        node_builder = node_builder.pre_shared_key(pre_shared_key);
+    }
 
-    let (mut network_client, mut network_events, network_event_loop) =
-        network::new(arguments.username, arguments.rendezvous_address)?;
+    let (network_client, network_events, network_event_loop) = node_builder.build()?;
 
     // Spawn the network task for it to run in the background
     tokio::task::spawn(network_event_loop.run());
+    let network_events = policy::spawn(
+        app_config.trade_policy,
+        network_client.clone(),
+        network_events,
+    );
+    let network_events = hooks::spawn(app_config.hooks, network_client.clone(), network_events);
+
+    if use_tui {
+        return tui::run(network_client, network_events).await;
+    }
+    if use_json {
+        return json::run(network_client, network_events).await;
+    }
+    #[cfg(unix)]
+    if let Some(http) = daemon {
+        return daemon::run(&socket_path, http, network_client, network_events).await;
+    }
 
-    let mut stdin = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    // Display network events independently of any commands in progress
+    let network_events = tokio::task::spawn(handle_network_events(
+        network_events,
+        network_client.clone(),
+    ));
 
-    // listen for user actions on stdin and events from the network
-    loop {
-        tokio::select! {
-            command = stdin.next_line() => handle_std_in(command, &mut network_client).await,
-            event = network_events.next() => handle_network_event(event, &mut network_client).await,
+    // Run each user action from stdin as its own task, so that long running
+    // actions such as trades don't block others
+    let jobs = Jobs::default();
+    if std::io::stdin().is_terminal() {
+        let runtime = tokio::runtime::Handle::current();
+        let helper = CommandHelper::new(network_client.clone(), runtime.clone());
+        tokio::task::spawn_blocking(move || {
+            read_commands(helper, |command| {
+                runtime.spawn(handle_std_in(command, network_client.clone(), jobs.clone()));
+            })
+        })
+        .await??;
+    } else {
+        let mut stdin = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+        loop {
+            match stdin.next_line().await {
+                Ok(Some(command)) => {
+                    tokio::task::spawn(handle_std_in(
+                        command,
+                        network_client.clone(),
+                        jobs.clone(),
+                    ));
+                }
+                Ok(None) => break,
+                Err(error) => eprintln!("Error reading command: {error:?}"),
+            }
         }
     }
+
+    // Keep the node running once stdin is closed
+    network_events.await?;
+    Ok(())
 }
 
+/// Settings given on the command line take precedence over those in the
+/// config file.
 #[derive(Parser, Debug)]
+#[allow(clippy::struct_excessive_bools)]
 #[command(name = "decent-share: File exchange")]
 struct Arguments {
-    /// A username to register with for user identification.
+    #[command(subcommand)]
+    action: Option<Action>,
+
+    /// The socket a daemon listens for commands on. Defaults to
+    /// `decent-share.sock` in `$XDG_RUNTIME_DIR`.
+    #[arg(long, global = true)]
+    socket: Option<PathBuf>,
+
+    /// Path to a TOML config file, see the README for its format.
+    #[arg(long, short)]
+    config: Option<PathBuf>,
+
+    /// A username to register with for user identification. Required unless
+    /// given in the config file.
     #[arg(long, short)]
-    username: String,
+    username: Option<String>,
+
+    /// Where to keep what the node remembers between runs, such as blocked
+    /// users. Defaults to a folder named after the user in
+    /// `~/.local/share/decent-share`.
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
 
-    /// The IP address of the rendezvous server.
+    /// The address of the rendezvous server. Either an IP address, a hostname,
+    /// or a full multiaddress such as `/dns4/example.com/tcp/443/wss`.
     #[arg(long, short)]
     rendezvous_address: Option<String>,
+
+    /// The multiaddress of a DHT node to bootstrap from, ending in
+    /// `/p2p/<peer_id>`. May be given multiple times.
+    #[arg(long, short)]
+    bootstrap: Vec<Multiaddr>,
+
+    /// A multiaddress to listen for connections on, e.g.
+    /// `/ip6/::1/tcp/4001`. May be given multiple times. Replaces the default
+    /// addresses, so `--port` and `--websocket-port` have no effect.
+    #[arg(long, short)]
+    listen: Vec<Multiaddr>,
+
+    /// The port to accept TCP and QUIC connections on. By default the OS
+    /// assigns any free port.
+    #[arg(long, short)]
+    port: Option<u16>,
+
+    /// The port to accept WebSocket connections on. By default the OS
+    /// assigns any free port.
+    #[arg(long, short)]
+    websocket_port: Option<u16>,
+
+    /// Don't discover peers on the local network using mDNS.
+    #[arg(long)]
+    no_mdns: bool,
+
+    /// Don't use the QUIC transport.
+    #[arg(long)]
+    no_quic: bool,
+
+    /// Don't use the WebSocket transport.
+    #[arg(long)]
+    no_websocket: bool,
+
+    /// Path to a swarm key file. When given, only peers holding the same key
+    /// are able to connect. Implies `--no-quic`.
+    #[arg(long, short)]
+    swarm_key: Option<PathBuf>,
+
+    /// Run a full-screen terminal interface instead of reading actions from
+    /// stdin line by line.
+    #[arg(long)]
+    tui: bool,
+
+    /// Read commands as JSON objects from stdin, and write their results and
+    /// network events to stdout as JSON objects, one per line. See the README
+    /// for the format.
+    #[arg(long, conflicts_with = "tui")]
+    json: bool,
+}
+
+/// Running the node in the background, or acting through a node already
+/// running in the background. Without one, the node runs interactively.
+#[derive(clap::Subcommand, Debug)]
+enum Action {
+    /// Run the node without a terminal, taking commands over a local socket
+    /// from the other subcommands. Takes the same options as running
+    /// interactively.
+    Daemon {
+        #[command(flatten)]
+        http: http::HttpOptions,
+    },
+    /// Print the daemon's network events as JSON, one per line, as they
+    /// happen.
+    Events,
+    /// Check that a saved trade receipt is signed by both parties to the
+    /// trade, without running a node.
+    VerifyReceipt { path: PathBuf },
+    #[command(flatten)]
+    Command(json::Command),
+}
+
+impl Arguments {
+    /// Overrides the settings in `config` with those given on the command line,
+    /// and picks a data directory if neither gave one.
+    fn apply_to(self, config: &mut NodeConfig) {
+        if let Some(username) = self.username {
+            config.username = username;
+        }
+        if let Some(data_dir) = self.data_dir {
+            config.data_dir = Some(data_dir);
+        }
+        if let Some(swarm_key) = self.swarm_key {
+            config.swarm_key = Some(swarm_key);
+        }
+        if let Some(rendezvous_address) = self.rendezvous_address {
+            config.discovery.rendezvous_address = Some(rendezvous_address);
+        }
+        config.discovery.bootstrap_addresses.extend(self.bootstrap);
+        if !self.listen.is_empty() {
+            config.transport.listen_addresses = self.listen;
+        }
+        if let Some(port) = self.port {
+            config.transport.port = port;
+        }
+        if let Some(websocket_port) = self.websocket_port {
+            config.transport.websocket_port = websocket_port;
+        }
+        config.discovery.mdns &= !self.no_mdns;
+        config.transport.quic &= !self.no_quic;
+        config.transport.websocket &= !self.no_websocket;
+        if config.data_dir.is_none() {
+            config.data_dir = default_data_dir(&config.username);
+        }
+    }
 }
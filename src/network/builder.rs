@@ -0,0 +1,178 @@
+use std::path::PathBuf;
+
+use anyhow::bail;
+use libp2p::{pnet::PreSharedKey, Multiaddr};
+
+use super::{Client, EventLoop, EventStream, NodeConfig};
+
+/// Configures and creates a `decent-share` node.
+///
+/// Building a node produces three components:
+///
+/// - A [`Client`] to perform actions on the network, such as sending chats
+///   or offering trades.
+///
+/// - An [`EventStream`] of [`Event`](super::Event)s from the network, such as
+///   inbound messages and trade offers.
+///
+/// - The [`EventLoop`] driving the network itself, which must be spawned as a
+///   task for the node to do anything.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), anyhow::Error> {
+/// use decent_share::NodeBuilder;
+/// use futures::StreamExt;
+///
+/// let (mut client, mut events, event_loop) = NodeBuilder::new("alice")
+///     .rendezvous_address("198.162.0.1")
+///     .build()?;
+/// tokio::spawn(event_loop.run());
+///
+/// client.send_message("Hello, world!".to_owned()).await?;
+/// while let Some(event) = events.next().await {
+///     println!("{event:?}");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The builder's methods cover the most common settings, everything else can
+/// be set through the [`NodeConfig`] given to [`NodeBuilder::from_config`].
+#[derive(Debug, Clone)]
+pub struct NodeBuilder {
+    config: NodeConfig,
+    pre_shared_key: Option<PreSharedKey>,
+}
+
+impl NodeBuilder {
+    /// Starts configuring a node which will register itself on the network
+    /// under the given username, with every other setting at its default.
+    pub fn new(username: impl Into<String>) -> Self {
+        Self::from_config(NodeConfig {
+            username: username.into(),
+            ..NodeConfig::default()
+        })
+    }
+
+    /// Starts configuring a node from an existing configuration, such as one
+    /// read with [`NodeConfig::from_file`].
+    pub fn from_config(config: NodeConfig) -> Self {
+        Self {
+            config,
+            pre_shared_key: None,
+        }
+    }
+
+    /// Connect to the rendezvous server at the given address, to discover
+    /// peers outside of the local network. This can be an IP address or
+    /// hostname, or a full multiaddress such as
+    /// `/dns4/example.com/tcp/443/wss`.
+    #[must_use]
+    pub fn rendezvous_address(mut self, address: impl Into<String>) -> Self {
+        self.config.discovery.rendezvous_address = Some(address.into());
+        self
+    }
+
+    /// Add a DHT node to bootstrap from. The address must end with
+    /// `/p2p/<peer_id>`.
+    #[must_use]
+    pub fn bootstrap_address(mut self, address: Multiaddr) -> Self {
+        self.config.discovery.bootstrap_addresses.push(address);
+        self
+    }
+
+    /// Add an address to listen for connections on. Once any address has
+    /// been added, the default addresses are no longer listened on, and the
+    /// ports set with [`NodeBuilder::port`] and
+    /// [`NodeBuilder::websocket_port`] have no effect.
+    #[must_use]
+    pub fn listen_address(mut self, address: Multiaddr) -> Self {
+        self.config.transport.listen_addresses.push(address);
+        self
+    }
+
+    /// The port to accept TCP and QUIC connections on. Defaults to `0`, which
+    /// lets the OS assign any free port.
+    #[must_use]
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.transport.port = port;
+        self
+    }
+
+    /// The port to accept WebSocket connections on. Defaults to `0`, which
+    /// lets the OS assign any free port.
+    #[must_use]
+    pub fn websocket_port(mut self, port: u16) -> Self {
+        self.config.transport.websocket_port = port;
+        self
+    }
+
+    /// Only allow connections with peers holding the same pre-shared key.
+    /// Takes precedence over the swarm key file in the [`NodeConfig`]. This
+    /// disables QUIC, as QUIC connections can not be protected by a
+    /// pre-shared key.
+    #[must_use]
+    pub fn pre_shared_key(mut self, pre_shared_key: PreSharedKey) -> Self {
+        self.pre_shared_key = Some(pre_shared_key);
+        self
+    }
+
+    /// Where to keep what the node should remember between runs, such as
+    /// its keypair and the peers which have been blocked. Nothing is
+    /// remembered by default, so the node has a new peer ID each run.
+    #[must_use]
+    pub fn data_dir(mut self, data_dir: impl Into<PathBuf>) -> Self {
+        self.config.data_dir = Some(data_dir.into());
+        self
+    }
+
+    /// Whether to discover peers on the local network using mDNS. Enabled by
+    /// default.
+    #[must_use]
+    pub fn mdns(mut self, enable: bool) -> Self {
+        self.config.discovery.mdns = enable;
+        self
+    }
+
+    /// Whether to use the QUIC transport. Enabled by default.
+    #[must_use]
+    pub fn quic(mut self, enable: bool) -> Self {
+        self.config.transport.quic = enable;
+        self
+    }
+
+    /// Whether to use the WebSocket transport. Enabled by default.
+    #[must_use]
+    pub fn websocket(mut self, enable: bool) -> Self {
+        self.config.transport.websocket = enable;
+        self
+    }
+
+    /// Creates the node's components, ready for the [`EventLoop`] to be run.
+    ///
+    /// # Errors
+    ///
+    /// Fails if no username was given, if the configuration is invalid, see
+    /// [`NodeConfig::validate`], if the swarm key file can not be read,
+    /// if any of the configured addresses are invalid or can not be listened
+    /// on, or if the network behaviours can not be initialised.
+    pub fn build(mut self) -> Result<(Client, EventStream, EventLoop), anyhow::Error> {
+        if self.config.username.is_empty() {
+            bail!("A username must be given to register with");
+        }
+        self.config.validate()?;
+
+        let pre_shared_key = match (self.pre_shared_key, &self.config.swarm_key) {
+            (Some(pre_shared_key), _) => Some(pre_shared_key),
+            (None, Some(swarm_key)) => Some(super::read_swarm_key(swarm_key)?),
+            (None, None) => None,
+        };
+
+        // QUIC connections can not be protected by a pre-shared key
+        if pre_shared_key.is_some() {
+            self.config.transport.quic = false;
+        }
+
+        super::new(self.config, pre_shared_key)
+    }
+}
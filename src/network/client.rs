@@ -1,48 +1,87 @@
 use std::{
     borrow::ToOwned,
     path::PathBuf,
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
 };
 
-use anyhow::{anyhow, bail};
 use futures::{
     channel::{mpsc, oneshot},
     SinkExt,
 };
-use libp2p::{gossipsub, kad, PeerId};
+use libp2p::PeerId;
 
-use super::{event_loop::Command, username_store::UsernameStore};
+use super::{
+    event_loop::{Command, TradeAcceptance},
+    transfer::Transfers,
+    username_store::UsernameStore,
+    Attestation, ClientError, PeerList, Reputation, Trade, TradeId, TransferProgress,
+};
 
+/// A handle to perform actions on the network. Clients are cheap to clone,
+/// and all clones share the same node and cache of known usernames.
+///
+/// Every call gives up with [`ClientError::Timeout`] if the network has not
+/// produced its result within the client's timeout, which defaults to the
+/// configured [`RequestConfig::call_timeout`](super::RequestConfig::call_timeout).
 #[derive(Clone)]
-pub(crate) struct Client {
+pub struct Client {
     pub(super) command_sender: mpsc::Sender<Command>,
     pub(super) username_store: Arc<Mutex<UsernameStore>>,
+    pub(super) local_peer_id: PeerId,
+    pub(super) timeout: Duration,
+    pub(super) transfers: Transfers,
 }
 
 impl Client {
+    /// Returns a client for the same node whose calls wait at most `timeout`
+    /// for their result, e.g. to allow a large trade more time.
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The peer ID of our own node.
+    pub fn local_peer_id(&self) -> PeerId {
+        self.local_peer_id
+    }
+
+    /// How far the files of the trades currently being exchanged have been
+    /// transferred.
+    pub fn transfers(&self) -> Vec<TransferProgress> {
+        self.transfers.progress()
+    }
+
     /// Search the DHT for the peer ID associated with a given username if we
     /// don't already have it cached.
-    pub(crate) async fn get_peer_id(&mut self, username: String) -> Option<PeerId> {
-        let mut peer_id = self
-            .username_store
-            .lock()
-            .unwrap()
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ClientError::UnknownUser`] if no peer has registered the
+    /// username.
+    pub async fn get_peer_id(&mut self, username: String) -> Result<PeerId, ClientError> {
+        let peer_id = self
+            .username_store()
             .get_peer_id(&username)
             .map(ToOwned::to_owned);
 
-        if peer_id.is_none() {
-            peer_id = self.find_user(username).await;
+        match peer_id {
+            Some(peer_id) => Ok(peer_id),
+            None => self.find_user(username).await,
         }
-        peer_id
     }
 
     /// Search the DHT for the username associated with a given peeer ID if we
     /// don't already have it cached.
-    pub(crate) async fn get_username(&mut self, peer_id: PeerId) -> Result<String, anyhow::Error> {
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ClientError::UnknownPeer`] if no username could be found
+    /// for the peer.
+    pub async fn get_username(&mut self, peer_id: PeerId) -> Result<String, ClientError> {
         let username = self
-            .username_store
-            .lock()
-            .unwrap()
+            .username_store()
             .get_username(&peer_id)
             .map(ToOwned::to_owned);
 
@@ -51,201 +90,337 @@ impl Client {
             None => self.find_peer_username(peer_id).await,
         }
     }
+
+    /// The usernames this client has already looked up or registered,
+    /// without searching the DHT.
+    pub fn known_usernames(&self) -> Vec<String> {
+        self.username_store().usernames().cloned().collect()
+    }
+
+    fn username_store(&self) -> MutexGuard<'_, UsernameStore> {
+        // The store is only ever inserted into, so it can not be left in an
+        // inconsistent state
+        self.username_store
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
 }
 
 /// Send messages to the network thread in the form of `Command` enum values
 /// This allows the main thread to remain responsive to the user interface
 /// while the network thread handles networking.
 impl Client {
-    pub(crate) async fn offer_trade(
+    /// Offer our file `offered_file_name` to the user `recipient_username`,
+    /// in exchange for their file `requested_file_name`. Should they accept,
+    /// their file will be written to `requested_file_path`. Returns the ID
+    /// the trade is known by until it completes.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the recipient is not a registered user or could not be
+    /// reached.
+    pub async fn offer_trade(
         &mut self,
         offered_file_name: String,
         offered_file_bytes: Vec<u8>,
         recipient_username: String,
         requested_file_name: String,
         requested_file_path: PathBuf,
-    ) -> Result<(), anyhow::Error> {
-        let Some(peer_id) = self.get_peer_id(recipient_username.clone()).await else {
-            bail!("'{recipient_username}' is not a registered user");
+    ) -> Result<TradeId, ClientError> {
+        let peer_id = self.get_peer_id(recipient_username).await?;
+
+        let (trade_id_sender, trade_id_receiver) = oneshot::channel();
+        let command = Command::MakeTradeOffer {
+            offered_file_name,
+            offered_file_bytes,
+            peer_id,
+            requested_file_name,
+            requested_file_path,
+            trade_id_sender,
         };
 
-        let (error_sender, error_receiver) = oneshot::channel();
-
-        self.command_sender
-            .send(Command::MakeTradeOffer {
-                offered_file_name,
-                offered_file_bytes,
-                peer_id,
-                requested_file_name,
-                requested_file_path,
-                error_sender,
-            })
-            .await
-            .expect("Command receiver was dropped");
-
-        error_receiver.await.expect("Error receiver was dropped")
+        Ok(self.request(command, trade_id_receiver).await??)
     }
 
-    pub(crate) async fn accept_trade(
+    /// Accept the trade offered to us as `trade_id`, sending the offerer the
+    /// contents of the file they requested. The file they offered is written
+    /// to `offered_file_path`. Should the call time out once the trade is
+    /// under way, that file is still written, and the outcome is reported as
+    /// [`Event::TradeCompleted`](super::Event::TradeCompleted) or
+    /// [`Event::Error`](super::Event::Error).
+    ///
+    /// # Errors
+    ///
+    /// Fails if no such trade is awaiting a response, the offerer could not
+    /// be reached or withheld their file, or their file could not be stored.
+    pub async fn accept_trade(
         &mut self,
-        username: String,
-        requested_file_name: String,
-        offered_file_name: String,
+        trade_id: TradeId,
         requested_file_bytes: Vec<u8>,
-    ) -> Result<Vec<u8>, anyhow::Error> {
-        let Some(peer_id) = self.get_peer_id(username.clone()).await else {
-            bail!("'{username}' is not a register user");
+        offered_file_path: PathBuf,
+    ) -> Result<(), ClientError> {
+        let (result_sender, result_receiver) = oneshot::channel();
+        let command = Command::RespondTrade {
+            trade_id,
+            acceptance: Some(TradeAcceptance {
+                requested_file_bytes,
+                offered_file_path,
+            }),
+            result_sender,
         };
 
-        let (offered_bytes_sender, offered_bytes_receiver) = oneshot::channel();
-
-        self.command_sender
-            .send(Command::RespondTrade {
-                peer_id,
-                requested_file_name,
-                offered_file_name,
-                requested_file_bytes: Some(requested_file_bytes),
-                offered_bytes_sender: Some(offered_bytes_sender),
-            })
-            .await
-            .expect("Command receiver was dropped");
-
-        match offered_bytes_receiver
-            .await
-            .expect("Offered bytes was dropped")
-        {
-            Ok(Some(bytes)) => Ok(bytes),
-            Ok(None) => Err(anyhow!("No bytes were received!")),
-            Err(error) => Err(error),
-        }
+        Ok(self.request(command, result_receiver).await??)
     }
 
-    pub(crate) async fn decline_trade(
-        &mut self,
-        username: String,
-        offered_file_name: String,
-        requested_file_name: String,
-    ) -> Result<(), anyhow::Error> {
-        let Some(peer_id) = self.get_peer_id(username.clone()).await else {
-            bail!("'{username}' is not a registered user");
+    /// Decline the trade offered to us as `trade_id`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if no such trade is awaiting a response.
+    pub async fn decline_trade(&mut self, trade_id: TradeId) -> Result<(), ClientError> {
+        let (result_sender, result_receiver) = oneshot::channel();
+        let command = Command::RespondTrade {
+            trade_id,
+            acceptance: None,
+            result_sender,
         };
 
-        self.command_sender
-            .send(Command::RespondTrade {
-                peer_id,
-                requested_file_name,
-                offered_file_name,
-                requested_file_bytes: None,
-                offered_bytes_sender: None,
-            })
-            .await
-            .expect("Command receiver was dropped");
+        Ok(self.request(command, result_receiver).await??)
+    }
 
-        Ok(())
+    /// Lists the trades offered to or by us which have not yet completed or
+    /// been declined.
+    ///
+    /// # Errors
+    ///
+    /// Fails only if the node has stopped.
+    pub async fn trades(&mut self) -> Result<Vec<Trade>, ClientError> {
+        let (trades_sender, trades_receiver) = oneshot::channel();
+        let command = Command::ListTrades { trades_sender };
+
+        self.request(command, trades_receiver).await
     }
 
-    pub(crate) async fn register_username(
-        &mut self,
-        username: String,
-    ) -> Result<(), kad::PutRecordError> {
+    /// Store our username in the DHT, so that other users can find us.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the record could not be stored locally or on any peer.
+    pub async fn register_username(&mut self, username: String) -> Result<(), ClientError> {
         let (status_sender, status_receiver) = oneshot::channel();
+        let command = Command::RegisterUsername {
+            username,
+            status_sender,
+        };
 
-        self.command_sender
-            .send(Command::RegisterUsername {
-                username,
-                status_sender,
-            })
-            .await
-            .expect("Command receiver was dropped");
-
-        status_receiver.await.expect("Status sender was dropped")
+        Ok(self.request(command, status_receiver).await??)
     }
 
-    async fn find_user(&mut self, username: String) -> Option<PeerId> {
+    async fn find_user(&mut self, username: String) -> Result<PeerId, ClientError> {
         let (peer_id_sender, peer_id_receiver) = oneshot::channel();
-        self.command_sender
-            .send(Command::FindPeerId {
-                username: username.clone(),
-                peer_id_sender,
-            })
-            .await
-            .expect("Command receiver was dropped");
+        let command = Command::FindPeerId {
+            username: username.clone(),
+            peer_id_sender,
+        };
 
-        let peer_id = peer_id_receiver
-            .await
-            .expect("Peer ID sender not be dropped.");
+        let Some(peer_id) = self.request(command, peer_id_receiver).await? else {
+            return Err(ClientError::UnknownUser(username));
+        };
 
-        if let Some(peer_id) = peer_id {
-            self.username_store
-                .lock()
-                .unwrap()
-                .insert(username, peer_id);
-        }
+        self.username_store().insert(username, peer_id);
 
-        peer_id
+        Ok(peer_id)
     }
 
-    async fn find_peer_username(&mut self, peer_id: PeerId) -> Result<String, anyhow::Error> {
+    async fn find_peer_username(&mut self, peer_id: PeerId) -> Result<String, ClientError> {
         let (username_sender, username_receiver) = oneshot::channel();
-        self.command_sender
-            .send(Command::FindPeerUsername {
-                peer_id,
-                username_sender,
-            })
-            .await
-            .expect("Command receiver was dropped");
+        let command = Command::FindPeerUsername {
+            peer_id,
+            username_sender,
+        };
 
-        let username = username_receiver
-            .await
-            .expect("Username sender was dropped");
+        let Ok(username) = self.request(command, username_receiver).await? else {
+            return Err(ClientError::UnknownPeer(peer_id));
+        };
 
-        if let Ok(ref username) = username {
-            self.username_store
-                .lock()
-                .unwrap()
-                .insert(username.to_owned(), peer_id);
-        }
+        self.username_store().insert(username.clone(), peer_id);
 
-        username
+        Ok(username)
     }
 
-    pub(crate) async fn send_message(
-        &mut self,
-        message: String,
-    ) -> Result<(), gossipsub::PublishError> {
+    /// Send a message to the global chat room.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the message could not be published, e.g. because no peers
+    /// are connected.
+    pub async fn send_message(&mut self, message: String) -> Result<(), ClientError> {
         let (status_sender, status_receiver) = oneshot::channel();
+        let command = Command::SendChatMessage {
+            message,
+            status_sender,
+        };
 
-        self.command_sender
-            .send(Command::SendChatMessage {
-                message,
-                status_sender,
-            })
-            .await
-            .expect("Command receiver was dropped");
-
-        status_receiver.await.expect("Status sender was dropped")
+        Ok(self.request(command, status_receiver).await??)
     }
 
-    pub(crate) async fn direct_message(
+    /// Send a message directly to the user `username`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `username` is not a registered user or could not be reached.
+    pub async fn direct_message(
         &mut self,
         username: String,
         message: String,
-    ) -> Result<(), anyhow::Error> {
-        let Some(peer_id) = self.get_peer_id(username.clone()).await else {
-            bail!("'{username}' is not a registered user");
-        };
+    ) -> Result<(), ClientError> {
+        let peer_id = self.get_peer_id(username).await?;
 
         let (error_sender, error_receiver) = oneshot::channel();
+        let command = Command::DirectMessage {
+            peer_id,
+            message,
+            error_sender,
+        };
 
-        self.command_sender
-            .send(Command::DirectMessage {
-                peer_id,
-                message,
-                error_sender,
-            })
-            .await
-            .expect("Command receiver was dropped");
+        Ok(self.request(command, error_receiver).await??)
+    }
+
+    /// Block a peer, closing any connections with them and refusing new
+    /// ones. Their requests and chat messages are dropped, and chat messages
+    /// they author are not forwarded to other peers. The peer stays blocked
+    /// across restarts if the node has a data directory.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the peer list could not be saved, though the peer is blocked
+    /// regardless.
+    pub async fn block_peer(&mut self, peer_id: PeerId) -> Result<(), ClientError> {
+        self.set_blocked(peer_id, true).await
+    }
+
+    /// Unblock a peer blocked with [`Client::block_peer`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if the peer list could not be saved, though the peer is
+    /// unblocked regardless.
+    pub async fn unblock_peer(&mut self, peer_id: PeerId) -> Result<(), ClientError> {
+        self.set_blocked(peer_id, false).await
+    }
+
+    async fn set_blocked(&mut self, peer_id: PeerId, blocked: bool) -> Result<(), ClientError> {
+        let (result_sender, result_receiver) = oneshot::channel();
+        let command = Command::BlockPeer {
+            peer_id,
+            blocked,
+            result_sender,
+        };
+
+        Ok(self.request(command, result_receiver).await??)
+    }
+
+    /// Mute a peer, so that their chat and direct messages are no longer
+    /// reported. Unlike blocking, they stay connected and may still offer
+    /// trades.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the peer list could not be saved, though the peer is muted
+    /// regardless.
+    pub async fn mute_peer(&mut self, peer_id: PeerId) -> Result<(), ClientError> {
+        self.set_muted(peer_id, true).await
+    }
 
-        error_receiver.await.expect("Error sender was dropped")
+    /// Unmute a peer muted with [`Client::mute_peer`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if the peer list could not be saved, though the peer is unmuted
+    /// regardless.
+    pub async fn unmute_peer(&mut self, peer_id: PeerId) -> Result<(), ClientError> {
+        self.set_muted(peer_id, false).await
+    }
+
+    async fn set_muted(&mut self, peer_id: PeerId, muted: bool) -> Result<(), ClientError> {
+        let (result_sender, result_receiver) = oneshot::channel();
+        let command = Command::MutePeer {
+            peer_id,
+            muted,
+            result_sender,
+        };
+
+        Ok(self.request(command, result_receiver).await??)
+    }
+
+    /// Lists the peers which are blocked or muted.
+    ///
+    /// # Errors
+    ///
+    /// Fails only if the node has stopped.
+    pub async fn peer_list(&mut self) -> Result<PeerList, ClientError> {
+        let (peer_list_sender, peer_list_receiver) = oneshot::channel();
+        let command = Command::GetPeerList { peer_list_sender };
+
+        self.request(command, peer_list_receiver).await
+    }
+
+    /// How our trades with a peer have gone. Peers we have never traded with
+    /// have a reputation of all zeroes.
+    ///
+    /// # Errors
+    ///
+    /// Fails only if the node has stopped.
+    pub async fn reputation(&mut self, peer_id: PeerId) -> Result<Reputation, ClientError> {
+        let (reputation_sender, reputation_receiver) = oneshot::channel();
+        let command = Command::GetReputation {
+            peer_id,
+            reputation_sender,
+        };
+
+        self.request(command, reputation_receiver).await
+    }
+
+    /// Asks the peer `issuer` how their trades with `subject` have gone. The
+    /// answer is signed by `issuer`, which has been checked, so it can be
+    /// passed on to others. Only peers which enable
+    /// [`RequestConfig::share_reputation`](super::RequestConfig::share_reputation)
+    /// answer.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `issuer` could not be reached, does not share reputations or
+    /// sent an attestation they did not sign.
+    pub async fn query_reputation(
+        &mut self,
+        issuer: PeerId,
+        subject: PeerId,
+    ) -> Result<Attestation, ClientError> {
+        let (attestation_sender, attestation_receiver) = oneshot::channel();
+        let command = Command::QueryReputation {
+            peer_id: issuer,
+            subject,
+            attestation_sender,
+        };
+
+        Ok(self.request(command, attestation_receiver).await??)
+    }
+
+    /// Sends a command to the event loop and waits for its result, giving up
+    /// once the client's timeout has passed. Giving up drops the receiver,
+    /// which lets the event loop discard the request.
+    async fn request<T>(
+        &mut self,
+        command: Command,
+        receiver: oneshot::Receiver<T>,
+    ) -> Result<T, ClientError> {
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        tokio::time::timeout_at(deadline, self.command_sender.send(command))
+            .await
+            .map_err(|_| ClientError::Timeout)??;
+        tokio::time::timeout_at(deadline, receiver)
+            .await
+            .map_err(|_| ClientError::Timeout)?
+            .map_err(ClientError::from)
     }
 }
@@ -0,0 +1,367 @@
+use std::{path::Path, path::PathBuf, time::Duration};
+
+use anyhow::bail;
+use libp2p::Multiaddr;
+use serde::{Deserialize, Serialize};
+
+/// The complete configuration of a `decent-share` node. Every field has a
+/// default, so a config file need only specify the values which differ.
+///
+/// ```toml
+/// username = "alice"
+///
+/// [transport]
+/// port = 4001
+/// quic = false
+///
+/// [discovery]
+/// rendezvous_address = "rendezvous.example.com"
+///
+/// [requests]
+/// max_file_size = 104857600
+/// ```
+///
+/// Durations are given in whole seconds.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    /// The username to register with for user identification.
+    pub username: String,
+    /// Path to a swarm key file. When given, only peers holding the same key
+    /// are able to connect.
+    pub swarm_key: Option<PathBuf>,
+    /// Where the node keeps what it should remember between runs, such as
+    /// its keypair and the peers which have been blocked. Without one the
+    /// node has a new peer ID each run, and nothing is remembered.
+    pub data_dir: Option<PathBuf>,
+    pub transport: TransportConfig,
+    pub discovery: DiscoveryConfig,
+    pub chat: ChatConfig,
+    pub requests: RequestConfig,
+    pub storage: StorageConfig,
+}
+
+/// How the node connects to other peers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransportConfig {
+    /// Addresses to listen for connections on. When empty, all IPv4 and IPv6
+    /// interfaces are listened on, using `port` and `websocket_port`.
+    pub listen_addresses: Vec<Multiaddr>,
+    /// The port to accept TCP and QUIC connections on. `0` lets the OS assign
+    /// any free port.
+    pub port: u16,
+    /// The port to accept WebSocket connections on. `0` lets the OS assign
+    /// any free port.
+    pub websocket_port: u16,
+    /// Whether to use the QUIC transport. Ignored when a swarm key is used.
+    pub quic: bool,
+    /// Whether to use the WebSocket transport.
+    pub websocket: bool,
+    /// How long a connection with no active streams is kept open.
+    #[serde(with = "seconds")]
+    pub idle_connection_timeout: Duration,
+    /// The most connections, to all peers, which may be open at once.
+    pub max_connections: u32,
+    /// The most connections which may be open to a single peer at once.
+    pub max_connections_per_peer: u32,
+    /// The most incoming connections which may be in the middle of being
+    /// established at once.
+    pub max_pending_incoming_connections: u32,
+}
+
+/// How the node finds other peers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    /// Whether to discover peers on the local network using mDNS.
+    pub mdns: bool,
+    /// The address of the rendezvous server. Either an IP address, a
+    /// hostname, or a full multiaddress.
+    pub rendezvous_address: Option<String>,
+    /// How often to ask the rendezvous server for newly registered peers.
+    #[serde(with = "seconds")]
+    pub rendezvous_discover_interval: Duration,
+    /// DHT nodes to bootstrap from, each ending in `/p2p/<peer_id>`.
+    pub bootstrap_addresses: Vec<Multiaddr>,
+    /// How often to refresh the DHT routing table by bootstrapping.
+    #[serde(with = "seconds")]
+    pub bootstrap_interval: Duration,
+    /// How often to look up a random peer ID, discovering new DHT peers.
+    #[serde(with = "seconds")]
+    pub random_walk_interval: Duration,
+}
+
+/// The global chat room.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChatConfig {
+    /// The gossipsub topic chat messages are published to. Only peers using
+    /// the same topic will see each other's messages.
+    pub topic: String,
+    /// The gossipsub heartbeat interval.
+    #[serde(with = "seconds")]
+    pub heartbeat_interval: Duration,
+    /// The largest chat message, in bytes, which will be sent or forwarded.
+    pub max_message_size: usize,
+    /// How long after a chat message was sent it is still shown and
+    /// forwarded. Older messages are assumed to be replayed. Peers' clocks
+    /// need to agree to within this.
+    #[serde(with = "seconds")]
+    pub max_message_age: Duration,
+}
+
+/// Direct messages and trades, sent as requests to a single peer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RequestConfig {
+    /// How long to wait for a peer to respond to a request. Trades of large
+    /// files over slow connections may need longer.
+    #[serde(with = "seconds")]
+    pub timeout: Duration,
+    /// How long a [`Client`](super::Client) call waits for its result, such
+    /// as a DHT lookup or a peer's response, before giving up. Should be
+    /// longer than `timeout`.
+    #[serde(with = "seconds")]
+    pub call_timeout: Duration,
+    /// The largest direct message or trade offer, in bytes.
+    pub max_message_size: u64,
+    /// The largest file, in bytes, which can be sent or received in a trade.
+    pub max_file_size: u64,
+    /// How many direct messages and trade offers a single peer may send us
+    /// per minute. Requests beyond this are dropped.
+    pub max_requests_per_minute: u32,
+    /// The most trade offers which may await our response at once. Further
+    /// offers are dropped until some have been responded to.
+    pub max_inbound_offers: usize,
+    /// The most trade offers from a single peer which may await our response
+    /// at once.
+    pub max_inbound_offers_per_peer: usize,
+    /// How long a trade offer may await our response. Offers which haven't
+    /// been responded to by then are dropped, and can no longer be accepted.
+    #[serde(with = "seconds")]
+    pub inbound_offer_timeout: Duration,
+    /// Whether to tell peers who ask how our trades with another peer have
+    /// gone, see [`Client::query_reputation`](super::Client::query_reputation).
+    pub share_reputation: bool,
+}
+
+/// How files received in trades are written to disk.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// Whether to flush received files to disk before reporting them as
+    /// written, so that they survive a crash or power loss.
+    pub fsync: bool,
+    /// Bytes of disk space to leave free. Received files which would not
+    /// leave this much space are refused.
+    pub reserved_space: u64,
+}
+
+impl NodeConfig {
+    /// Reads a node configuration from a TOML file.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can not be read or is not a valid configuration.
+    pub fn from_file(path: &Path) -> Result<Self, anyhow::Error> {
+        let config: Self = toml::from_str(&std::fs::read_to_string(path)?)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the values which can be given but aren't usable, such as
+    /// intervals of zero seconds.
+    ///
+    /// # Errors
+    ///
+    /// Fails naming the first such value.
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        let intervals = [
+            (
+                "discovery.rendezvous_discover_interval",
+                self.discovery.rendezvous_discover_interval,
+            ),
+            (
+                "discovery.bootstrap_interval",
+                self.discovery.bootstrap_interval,
+            ),
+            (
+                "discovery.random_walk_interval",
+                self.discovery.random_walk_interval,
+            ),
+            ("chat.heartbeat_interval", self.chat.heartbeat_interval),
+            ("chat.max_message_age", self.chat.max_message_age),
+        ];
+        for (name, interval) in intervals {
+            if interval.is_zero() {
+                bail!("`{name}` must be at least one second");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        Self {
+            listen_addresses: Vec::new(),
+            port: 0,
+            websocket_port: 0,
+            quic: true,
+            websocket: true,
+            idle_connection_timeout: Duration::from_mins(1),
+            max_connections: 256,
+            max_connections_per_peer: 4,
+            max_pending_incoming_connections: 32,
+        }
+    }
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            mdns: true,
+            rendezvous_address: None,
+            rendezvous_discover_interval: Duration::from_secs(30),
+            bootstrap_addresses: Vec::new(),
+            bootstrap_interval: Duration::from_mins(5),
+            random_walk_interval: Duration::from_mins(1),
+        }
+    }
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            // Versioned since messages were wrapped in an envelope, which
+            // older peers would neither send nor understand
+            topic: "chat-room/2".to_owned(),
+            // This is set to aid debugging by not cluttering the log space
+            heartbeat_interval: Duration::from_secs(10),
+            max_message_size: 65536,
+            max_message_age: Duration::from_mins(5),
+        }
+    }
+}
+
+impl Default for RequestConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            call_timeout: Duration::from_mins(1),
+            max_message_size: 1024 * 1024,
+            max_file_size: 10 * 1024 * 1024,
+            max_requests_per_minute: 30,
+            max_inbound_offers: 100,
+            max_inbound_offers_per_peer: 10,
+            inbound_offer_timeout: Duration::from_hours(1),
+            share_reputation: false,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            fsync: true,
+            reserved_space: 0,
+        }
+    }
+}
+
+/// (De)serializes a [`Duration`] as a whole number of seconds.
+mod seconds {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(
+        duration: &Duration,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_secs())
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Duration, D::Error> {
+        Ok(Duration::from_secs(u64::deserialize(deserializer)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unspecified_values_are_defaulted() {
+        let config: NodeConfig = toml::from_str(
+            r#"
+            username = "alice"
+
+            [transport]
+            port = 4001
+            quic = false
+
+            [requests]
+            timeout = 30
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.username, "alice");
+        assert_eq!(config.transport.port, 4001);
+        assert!(!config.transport.quic);
+        assert!(config.transport.websocket);
+        assert_eq!(config.requests.timeout, Duration::from_secs(30));
+        assert_eq!(
+            config.requests.call_timeout,
+            RequestConfig::default().call_timeout
+        );
+        assert_eq!(config.discovery, DiscoveryConfig::default());
+    }
+
+    #[test]
+    fn empty_config_is_default() {
+        assert_eq!(
+            toml::from_str::<NodeConfig>("").unwrap(),
+            NodeConfig::default()
+        );
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert!(toml::from_str::<NodeConfig>("usernme = \"alice\"").is_err());
+        assert!(toml::from_str::<NodeConfig>("[transport]\nprot = 4001").is_err());
+        assert!(toml::from_str::<NodeConfig>("[transports]\nport = 4001").is_err());
+    }
+
+    #[test]
+    fn zero_intervals_are_rejected() {
+        assert!(NodeConfig::default().validate().is_ok());
+        for config in [
+            "[discovery]\nbootstrap_interval = 0",
+            "[discovery]\nrandom_walk_interval = 0",
+            "[discovery]\nrendezvous_discover_interval = 0",
+            "[chat]\nheartbeat_interval = 0",
+            "[chat]\nmax_message_age = 0",
+        ] {
+            let config: NodeConfig = toml::from_str(config).unwrap();
+            assert!(config.validate().is_err(), "{config:?}");
+        }
+    }
+
+    #[test]
+    fn config_round_trips() {
+        let mut config = NodeConfig {
+            username: "bob".to_owned(),
+            ..NodeConfig::default()
+        };
+        config.discovery.rendezvous_address = Some("rendezvous.example.com".to_owned());
+        config.chat.max_message_age = Duration::from_secs(90);
+
+        let serialized = toml::to_string(&config).unwrap();
+        assert_eq!(toml::from_str::<NodeConfig>(&serialized).unwrap(), config);
+    }
+}
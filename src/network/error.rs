@@ -0,0 +1,138 @@
+use std::string::FromUtf8Error;
+
+use futures::channel::{mpsc, oneshot};
+use libp2p::{gossipsub, kad, request_response, swarm::DialError, PeerId};
+
+use super::{StorageError, TradeId};
+
+/// Failures of the network layer. These are either returned to the
+/// [`Client`](super::Client) call which caused them, or reported through
+/// [`Event::Error`](super::Event::Error) when no call is waiting on them.
+/// Either way the node keeps running.
+#[derive(Debug, thiserror::Error)]
+pub enum NetworkError {
+    /// A record could not be stored in our own copy of the DHT.
+    #[error("Failed to store record locally: {0}")]
+    StoreRecord(#[from] kad::store::Error),
+    /// A record could not be stored on any other DHT peer.
+    #[error("Failed to publish record to the DHT: {0}")]
+    PutRecord(#[from] kad::PutRecordError),
+    /// A record could not be found in the DHT.
+    #[error("Failed to look up record in the DHT: {0}")]
+    GetRecord(#[from] kad::GetRecordError),
+    /// A DHT lookup finished without finding a record.
+    #[error("No record was found in the DHT")]
+    RecordNotFound,
+    /// A username record found in the DHT is not valid UTF-8.
+    #[error("Username record is not valid UTF-8: {0}")]
+    InvalidUsername(#[from] FromUtf8Error),
+    /// A request we sent to a peer failed, e.g. because they could not be
+    /// reached or did not respond in time.
+    #[error("Request to {peer_id} failed: {error}")]
+    Request {
+        peer_id: PeerId,
+        error: request_response::OutboundFailure,
+    },
+    /// The connection to a peer closed before we could respond to their
+    /// request.
+    #[error("Connection to {peer_id} closed before their request could be answered")]
+    Response { peer_id: PeerId },
+    /// A peer we discovered could not be dialed.
+    #[error("Failed to dial {peer_id}: {error}")]
+    Dial { peer_id: PeerId, error: DialError },
+    /// A file received in a trade could not be stored.
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    /// A peer accepted our response to their trade, but did not send the file
+    /// they offered.
+    #[error("{peer_id} did not send the file they offered")]
+    FileWithheld { peer_id: PeerId },
+    /// A peer sent a larger file than they claimed when offering it, so it
+    /// was not stored.
+    #[error("{peer_id} sent a larger file than they offered")]
+    OversizedFile { peer_id: PeerId },
+    /// Direct messages and trade offers can not be sent to ourselves.
+    #[error("Sending requests to yourself is forbidden")]
+    SelfRequest,
+    /// A trade was responded to which was never offered, or has already been
+    /// responded to.
+    #[error("No pending trade offer with ID {trade_id}")]
+    UnknownTrade { trade_id: TradeId },
+    /// A peer sent a receipt for a trade which does not match what was
+    /// traded, or which they did not sign. The trade itself still completed.
+    #[error("{peer_id} sent an invalid receipt")]
+    InvalidReceipt { peer_id: PeerId },
+    /// A peer asked for a reputation does not share them.
+    #[error("{peer_id} does not share reputations")]
+    ReputationNotShared { peer_id: PeerId },
+    /// A peer answered with an attestation which they did not sign, or which
+    /// is about a different peer than the one asked about.
+    #[error("{peer_id} sent an invalid attestation")]
+    InvalidAttestation { peer_id: PeerId },
+}
+
+/// Failures of the actions performed through a [`Client`](super::Client).
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    /// No peer has registered the given username.
+    #[error("'{0}' is not a registered user")]
+    UnknownUser(String),
+    /// No username could be found for the given peer.
+    #[error("No username is registered for {0}")]
+    UnknownPeer(PeerId),
+    /// The peer could not be connected to, or the connection was lost before
+    /// they responded.
+    #[error("{peer_id} could not be reached")]
+    PeerUnreachable { peer_id: PeerId },
+    /// The peer refused our request, e.g. because they do not support it or
+    /// withheld the file they offered.
+    #[error("{peer_id} rejected the request")]
+    Rejected { peer_id: PeerId },
+    /// No response arrived in time.
+    #[error("Timed out waiting for a response")]
+    Timeout,
+    /// The trade being responded to was never offered, or has already been
+    /// responded to.
+    #[error("No pending trade offer with ID {trade_id}")]
+    TradeNotFound { trade_id: TradeId },
+    /// A chat message could not be published.
+    #[error("Failed to publish chat message: {0}")]
+    Publish(#[from] gossipsub::PublishError),
+    /// The [`EventLoop`](super::EventLoop) is no longer running.
+    #[error("The network is no longer running")]
+    Stopped,
+    /// Any other failure of the network layer.
+    #[error(transparent)]
+    Network(NetworkError),
+}
+
+impl From<NetworkError> for ClientError {
+    fn from(error: NetworkError) -> Self {
+        match error {
+            NetworkError::Request {
+                error: request_response::OutboundFailure::Timeout,
+                ..
+            } => Self::Timeout,
+            NetworkError::Request {
+                peer_id,
+                error: request_response::OutboundFailure::UnsupportedProtocols,
+            }
+            | NetworkError::FileWithheld { peer_id } => Self::Rejected { peer_id },
+            NetworkError::Request { peer_id, .. } => Self::PeerUnreachable { peer_id },
+            NetworkError::UnknownTrade { trade_id } => Self::TradeNotFound { trade_id },
+            error => Self::Network(error),
+        }
+    }
+}
+
+impl From<mpsc::SendError> for ClientError {
+    fn from(_: mpsc::SendError) -> Self {
+        Self::Stopped
+    }
+}
+
+impl From<oneshot::Canceled> for ClientError {
+    fn from(_: oneshot::Canceled) -> Self {
+        Self::Stopped
+    }
+}
@@ -1,13 +1,22 @@
-use anyhow::anyhow;
-use futures::SinkExt;
+use std::time::Duration;
+
+use futures::channel::oneshot;
 use libp2p::{
     gossipsub, identify,
     kad::{self, QueryId},
     multiaddr, rendezvous, request_response, Multiaddr, PeerId,
 };
+use tokio::time::Instant;
 
-use super::{Event, EventLoop};
-use crate::network::{DirectMessage, NoResponse, TradeOffer, TradeResponse, TradeResponseResponse};
+use super::{
+    receipt, unix_time, AcceptedTrade, Attestation, Event, EventLoop, NetworkResult, Receipt,
+    RendezvousStatus, SignedReceipt, StoredOfferedFile, StoredTradeFile, TradeDirection,
+    TradeOutcome, TradeParty, TradeStatus, INITIAL_RENDEZVOUS_BACKOFF, MAX_RENDEZVOUS_BACKOFF,
+};
+use crate::network::{
+    DirectMessage, NetworkError, NoResponse, ReputationRequest, ReputationResponse, TradeOffer,
+    TradeOfferRequest, TradeResponse, TradeResponseResponse,
+};
 
 /// Handler functions for inbound network events
 impl EventLoop {
@@ -19,36 +28,42 @@ impl EventLoop {
             })) => {
                 if let Some(peer_id_sender) = self.pending_peer_id_request.remove(&query_id) {
                     let peer_id = PeerId::from_bytes(&value).ok();
-                    peer_id_sender
-                        .send(peer_id)
-                        .expect("Peer ID receiver was dropped");
+                    let _ = peer_id_sender.send(peer_id);
                 } else if let Some(username_sender) =
                     self.pending_username_request.remove(&query_id)
                 {
-                    let username = String::from_utf8(value).map_err(|error| anyhow!(error));
-                    username_sender
-                        .send(username)
-                        .expect("Username receiver was dropped");
+                    let username = String::from_utf8(value).map_err(NetworkError::from);
+                    let _ = username_sender.send(username);
+                }
+
+                // Only the first record found is of interest
+                if let Some(mut query) = self.swarm.behaviour_mut().kademlia.query_mut(&query_id) {
+                    query.finish();
+                }
+            }
+            // The query finished without finding a record, as any record
+            // found would already have been handled
+            Ok(kad::GetRecordOk::FinishedWithNoAdditionalRecord { .. }) => {
+                if let Some(peer_id_sender) = self.pending_peer_id_request.remove(&query_id) {
+                    let _ = peer_id_sender.send(None);
+                } else if let Some(username_sender) =
+                    self.pending_username_request.remove(&query_id)
+                {
+                    let _ = username_sender.send(Err(NetworkError::RecordNotFound));
                 }
             }
-            Ok(_) => {}
             Err(error) => {
                 if let Some(peer_id_sender) = self.pending_peer_id_request.remove(&query_id) {
-                    peer_id_sender
-                        .send(None)
-                        .expect("Peer ID receiver was dropped");
+                    let _ = peer_id_sender.send(None);
                 } else if let Some(username_sender) =
                     self.pending_username_request.remove(&query_id)
                 {
-                    username_sender
-                        .send(Err(anyhow!(error)))
-                        .expect("Username receiver was dropped");
+                    let _ = username_sender.send(Err(error.into()));
                 }
             }
         }
     }
 
-    #[allow(clippy::unused_self)]
     pub(super) fn handle_put_record(
         &mut self,
         record: kad::PutRecordResult,
@@ -56,10 +71,8 @@ impl EventLoop {
     ) {
         if let Some(status_sender) = self.pending_register_username.remove(&query_id) {
             self.has_registered_username = record.is_ok();
-            let status = record.map(|_| ());
-            status_sender
-                .send(status)
-                .expect("Status receiver was dropped");
+            let status = record.map(|_| ()).map_err(NetworkError::from);
+            let _ = status_sender.send(status);
         }
     }
 
@@ -72,26 +85,33 @@ impl EventLoop {
             request_response::Message::Request {
                 request, channel, ..
             } => {
-                self.event_sender
-                    .send(Event::InboundDirectMessage {
+                // Dropping the channel fails the request on their side
+                if self.should_drop_request(peer_id) {
+                    return;
+                }
+                if !self.peer_list.muted.contains(&peer_id) {
+                    self.send_event(Event::InboundDirectMessage {
                         peer_id,
                         message: request.0,
                     })
-                    .await
-                    .expect("Event receiver was dropped");
+                    .await;
+                }
 
-                self.swarm
+                if self
+                    .swarm
                     .behaviour_mut()
                     .direct_messaging
                     .send_response(channel, NoResponse())
-                    .expect("Connection to peer was dropped");
+                    .is_err()
+                {
+                    self.send_event(Event::Error(NetworkError::Response { peer_id }))
+                        .await;
+                }
             }
             request_response::Message::Response { request_id, .. } => {
-                let _ = self
-                    .pending_request_message
-                    .remove(&request_id)
-                    .expect("Message was not pending")
-                    .send(Ok(()));
+                if let Some(error_sender) = self.pending_request_message.remove(&request_id) {
+                    let _ = error_sender.send(Ok(()));
+                }
             }
         }
     }
@@ -100,17 +120,16 @@ impl EventLoop {
         &mut self,
         request_id: request_response::OutboundRequestId,
         error: request_response::OutboundFailure,
+        peer_id: PeerId,
     ) {
-        self.pending_request_message
-            .remove(&request_id)
-            .expect("Message was not pending")
-            .send(Err(anyhow!(error)))
-            .expect("Direct messaging receiver was dropped");
+        if let Some(error_sender) = self.pending_request_message.remove(&request_id) {
+            let _ = error_sender.send(Err(NetworkError::Request { peer_id, error }));
+        }
     }
 
     pub(super) async fn handle_trade_offering_message(
         &mut self,
-        message: request_response::Message<TradeOffer, NoResponse>,
+        message: request_response::Message<TradeOfferRequest, NoResponse>,
         peer_id: PeerId,
     ) {
         match message {
@@ -118,45 +137,82 @@ impl EventLoop {
             request_response::Message::Request {
                 request, channel, ..
             } => {
-                self.swarm
+                if self.should_drop_request(peer_id) || self.has_too_many_offers(peer_id) {
+                    return;
+                }
+                if self
+                    .swarm
                     .behaviour_mut()
                     .trade_offering
                     .send_response(channel, NoResponse())
-                    .expect("Connection to peer was dropped");
-
-                self.inbound_trade_offers.insert((peer_id, request.clone()));
+                    .is_err()
+                {
+                    // The offerer will consider the offer failed, so it can
+                    // not be accepted
+                    self.send_event(Event::Error(NetworkError::Response { peer_id }))
+                        .await;
+                    return;
+                }
 
-                self.event_sender
-                    .send(Event::InboundTradeOffer {
-                        offered_file_name: request.offered_file_name,
-                        peer_id,
-                        requested_file_name: request.requested_file_name,
-                    })
-                    .await
-                    .expect("Event receiver was dropped");
+                let TradeOfferRequest {
+                    offer,
+                    offered_file_size,
+                } = request;
+                let trade_id = self.insert_trade(peer_id, TradeDirection::Inbound, &offer);
+                self.inbound_trade_offers.insert(
+                    trade_id,
+                    peer_id,
+                    offer.clone(),
+                    offered_file_size,
+                );
+
+                self.send_event(Event::InboundTradeOffer {
+                    trade_id,
+                    offered_file_name: offer.offered_file_name,
+                    offered_file_size,
+                    peer_id,
+                    requested_file_name: offer.requested_file_name,
+                    reputation: self.ledger.get(&peer_id),
+                })
+                .await;
             }
 
             // Another peer has received our trade offer
             request_response::Message::Response { request_id, .. } => {
-                if let Some(status_sender) = self.pending_trade_offer_request.remove(&request_id) {
-                    status_sender
-                        .send(Ok(()))
-                        .expect("Status sender was dropped");
+                if let Some((trade_id, trade_id_sender)) =
+                    self.pending_trade_offer_request.remove(&request_id)
+                {
+                    let _ = trade_id_sender.send(Ok(trade_id));
                 }
             }
         }
     }
 
-    #[allow(clippy::unused_self)]
+    /// Whether we already have as many trade offers awaiting our response
+    /// as we allow, either in total or from `peer_id`.
+    fn has_too_many_offers(&mut self, peer_id: PeerId) -> bool {
+        self.expire_inbound_offers();
+        let too_many = !self.inbound_trade_offers.has_room_for(peer_id);
+        if too_many {
+            tracing::debug!(%peer_id, "Dropping trade offer, too many offers are pending");
+        }
+        too_many
+    }
+
     pub(super) fn handle_trade_offering_outbound_failure(
         &mut self,
         error: request_response::OutboundFailure,
         request_id: request_response::OutboundRequestId,
+        peer_id: PeerId,
     ) {
-        if let Some(status_sender) = self.pending_trade_offer_request.remove(&request_id) {
-            status_sender
-                .send(Err(anyhow!(error)))
-                .expect("Status receiver was dropped");
+        if let Some((trade_id, trade_id_sender)) =
+            self.pending_trade_offer_request.remove(&request_id)
+        {
+            // The offer never arrived, so it can not be responded to
+            self.trades.remove(&trade_id);
+            self.outgoing_trade_offers
+                .retain(|_, (outgoing_trade_id, _, _)| *outgoing_trade_id != trade_id);
+            let _ = trade_id_sender.send(Err(NetworkError::Request { peer_id, error }));
         }
     }
 
@@ -170,51 +226,74 @@ impl EventLoop {
             request_response::Message::Request {
                 request, channel, ..
             } => {
+                if self.is_blocked(&peer_id) {
+                    return;
+                }
                 let offer = TradeOffer {
                     requested_file_name: request.requested_file_name.clone(),
                     offered_file_name: request.offered_file_name.clone(),
                 };
                 let entry = self.outgoing_trade_offers.remove(&(peer_id, offer));
-                let Some((offered_file_bytes, requested_file_path)) = entry else {
+                let Some((trade_id, offered_file_bytes, requested_file_path)) = entry else {
                     return;
                 };
 
-                self.event_sender
-                    .send(Event::InboundTradeResponse {
+                let Some(requested_file_bytes) = request.requested_file_bytes else {
+                    self.trades.remove(&trade_id);
+                    self.record_trade(peer_id, TradeOutcome::Declined);
+                    self.send_event(Event::InboundTradeResponse {
+                        trade_id,
                         peer_id,
                         offered_file_name: request.offered_file_name.clone(),
                         requested_file_name: request.requested_file_name.clone(),
-                        was_accepted: request.requested_file_bytes.is_some(),
+                        was_accepted: false,
                     })
-                    .await
-                    .expect("Event receiver was dropped");
-
-                let mut response: Option<Vec<u8>> = None;
-
-                if let Some(requested_file_bytes) = request.requested_file_bytes {
-                    if let Some(parent_directory) = requested_file_path.parent() {
-                        tokio::fs::create_dir_all(parent_directory)
-                            .await
-                            .expect("Failed to create parent directories");
+                    .await;
+                    if self
+                        .swarm
+                        .behaviour_mut()
+                        .trade_response
+                        .send_response(
+                            channel,
+                            TradeResponseResponse {
+                                offered_file_name: request.offered_file_name,
+                                requested_file_name: request.requested_file_name,
+                                offered_file_bytes: None,
+                                receipt: None,
+                                transfer: None,
+                            },
+                        )
+                        .is_err()
+                    {
+                        self.send_event(Event::Error(NetworkError::Response { peer_id }))
+                            .await;
                     }
-                    tokio::fs::write(requested_file_path, requested_file_bytes)
-                        .await
-                        .expect("Failed to write to file system");
-                    response = Some(offered_file_bytes);
-                }
+                    return;
+                };
 
-                self.swarm
-                    .behaviour_mut()
-                    .trade_response
-                    .send_response(
+                // Only hand over our file once theirs has been stored, see
+                // `handle_trade_file_stored`
+                self.set_trade_status(trade_id, TradeStatus::Accepted);
+                let requested_file_hash = receipt::hash(&requested_file_bytes);
+                let recipient_username = request.username;
+                let storage = self.storage.clone();
+                self.pending_trade_file_writes.push(Box::pin(async move {
+                    let result = storage
+                        .write(requested_file_path.clone(), requested_file_bytes)
+                        .await;
+                    StoredTradeFile {
+                        trade_id,
+                        peer_id,
                         channel,
-                        TradeResponseResponse {
-                            offered_file_name: request.offered_file_name,
-                            requested_file_name: request.requested_file_name,
-                            offered_file_bytes: response,
-                        },
-                    )
-                    .expect("Connection to peer was dropped");
+                        offered_file_name: request.offered_file_name,
+                        requested_file_name: request.requested_file_name,
+                        offered_file_bytes,
+                        requested_file_path,
+                        requested_file_hash,
+                        recipient_username,
+                        result,
+                    }
+                }));
             }
 
             // We responded to another peer's trade, and they have delivered
@@ -223,27 +302,364 @@ impl EventLoop {
                 response,
                 request_id,
             } => {
-                if let Some(offered_bytes_sender) =
-                    self.pending_trade_response_response.remove(&request_id)
-                {
-                    offered_bytes_sender
-                        .send(Ok(response.offered_file_bytes))
-                        .expect("Offered bytes receiver was dropped");
+                self.handle_offered_file(response, request_id, peer_id)
+                    .await;
+            }
+        }
+    }
+
+    async fn handle_offered_file(
+        &mut self,
+        mut response: TradeResponseResponse,
+        request_id: request_response::OutboundRequestId,
+        peer_id: PeerId,
+    ) {
+        let Some(AcceptedTrade {
+            trade_id,
+            offered_file_path,
+            offered_file_size,
+            requested_file_hash,
+            result_sender,
+        }) = self.pending_trade_response_response.remove(&request_id)
+        else {
+            return;
+        };
+        self.trades.remove(&trade_id);
+        let Some(offered_file_bytes) = response.offered_file_bytes.take() else {
+            self.record_trade(peer_id, TradeOutcome::Failed);
+            self.report_accepted_trade(result_sender, Err(NetworkError::FileWithheld { peer_id }))
+                .await;
+            return;
+        };
+        if offered_file_size.is_some_and(|size| offered_file_bytes.len() as u64 > size) {
+            self.record_trade(peer_id, TradeOutcome::VerificationFailed);
+            self.report_accepted_trade(result_sender, Err(NetworkError::OversizedFile { peer_id }))
+                .await;
+            return;
+        }
+
+        // A missing receipt is not an error, as the offerer may not support
+        // them yet
+        if let Some(signed) = response.receipt.take() {
+            match self.countersign_receipt(
+                signed,
+                peer_id,
+                &response,
+                &receipt::hash(&offered_file_bytes),
+                &requested_file_hash,
+            ) {
+                Some(signed) => {
+                    self.save_receipt(trade_id, peer_id, &signed);
+                    self.swarm
+                        .behaviour_mut()
+                        .trade_receipt
+                        .send_request(&peer_id, signed);
                 }
+                None => {
+                    self.send_event(Event::Error(NetworkError::InvalidReceipt { peer_id }))
+                        .await;
+                }
+            }
+        }
+
+        // The file is stored even if whoever accepted the trade has stopped
+        // waiting, as it was paid for with ours
+        let written = self
+            .storage
+            .queue_write(offered_file_path.clone(), offered_file_bytes);
+        self.pending_offered_file_writes.push(Box::pin(async move {
+            StoredOfferedFile {
+                trade_id,
+                peer_id,
+                offered_file_name: response.offered_file_name,
+                requested_file_name: response.requested_file_name,
+                offered_file_path,
+                result_sender,
+                result: written.await,
+            }
+        }));
+    }
+
+    pub(super) async fn handle_offered_file_stored(&mut self, stored: StoredOfferedFile) {
+        let StoredOfferedFile {
+            trade_id,
+            peer_id,
+            offered_file_name,
+            requested_file_name,
+            offered_file_path,
+            result_sender,
+            result,
+        } = stored;
+
+        // Failing to store their file is no fault of theirs, so the trade
+        // only counts towards their reputation once it has been stored
+        if result.is_ok() {
+            self.record_trade(peer_id, TradeOutcome::Completed);
+            self.send_event(Event::TradeCompleted {
+                trade_id,
+                peer_id,
+                direction: TradeDirection::Inbound,
+                offered_file_name,
+                requested_file_name,
+                received_file_path: offered_file_path,
+            })
+            .await;
+        }
+        self.report_accepted_trade(result_sender, result.map_err(NetworkError::from))
+            .await;
+    }
+
+    /// Hands the outcome of a trade we accepted to whoever accepted it, or
+    /// emits it as an error event should they have stopped waiting.
+    async fn report_accepted_trade(
+        &mut self,
+        result_sender: oneshot::Sender<NetworkResult<()>>,
+        result: NetworkResult<()>,
+    ) {
+        if let Err(Err(error)) = result_sender.send(result) {
+            self.send_event(Event::Error(error)).await;
+        }
+    }
+
+    /// Checks the receipt the offerer of a trade we accepted signed against
+    /// what was actually traded, and adds our signature to it.
+    fn countersign_receipt(
+        &self,
+        mut signed: SignedReceipt,
+        peer_id: PeerId,
+        response: &TradeResponseResponse,
+        offered_file_hash: &str,
+        requested_file_hash: &str,
+    ) -> Option<SignedReceipt> {
+        let Receipt {
+            offerer, recipient, ..
+        } = signed.receipt().ok()?;
+        let is_accurate = offerer.peer_id == peer_id
+            && offerer.file_name == response.offered_file_name
+            && offerer.file_hash == offered_file_hash
+            && recipient.peer_id == *self.swarm.local_peer_id()
+            && recipient.username == self.username
+            && recipient.file_name == response.requested_file_name
+            && recipient.file_hash == requested_file_hash;
+        if !is_accurate || !signed.is_signed_by(peer_id) {
+            return None;
+        }
+        signed
+            .countersign(&self.keypair)
+            .inspect_err(|error| tracing::warn!(%peer_id, "Failed to sign receipt: {error}"))
+            .ok()?;
+        Some(signed)
+    }
+
+    pub(super) async fn handle_trade_file_stored(&mut self, stored: StoredTradeFile) {
+        let StoredTradeFile {
+            trade_id,
+            peer_id,
+            channel,
+            offered_file_name,
+            requested_file_name,
+            offered_file_bytes,
+            requested_file_path,
+            requested_file_hash,
+            recipient_username,
+            result,
+        } = stored;
+
+        self.trades.remove(&trade_id);
+        let (offered_file_bytes, receipt) = match result {
+            Ok(()) => {
+                self.record_trade(peer_id, TradeOutcome::Completed);
+                self.send_event(Event::InboundTradeResponse {
+                    trade_id,
+                    peer_id,
+                    offered_file_name: offered_file_name.clone(),
+                    requested_file_name: requested_file_name.clone(),
+                    was_accepted: true,
+                })
+                .await;
+                self.send_event(Event::TradeCompleted {
+                    trade_id,
+                    peer_id,
+                    direction: TradeDirection::Outbound,
+                    offered_file_name: offered_file_name.clone(),
+                    requested_file_name: requested_file_name.clone(),
+                    received_file_path: requested_file_path,
+                })
+                .await;
+                // Recipients which don't give a username don't support
+                // receipts
+                let receipt = recipient_username.and_then(|username| {
+                    let receipt = Receipt {
+                        offerer: TradeParty {
+                            peer_id: *self.swarm.local_peer_id(),
+                            username: self.username.clone(),
+                            file_name: offered_file_name.clone(),
+                            file_hash: receipt::hash(&offered_file_bytes),
+                        },
+                        recipient: TradeParty {
+                            peer_id,
+                            username,
+                            file_name: requested_file_name.clone(),
+                            file_hash: requested_file_hash,
+                        },
+                        completed_at: unix_time(),
+                    };
+                    self.sign_receipt(trade_id, peer_id, &receipt)
+                });
+                (Some(offered_file_bytes), receipt)
             }
+            Err(error) => {
+                self.send_event(Event::Error(error.into())).await;
+                (None, None)
+            }
+        };
+
+        // Their file has already arrived, only ours is left to send
+        let transfer = offered_file_bytes
+            .is_some()
+            .then(|| self.transfers.start(trade_id, peer_id, Some(0)));
+        if self
+            .swarm
+            .behaviour_mut()
+            .trade_response
+            .send_response(
+                channel,
+                TradeResponseResponse {
+                    offered_file_name,
+                    requested_file_name,
+                    offered_file_bytes,
+                    receipt,
+                    transfer,
+                },
+            )
+            .is_err()
+        {
+            self.send_event(Event::Error(NetworkError::Response { peer_id }))
+                .await;
         }
     }
 
-    pub(super) fn handle_trade_response_outbound_failure(
+    pub(super) async fn handle_trade_response_outbound_failure(
         &mut self,
         request_id: request_response::OutboundRequestId,
         error: request_response::OutboundFailure,
+        peer_id: PeerId,
+    ) {
+        if let Some(accepted_trade) = self.pending_trade_response_response.remove(&request_id) {
+            self.trades.remove(&accepted_trade.trade_id);
+            self.record_trade(peer_id, TradeOutcome::Failed);
+            self.report_accepted_trade(
+                accepted_trade.result_sender,
+                Err(NetworkError::Request { peer_id, error }),
+            )
+            .await;
+        }
+    }
+
+    pub(super) async fn handle_reputation_message(
+        &mut self,
+        message: request_response::Message<ReputationRequest, ReputationResponse>,
+        peer_id: PeerId,
     ) {
-        if let Some(offered_bytes_sender) = self.pending_trade_response_response.remove(&request_id)
+        match message {
+            // Another peer wants to know how our trades with someone went
+            request_response::Message::Request {
+                request, channel, ..
+            } => {
+                if self.should_drop_request(peer_id) {
+                    return;
+                }
+                let subject = request.subject;
+                let attestation = self
+                    .limits
+                    .share_reputation
+                    .then(|| {
+                        Attestation::sign(&self.keypair, subject, self.ledger.get(&subject))
+                            .inspect_err(|error| {
+                                tracing::warn!(%subject, "Failed to sign attestation: {error}");
+                            })
+                            .ok()
+                    })
+                    .flatten();
+                if self
+                    .swarm
+                    .behaviour_mut()
+                    .reputation
+                    .send_response(channel, ReputationResponse(attestation))
+                    .is_err()
+                {
+                    self.send_event(Event::Error(NetworkError::Response { peer_id }))
+                        .await;
+                }
+            }
+            request_response::Message::Response {
+                request_id,
+                response,
+            } => {
+                let Some((subject, attestation_sender)) =
+                    self.pending_reputation_request.remove(&request_id)
+                else {
+                    return;
+                };
+                let result = match response.0 {
+                    Some(attestation) => attestation
+                        .verify(peer_id, subject)
+                        .ok_or(NetworkError::InvalidAttestation { peer_id }),
+                    None => Err(NetworkError::ReputationNotShared { peer_id }),
+                };
+                let _ = attestation_sender.send(result);
+            }
+        }
+    }
+
+    pub(super) async fn handle_trade_receipt_message(
+        &mut self,
+        message: request_response::Message<SignedReceipt, NoResponse>,
+        peer_id: PeerId,
+    ) {
+        // The recipient of a trade we offered has countersigned its receipt
+        let request_response::Message::Request {
+            request: signed,
+            channel,
+            ..
+        } = message
+        else {
+            return;
+        };
+        if self.should_drop_request(peer_id) {
+            return;
+        }
+        if self
+            .swarm
+            .behaviour_mut()
+            .trade_receipt
+            .send_response(channel, NoResponse())
+            .is_err()
         {
-            offered_bytes_sender
-                .send(Err(anyhow::Error::from(error)))
-                .expect("Offered bytes receiver was dropped");
+            self.send_event(Event::Error(NetworkError::Response { peer_id }))
+                .await;
+        }
+
+        let trade_id = self.pending_receipts.take(signed.signed_text(), peer_id);
+        match trade_id {
+            Some(trade_id) if signed.verify().is_ok() => {
+                self.save_receipt(trade_id, peer_id, &signed);
+            }
+            _ => {
+                self.send_event(Event::Error(NetworkError::InvalidReceipt { peer_id }))
+                    .await;
+            }
+        }
+    }
+
+    pub(super) fn handle_reputation_outbound_failure(
+        &mut self,
+        request_id: request_response::OutboundRequestId,
+        error: request_response::OutboundFailure,
+        peer_id: PeerId,
+    ) {
+        if let Some((_, attestation_sender)) = self.pending_reputation_request.remove(&request_id) {
+            let _ = attestation_sender.send(Err(NetworkError::Request { peer_id, error }));
         }
     }
 
@@ -276,20 +692,56 @@ impl EventLoop {
         }
     }
 
-    #[allow(clippy::unused_self)]
+    /// Reports whether a chat message should be forwarded to the rest of the
+    /// mesh, and passes it on if it is valid and its author hasn't been muted.
     pub(super) async fn handle_gossipsub_message(
         &mut self,
-        message: &gossipsub::Message,
-        peer_id: PeerId,
+        message: gossipsub::Message,
+        message_id: &gossipsub::MessageId,
+        propagation_source: PeerId,
     ) {
-        let message = String::from_utf8_lossy(&message.data).into_owned();
-        self.event_sender
-            .send(Event::InboundChat { peer_id, message })
-            .await
-            .expect("Event receiver was dropped");
+        // Messages are signed, so always name their author
+        let author = message.source.unwrap_or(propagation_source);
+        let validated = if self.is_blocked(&propagation_source) {
+            Err(gossipsub::MessageAcceptance::Reject)
+        } else if self.is_blocked(&author) {
+            // Only we have blocked the author, so whoever forwarded their
+            // message did nothing wrong
+            Err(gossipsub::MessageAcceptance::Ignore)
+        } else {
+            self.chat_validator.validate(&message, message_id)
+        };
+        let (acceptance, message) = match validated {
+            Ok(message) => (gossipsub::MessageAcceptance::Accept, Some(message)),
+            Err(acceptance) => {
+                tracing::debug!(
+                    %author,
+                    %propagation_source,
+                    ?acceptance,
+                    "Chat message not accepted"
+                );
+                (acceptance, None)
+            }
+        };
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .report_message_validation_result(message_id, &propagation_source, acceptance);
+        let Some(message) = message else {
+            return;
+        };
+        if self.peer_list.muted.contains(&author) {
+            return;
+        }
+
+        self.send_event(Event::InboundChat {
+            peer_id: author,
+            message,
+        })
+        .await;
     }
 
-    pub(super) fn handle_rendezvous_discovered(
+    pub(super) async fn handle_rendezvous_discovered(
         &mut self,
         registrations: Vec<rendezvous::Registration>,
         cookie: rendezvous::Cookie,
@@ -317,7 +769,10 @@ impl EventLoop {
                         address.clone().with(p2p_suffix)
                     };
 
-                self.swarm.dial(address_with_p2p).unwrap();
+                if let Err(error) = self.swarm.dial(address_with_p2p) {
+                    self.send_event(Event::Error(NetworkError::Dial { peer_id, error }))
+                        .await;
+                }
 
                 self.swarm
                     .behaviour_mut()
@@ -332,47 +787,173 @@ impl EventLoop {
         }
     }
 
-    pub(super) fn handle_connected_to_rendezvous_server(&mut self) {
+    pub(super) fn handle_connected_to_rendezvous_server(&mut self, rendezvous_peer_id: PeerId) {
+        self.rendezvous_backoff = INITIAL_RENDEZVOUS_BACKOFF;
+        self.rendezvous_redial_deadline = None;
+
         self.swarm.behaviour_mut().rendezvous.discover(
             Some(self.rendezvous_namespace.clone()),
             None,
             None,
-            self.rendezvous_peer_id.unwrap(),
+            rendezvous_peer_id,
         );
     }
 
-    pub(super) fn handle_identify_received(
-        &mut self,
-        info: identify::Info,
-    ) {
-        self.swarm.add_external_address(info.observed_addr);
+    pub(super) fn handle_discover_tick(&mut self) {
+        let Some(rendezvous_peer_id) = self.rendezvous_peer_id else {
+            return;
+        };
+        self.swarm.behaviour_mut().rendezvous.discover(
+            Some(self.rendezvous_namespace.clone()),
+            self.cookie.clone(),
+            None,
+            rendezvous_peer_id,
+        );
+    }
+
+    pub(super) async fn handle_disconnected_from_rendezvous_server(&mut self) {
+        // Other behaviours may also fail to reach the rendezvous server, only
+        // the first failure should schedule a reconnection attempt.
+        if self.rendezvous_redial_deadline.is_some() {
+            return;
+        }
+
+        // Our registration can not be refreshed without a connection, it
+        // will instead be made again once we have reconnected.
+        self.rendezvous_register_deadline = None;
+
+        let retry_in = self.next_rendezvous_backoff();
+        self.rendezvous_redial_deadline = Some(Instant::now() + retry_in);
+        self.set_rendezvous_status(RendezvousStatus::Disconnected { retry_in })
+            .await;
+    }
+
+    pub(super) async fn handle_rendezvous_redial(&mut self) {
+        self.rendezvous_redial_deadline = None;
+        let Some(rendezvous_address) = self.rendezvous_address.clone() else {
+            return;
+        };
+        if self
+            .rendezvous_peer_id
+            .is_some_and(|peer_id| self.swarm.is_connected(&peer_id))
+        {
+            return;
+        }
+
+        tracing::info!(%rendezvous_address, "Reconnecting to rendezvous point");
+        self.set_rendezvous_status(RendezvousStatus::Connecting)
+            .await;
+        if let Err(error) = self.swarm.dial(rendezvous_address) {
+            tracing::warn!("Failed to dial rendezvous point: {error}");
+            self.handle_disconnected_from_rendezvous_server().await;
+        }
+    }
 
+    pub(super) fn handle_rendezvous_register(&mut self) {
+        self.rendezvous_register_deadline = None;
         let Some(rendezvous_peer_id) = self.rendezvous_peer_id else {
             return;
         };
 
-        // once `/identify` did its job, we know our external address and can
-        // register. This needs to be done explicitly for this case, as it's a
-        // local address.
         if let Err(error) = self.swarm.behaviour_mut().rendezvous.register(
             self.rendezvous_namespace.clone(),
             rendezvous_peer_id,
             None,
         ) {
-            tracing::error!("Failed to register: {error}");
-        } else {
-            tracing::info!("Connection established with rendezvous point");
+            // We don't yet know our external address, try again later.
+            tracing::warn!("Failed to register with rendezvous point: {error}");
+            let retry_in = self.next_rendezvous_backoff();
+            self.rendezvous_register_deadline = Some(Instant::now() + retry_in);
+        }
+    }
+
+    pub(super) async fn handle_rendezvous_registered(&mut self, ttl: rendezvous::Ttl) {
+        tracing::info!("Registered with rendezvous point for {ttl} seconds");
+        self.rendezvous_backoff = INITIAL_RENDEZVOUS_BACKOFF;
+
+        // Refresh the registration once three quarters of its lifetime has
+        // passed, leaving time to retry should the refresh fail.
+        let refresh_in = Duration::from_secs(ttl) * 3 / 4;
+        self.rendezvous_register_deadline = Some(Instant::now() + refresh_in);
+
+        self.set_rendezvous_status(RendezvousStatus::Registered { ttl })
+            .await;
+    }
+
+    pub(super) async fn handle_rendezvous_register_failed(&mut self, error: rendezvous::ErrorCode) {
+        tracing::error!("Rendezvous point rejected registration: {error:?}");
+
+        let retry_in = self.next_rendezvous_backoff();
+        self.rendezvous_register_deadline = Some(Instant::now() + retry_in);
+        self.set_rendezvous_status(RendezvousStatus::RegistrationFailed { error, retry_in })
+            .await;
+    }
+
+    pub(super) fn handle_identify_received(&mut self, info: identify::Info, peer_id: PeerId) {
+        self.swarm.add_external_address(info.observed_addr);
+
+        // Peers serving the DHT can be added to our routing table using the
+        // addresses they are listening on.
+        if info.protocols.contains(&kad::PROTOCOL_NAME) {
+            for address in info.listen_addrs {
+                self.swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .add_address(&peer_id, address);
+            }
+        }
+
+        if Some(peer_id) != self.rendezvous_peer_id
+            || matches!(self.rendezvous_status, RendezvousStatus::Registered { .. })
+        {
+            return;
+        }
+
+        // once `/identify` did its job, we know our external address and can
+        // register. This needs to be done explicitly for this case, as it's a
+        // local address.
+        tracing::info!("Connection established with rendezvous point");
+        self.handle_rendezvous_register();
+    }
+
+    pub(super) fn handle_bootstrap_tick(&mut self) {
+        if let Err(error) = self.swarm.behaviour_mut().kademlia.bootstrap() {
+            tracing::debug!("Unable to bootstrap DHT: {error}");
+        }
+    }
+
+    pub(super) fn handle_random_walk_tick(&mut self) {
+        // Looking up a random peer ID fills our routing table with peers
+        // spread across the key space.
+        self.swarm
+            .behaviour_mut()
+            .kademlia
+            .get_closest_peers(PeerId::random());
+    }
+
+    /// Returns the delay to wait before the next attempt to reach the
+    /// rendezvous server, doubling the delay for the attempt after.
+    fn next_rendezvous_backoff(&mut self) -> Duration {
+        let backoff = self.rendezvous_backoff;
+        self.rendezvous_backoff = (backoff * 2).min(MAX_RENDEZVOUS_BACKOFF);
+        backoff
+    }
+
+    async fn set_rendezvous_status(&mut self, status: RendezvousStatus) {
+        if self.rendezvous_status == status {
+            return;
         }
+        self.rendezvous_status = status.clone();
+        self.send_event(Event::RendezvousStatusChanged(status))
+            .await;
     }
 
     pub(super) async fn handle_kademlia_routing_updated(&mut self) {
         if !self.has_registered_username {
-            self.event_sender
-                .send(Event::RegistrationRequest {
-                    username: self.username.clone(),
-                })
-                .await
-                .expect("Event receiver was dropped");
+            self.send_event(Event::RegistrationRequest {
+                username: self.username.clone(),
+            })
+            .await;
             self.has_registered_username = true;
         }
     }
@@ -0,0 +1,207 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    time::Duration,
+};
+
+use libp2p::gossipsub::{Message, MessageAcceptance, MessageId};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use super::unix_time;
+
+/// A chat message as published to the topic. The time it was sent is signed
+/// along with the message, so that it can't be replayed long after. Unknown
+/// fields are ignored, so that newer versions can add to it.
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    message: String,
+    /// Seconds since the Unix epoch.
+    sent_at: u64,
+}
+
+/// Encodes `message` for publishing.
+pub(super) fn encode(message: &str) -> Vec<u8> {
+    serde_json::to_vec(&Envelope {
+        message: message.to_owned(),
+        sent_at: unix_time(),
+    })
+    .expect("Chat envelopes serialize as JSON")
+}
+
+/// Checks the chat messages received from the mesh before they are passed on
+/// to the user or forwarded to other peers.
+pub(super) struct ChatValidator {
+    max_message_size: usize,
+    max_message_age: Duration,
+    /// The messages seen recently, oldest first. They are remembered for
+    /// twice `max_message_age`, as a message may claim to have been sent up
+    /// to `max_message_age` in the future, after which it is turned away by
+    /// the time it was sent instead.
+    seen: VecDeque<(Instant, MessageId)>,
+    seen_ids: HashSet<MessageId>,
+}
+
+impl ChatValidator {
+    pub(super) fn new(max_message_size: usize, max_message_age: Duration) -> Self {
+        Self {
+            max_message_size,
+            max_message_age,
+            seen: VecDeque::new(),
+            seen_ids: HashSet::new(),
+        }
+    }
+
+    /// The text of `message`, or how gossipsub should treat it if it
+    /// shouldn't be shown.
+    ///
+    /// Malformed messages are rejected, which counts against the peer who
+    /// forwarded them. Stale and repeated messages are ignored instead, as
+    /// they could have been sent in good faith by a peer with a skewed clock
+    /// or from a different path through the mesh.
+    pub(super) fn validate(
+        &mut self,
+        message: &Message,
+        message_id: &MessageId,
+    ) -> Result<String, MessageAcceptance> {
+        if message.source.is_none()
+            || message.sequence_number.is_none()
+            || message.data.len() > self.max_message_size
+        {
+            return Err(MessageAcceptance::Reject);
+        }
+        let envelope: Envelope =
+            serde_json::from_slice(&message.data).map_err(|_| MessageAcceptance::Reject)?;
+
+        let max_age = self.max_message_age.as_secs();
+        let now = unix_time();
+        if envelope.sent_at.saturating_add(max_age) < now
+            || envelope.sent_at > now.saturating_add(max_age)
+        {
+            return Err(MessageAcceptance::Ignore);
+        }
+
+        let received = Instant::now();
+        while let Some((seen_at, _)) = self.seen.front() {
+            if received.duration_since(*seen_at) < self.max_message_age * 2 {
+                break;
+            }
+            if let Some((_, id)) = self.seen.pop_front() {
+                self.seen_ids.remove(&id);
+            }
+        }
+        if !self.seen_ids.insert(message_id.clone()) {
+            return Err(MessageAcceptance::Ignore);
+        }
+        self.seen.push_back((received, message_id.clone()));
+
+        Ok(envelope.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::{gossipsub::TopicHash, PeerId};
+
+    use super::*;
+
+    const MAX_MESSAGE_AGE: Duration = Duration::from_mins(5);
+
+    fn message(text: &str, sent_at: u64) -> Message {
+        Message {
+            source: Some(PeerId::random()),
+            data: serde_json::to_vec(&Envelope {
+                message: text.to_owned(),
+                sent_at,
+            })
+            .unwrap(),
+            sequence_number: Some(1),
+            topic: TopicHash::from_raw("chat-room/2"),
+        }
+    }
+
+    fn message_id(id: &str) -> MessageId {
+        MessageId::from(id)
+    }
+
+    #[test]
+    fn messages_outside_the_age_window_are_ignored() {
+        let mut validator = ChatValidator::new(1024, MAX_MESSAGE_AGE);
+        let now = unix_time();
+        let max_age = MAX_MESSAGE_AGE.as_secs();
+
+        assert!(matches!(
+            validator.validate(&message("hi", now), &message_id("1")),
+            Ok(text) if text == "hi"
+        ));
+        assert!(matches!(
+            validator.validate(&message("old", now - max_age + 10), &message_id("2")),
+            Ok(text) if text == "old"
+        ));
+        assert!(matches!(
+            validator.validate(&message("early", now + max_age - 10), &message_id("3")),
+            Ok(text) if text == "early"
+        ));
+        assert!(matches!(
+            validator.validate(&message("stale", now - max_age - 10), &message_id("4")),
+            Err(MessageAcceptance::Ignore)
+        ));
+        assert!(matches!(
+            validator.validate(&message("future", now + max_age + 10), &message_id("5")),
+            Err(MessageAcceptance::Ignore)
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn repeated_messages_are_ignored_within_the_replay_window() {
+        let mut validator = ChatValidator::new(1024, MAX_MESSAGE_AGE);
+        let message = message("hi", unix_time());
+
+        assert!(validator.validate(&message, &message_id("1")).is_ok());
+        assert!(matches!(
+            validator.validate(&message, &message_id("1")),
+            Err(MessageAcceptance::Ignore)
+        ));
+        assert!(validator.validate(&message, &message_id("2")).is_ok());
+
+        tokio::time::advance(MAX_MESSAGE_AGE).await;
+        assert!(matches!(
+            validator.validate(&message, &message_id("1")),
+            Err(MessageAcceptance::Ignore)
+        ));
+
+        // It is forgotten once it would be turned away by the time it was sent
+        tokio::time::advance(MAX_MESSAGE_AGE).await;
+        assert!(validator.validate(&message, &message_id("1")).is_ok());
+        assert_eq!(validator.seen_ids.len(), 1);
+    }
+
+    #[test]
+    fn malformed_messages_are_rejected_but_unknown_fields_are_not() {
+        let mut validator = ChatValidator::new(64, MAX_MESSAGE_AGE);
+        let now = unix_time();
+
+        let mut unsigned = message("hi", now);
+        unsigned.source = None;
+        let mut raw_text = message("hi", now);
+        raw_text.data = b"hi".to_vec();
+
+        for (id, message) in [unsigned, raw_text, message(&"a".repeat(64), now)]
+            .iter()
+            .enumerate()
+        {
+            assert!(matches!(
+                validator.validate(message, &message_id(&id.to_string())),
+                Err(MessageAcceptance::Reject)
+            ));
+        }
+
+        // Fields added by newer versions are ignored
+        let mut unknown_field = message("hi", now);
+        unknown_field.data =
+            format!(r#"{{"message":"hi","sent_at":{now},"extra":1}}"#).into_bytes();
+        assert_eq!(
+            validator.validate(&unknown_field, &message_id("3")).ok(),
+            Some("hi".to_owned())
+        );
+    }
+}
@@ -1,9 +1,9 @@
 use std::path::PathBuf;
 
 use futures::channel::oneshot;
-use libp2p::{gossipsub, kad, PeerId};
+use libp2p::{gossipsub, PeerId};
 
-use super::EventLoop;
+use super::{Attestation, EventLoop, NetworkResult, PeerList, Reputation, Trade, TradeId};
 
 /// Interprocess communication 'commands' sent from the main thread to the
 /// network thread.
@@ -11,7 +11,7 @@ use super::EventLoop;
 pub(crate) enum Command {
     RegisterUsername {
         username: String,
-        status_sender: oneshot::Sender<Result<(), kad::PutRecordError>>,
+        status_sender: oneshot::Sender<NetworkResult<()>>,
     },
     FindPeerId {
         username: String,
@@ -19,7 +19,7 @@ pub(crate) enum Command {
     },
     FindPeerUsername {
         peer_id: PeerId,
-        username_sender: oneshot::Sender<Result<String, anyhow::Error>>,
+        username_sender: oneshot::Sender<NetworkResult<String>>,
     },
     MakeTradeOffer {
         offered_file_name: String,
@@ -27,14 +27,16 @@ pub(crate) enum Command {
         peer_id: PeerId,
         requested_file_name: String,
         requested_file_path: PathBuf,
-        error_sender: oneshot::Sender<Result<(), anyhow::Error>>,
+        trade_id_sender: oneshot::Sender<NetworkResult<TradeId>>,
     },
     RespondTrade {
-        peer_id: PeerId,
-        requested_file_name: String,
-        offered_file_name: String,
-        requested_file_bytes: Option<Vec<u8>>,
-        offered_bytes_sender: Option<oneshot::Sender<Result<Option<Vec<u8>>, anyhow::Error>>>,
+        trade_id: TradeId,
+        /// `None` declines the trade
+        acceptance: Option<TradeAcceptance>,
+        result_sender: oneshot::Sender<NetworkResult<()>>,
+    },
+    ListTrades {
+        trades_sender: oneshot::Sender<Vec<Trade>>,
     },
     SendChatMessage {
         message: String,
@@ -43,12 +45,44 @@ pub(crate) enum Command {
     DirectMessage {
         peer_id: PeerId,
         message: String,
-        error_sender: oneshot::Sender<Result<(), anyhow::Error>>,
+        error_sender: oneshot::Sender<NetworkResult<()>>,
+    },
+    BlockPeer {
+        peer_id: PeerId,
+        /// `false` unblocks the peer
+        blocked: bool,
+        result_sender: oneshot::Sender<NetworkResult<()>>,
+    },
+    MutePeer {
+        peer_id: PeerId,
+        /// `false` unmutes the peer
+        muted: bool,
+        result_sender: oneshot::Sender<NetworkResult<()>>,
+    },
+    GetPeerList {
+        peer_list_sender: oneshot::Sender<PeerList>,
+    },
+    GetReputation {
+        peer_id: PeerId,
+        reputation_sender: oneshot::Sender<Reputation>,
+    },
+    QueryReputation {
+        /// The peer asked
+        peer_id: PeerId,
+        subject: PeerId,
+        attestation_sender: oneshot::Sender<NetworkResult<Attestation>>,
     },
 }
 
+/// Our side of an accepted trade, and where to store the other side.
+#[derive(Debug)]
+pub(crate) struct TradeAcceptance {
+    pub requested_file_bytes: Vec<u8>,
+    pub offered_file_path: PathBuf,
+}
+
 impl EventLoop {
-    pub fn handle_command(&mut self, command: Command) {
+    pub(super) fn handle_command(&mut self, command: Command) {
         match command {
             Command::RegisterUsername {
                 username,
@@ -68,28 +102,23 @@ impl EventLoop {
                 peer_id,
                 requested_file_name,
                 requested_file_path,
-                error_sender,
+                trade_id_sender,
             } => self.handle_make_trade_offer(
                 offered_file_name,
                 offered_file_bytes,
                 peer_id,
                 requested_file_name,
                 requested_file_path,
-                error_sender,
+                trade_id_sender,
             ),
             Command::RespondTrade {
-                peer_id,
-                requested_file_name,
-                offered_file_name,
-                requested_file_bytes,
-                offered_bytes_sender,
-            } => self.handle_respond_trade(
-                peer_id,
-                requested_file_name,
-                offered_file_name,
-                requested_file_bytes,
-                offered_bytes_sender,
-            ),
+                trade_id,
+                acceptance,
+                result_sender,
+            } => self.handle_respond_trade(trade_id, acceptance, result_sender),
+            Command::ListTrades { trades_sender } => {
+                let _ = trades_sender.send(self.trades.values().cloned().collect());
+            }
             Command::SendChatMessage {
                 message,
                 status_sender,
@@ -101,6 +130,30 @@ impl EventLoop {
             } => {
                 self.handle_direct_message(&peer_id, message, error_sender);
             }
+            Command::BlockPeer {
+                peer_id,
+                blocked,
+                result_sender,
+            } => self.handle_block_peer(peer_id, blocked, result_sender),
+            Command::MutePeer {
+                peer_id,
+                muted,
+                result_sender,
+            } => self.handle_mute_peer(peer_id, muted, result_sender),
+            Command::GetPeerList { peer_list_sender } => {
+                let _ = peer_list_sender.send(self.peer_list.clone());
+            }
+            Command::GetReputation {
+                peer_id,
+                reputation_sender,
+            } => {
+                let _ = reputation_sender.send(self.ledger.get(&peer_id));
+            }
+            Command::QueryReputation {
+                peer_id,
+                subject,
+                attestation_sender,
+            } => self.handle_query_reputation(peer_id, subject, attestation_sender),
         }
     }
 }
@@ -1,11 +1,14 @@
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf};
 
-use anyhow::anyhow;
 use futures::channel::oneshot;
 use libp2p::{gossipsub, kad, PeerId};
 
-use super::{DirectMessage, EventLoop, TradeResponse};
-use crate::network::TradeOffer;
+use super::{
+    chat, command::TradeAcceptance, inbound_offers::InboundOffer, receipt, AcceptedTrade,
+    Attestation, DirectMessage, EventLoop, NetworkResult, TradeDirection, TradeId, TradeResponse,
+    TradeStatus,
+};
+use crate::network::{peer_list, NetworkError, ReputationRequest, TradeOffer, TradeOfferRequest};
 
 /// Handler functions for Commands from the main thread. These perform outbound
 /// network requests/queries as instructed by the user.
@@ -13,7 +16,7 @@ impl EventLoop {
     pub(super) fn handle_register_username(
         &mut self,
         username: &str,
-        status_sender: oneshot::Sender<Result<(), kad::PutRecordError>>,
+        status_sender: oneshot::Sender<NetworkResult<()>>,
     ) {
         let peer_id_bytes = self.swarm.local_peer_id().to_bytes();
         let username_bytes = username.to_lowercase().into_bytes();
@@ -24,15 +27,18 @@ impl EventLoop {
             publisher: None,
             expires: None,
         };
-        let query_id = self
+        let query_id = match self
             .swarm
             .behaviour_mut()
             .kademlia
             .put_record(record, kad::Quorum::One)
-            .expect("Failed to store record locally");
-
-        self.pending_register_username
-            .insert(query_id, status_sender);
+        {
+            Ok(query_id) => query_id,
+            Err(error) => {
+                let _ = status_sender.send(Err(error.into()));
+                return;
+            }
+        };
 
         let record = kad::Record {
             key: kad::RecordKey::new(&peer_id_bytes),
@@ -40,11 +46,18 @@ impl EventLoop {
             publisher: None,
             expires: None,
         };
-        self.swarm
+        if let Err(error) = self
+            .swarm
             .behaviour_mut()
             .kademlia
             .put_record(record, kad::Quorum::One)
-            .expect("Failed to store record locally");
+        {
+            let _ = status_sender.send(Err(error.into()));
+            return;
+        }
+
+        self.pending_register_username
+            .insert(query_id, status_sender);
     }
 
     pub(super) fn handle_find_peer_id(
@@ -61,7 +74,7 @@ impl EventLoop {
     pub(super) fn handle_find_peer_username(
         &mut self,
         peer_id: PeerId,
-        username_sender: oneshot::Sender<Result<String, anyhow::Error>>,
+        username_sender: oneshot::Sender<NetworkResult<String>>,
     ) {
         let key = kad::RecordKey::new(&peer_id.to_bytes());
         let query_id = self.swarm.behaviour_mut().kademlia.get_record(key);
@@ -76,14 +89,10 @@ impl EventLoop {
         peer_id: PeerId,
         requested_file_name: String,
         requested_file_path: PathBuf,
-        error_sender: oneshot::Sender<Result<(), anyhow::Error>>,
+        trade_id_sender: oneshot::Sender<NetworkResult<TradeId>>,
     ) {
         if &peer_id == self.swarm.local_peer_id() {
-            error_sender
-                .send(Err(anyhow!(
-                    "Sending trade offers to yourself is forbidden"
-                )))
-                .expect("Error receiver was dropped");
+            let _ = trade_id_sender.send(Err(NetworkError::SelfRequest));
             return;
         }
 
@@ -91,51 +100,107 @@ impl EventLoop {
             offered_file_name,
             requested_file_name,
         };
-        let query_id = self
-            .swarm
-            .behaviour_mut()
-            .trade_offering
-            .send_request(&peer_id, offer.clone());
+        let trade_id = self.insert_trade(peer_id, TradeDirection::Outbound, &offer);
+        let query_id = self.swarm.behaviour_mut().trade_offering.send_request(
+            &peer_id,
+            TradeOfferRequest {
+                offer: offer.clone(),
+                offered_file_size: Some(offered_file_bytes.len() as u64),
+            },
+        );
 
         self.pending_trade_offer_request
-            .insert(query_id, error_sender);
+            .insert(query_id, (trade_id, trade_id_sender));
 
-        self.outgoing_trade_offers
-            .insert((peer_id, offer), (offered_file_bytes, requested_file_path));
+        // Offering the same trade again replaces the earlier offer, since the
+        // peer's response can't be told apart between the two
+        if let Some((replaced_trade_id, _, _)) = self.outgoing_trade_offers.insert(
+            (peer_id, offer),
+            (trade_id, offered_file_bytes, requested_file_path),
+        ) {
+            self.trades.remove(&replaced_trade_id);
+        }
     }
 
     pub(super) fn handle_respond_trade(
         &mut self,
-        peer_id: PeerId,
-        requested_file_name: String,
-        offered_file_name: String,
-        requested_file_bytes: Option<Vec<u8>>,
-        offered_bytes_sender: Option<oneshot::Sender<Result<Option<Vec<u8>>, anyhow::Error>>>,
+        trade_id: TradeId,
+        acceptance: Option<TradeAcceptance>,
+        result_sender: oneshot::Sender<NetworkResult<()>>,
     ) {
-        let offer = TradeOffer {
-            requested_file_name: requested_file_name.clone(),
-            offered_file_name: offered_file_name.clone(),
-        };
-        if !self.inbound_trade_offers.remove(&(peer_id, offer)) {
-            if let Some(offered_bytes_sender) = offered_bytes_sender {
-                offered_bytes_sender.send(Err(anyhow!(format!(
-                    "No valid trade with this user for {offered_file_name} and {requested_file_name}"
-                )))).expect("Offered bytes receiver was dropped");
+        // The offer is kept when the file won't fit, so that it can still be
+        // accepted with another path or declined
+        let offered_file_size = self
+            .inbound_trade_offers
+            .get(trade_id)
+            .and_then(|inbound_offer| inbound_offer.offered_file_size);
+        if let (Some(acceptance), Some(offered_file_size)) = (&acceptance, offered_file_size) {
+            if let Err(error) = self
+                .storage
+                .check_space(&acceptance.offered_file_path, offered_file_size)
+            {
+                let _ = result_sender.send(Err(error.into()));
+                return;
             }
-            return;
         }
+
+        let Some(InboundOffer {
+            peer_id,
+            offer,
+            offered_file_size,
+            ..
+        }) = self.inbound_trade_offers.remove(trade_id)
+        else {
+            let _ = result_sender.send(Err(NetworkError::UnknownTrade { trade_id }));
+            return;
+        };
+
+        let TradeOffer {
+            offered_file_name,
+            requested_file_name,
+        } = offer;
+        let Some(TradeAcceptance {
+            requested_file_bytes,
+            offered_file_path,
+        }) = acceptance
+        else {
+            self.swarm.behaviour_mut().trade_response.send_request(
+                &peer_id,
+                TradeResponse {
+                    requested_file_name,
+                    offered_file_name,
+                    requested_file_bytes: None,
+                    username: Some(self.username.clone()),
+                    transfer: None,
+                },
+            );
+            self.trades.remove(&trade_id);
+            let _ = result_sender.send(Ok(()));
+            return;
+        };
+
+        let requested_file_hash = receipt::hash(&requested_file_bytes);
         let request_id = self.swarm.behaviour_mut().trade_response.send_request(
             &peer_id,
             TradeResponse {
                 requested_file_name,
                 offered_file_name,
-                requested_file_bytes,
+                requested_file_bytes: Some(requested_file_bytes),
+                username: Some(self.username.clone()),
+                transfer: Some(self.transfers.start(trade_id, peer_id, offered_file_size)),
+            },
+        );
+        self.set_trade_status(trade_id, TradeStatus::Accepted);
+        self.pending_trade_response_response.insert(
+            request_id,
+            AcceptedTrade {
+                trade_id,
+                offered_file_path,
+                offered_file_size,
+                requested_file_hash,
+                result_sender,
             },
         );
-        if let Some(offered_bytes_sender) = offered_bytes_sender {
-            self.pending_trade_response_response
-                .insert(request_id, offered_bytes_sender);
-        }
     }
 
     pub(super) fn handle_send_chat_message(
@@ -147,26 +212,20 @@ impl EventLoop {
             .swarm
             .behaviour_mut()
             .gossipsub
-            .publish(self.gossipsub_topic.clone(), message.as_bytes())
+            .publish(self.gossipsub_topic.clone(), chat::encode(message))
             .map(|_| ());
 
-        status_sender
-            .send(status)
-            .expect("Status receiver was dropped");
+        let _ = status_sender.send(status);
     }
 
     pub(super) fn handle_direct_message(
         &mut self,
         peer_id: &PeerId,
         message: String,
-        error_sender: oneshot::Sender<Result<(), anyhow::Error>>,
+        error_sender: oneshot::Sender<NetworkResult<()>>,
     ) {
         if peer_id == self.swarm.local_peer_id() {
-            error_sender
-                .send(Err(anyhow!(
-                    "Sending direct messages to yourself is forbidden"
-                )))
-                .expect("Error receiver was dropped");
+            let _ = error_sender.send(Err(NetworkError::SelfRequest));
             return;
         }
 
@@ -178,4 +237,126 @@ impl EventLoop {
         self.pending_request_message
             .insert(request_id, error_sender);
     }
+
+    pub(super) fn handle_block_peer(
+        &mut self,
+        peer_id: PeerId,
+        blocked: bool,
+        result_sender: oneshot::Sender<NetworkResult<()>>,
+    ) {
+        let block_list = &mut self.swarm.behaviour_mut().block_list;
+        if blocked {
+            // Also closes any connections with them
+            block_list.block_peer(peer_id);
+            self.peer_list.blocked.insert(peer_id);
+        } else {
+            block_list.unblock_peer(peer_id);
+            self.peer_list.blocked.remove(&peer_id);
+        }
+        self.save_peer_list(result_sender);
+    }
+
+    pub(super) fn handle_mute_peer(
+        &mut self,
+        peer_id: PeerId,
+        muted: bool,
+        result_sender: oneshot::Sender<NetworkResult<()>>,
+    ) {
+        if muted {
+            self.peer_list.muted.insert(peer_id);
+        } else {
+            self.peer_list.muted.remove(&peer_id);
+        }
+        self.save_peer_list(result_sender);
+    }
+
+    /// Writes the peer list to the data directory, if there is one, sending
+    /// the result once it has been written.
+    fn save_peer_list(&self, result_sender: oneshot::Sender<NetworkResult<()>>) {
+        let Some(data_dir) = &self.data_dir else {
+            let _ = result_sender.send(Ok(()));
+            return;
+        };
+        let path = data_dir.join(peer_list::FILE_NAME);
+        let written = self.storage.queue_write(path, self.peer_list.to_bytes());
+        tokio::spawn(async move {
+            let result = written.await;
+            let _ = result_sender.send(result.map_err(NetworkError::from));
+        });
+    }
+
+    pub(super) fn handle_query_reputation(
+        &mut self,
+        peer_id: PeerId,
+        subject: PeerId,
+        attestation_sender: oneshot::Sender<NetworkResult<Attestation>>,
+    ) {
+        if &peer_id == self.swarm.local_peer_id() {
+            let _ = attestation_sender.send(Err(NetworkError::SelfRequest));
+            return;
+        }
+
+        let request_id = self
+            .swarm
+            .behaviour_mut()
+            .reputation
+            .send_request(&peer_id, ReputationRequest { subject });
+        self.pending_reputation_request
+            .insert(request_id, (subject, attestation_sender));
+    }
+
+    /// Forgets requests whose callers have stopped waiting for the result,
+    /// e.g. because their call timed out. DHT queries which are no longer
+    /// needed are stopped as well.
+    pub(super) fn prune_abandoned_requests(&mut self) {
+        self.expire_inbound_offers();
+        self.expire_pending_receipts();
+
+        let mut abandoned_queries = Vec::new();
+        remove_abandoned_queries(&mut self.pending_register_username, &mut abandoned_queries);
+        remove_abandoned_queries(&mut self.pending_peer_id_request, &mut abandoned_queries);
+        remove_abandoned_queries(&mut self.pending_username_request, &mut abandoned_queries);
+        for query_id in abandoned_queries {
+            if let Some(mut query) = self.swarm.behaviour_mut().kademlia.query_mut(&query_id) {
+                query.finish();
+            }
+        }
+
+        // Outbound requests can not be cancelled, their eventual response or
+        // failure is ignored instead
+        self.pending_request_message
+            .retain(|_, sender| !sender.is_canceled());
+        // Nobody knows the ID of an offer whose caller stopped waiting for it,
+        // so it could never be followed up on
+        let (trades, outgoing_trade_offers) = (&mut self.trades, &mut self.outgoing_trade_offers);
+        self.pending_trade_offer_request
+            .retain(|_, (trade_id, sender)| {
+                if sender.is_canceled() {
+                    trades.remove(trade_id);
+                    outgoing_trade_offers
+                        .retain(|_, (outgoing_trade_id, _, _)| outgoing_trade_id != trade_id);
+                    return false;
+                }
+                true
+            });
+        self.pending_reputation_request
+            .retain(|_, (_, sender)| !sender.is_canceled());
+        // Trades we accepted are kept, as our file may already be on its way
+        // and theirs is still stored once it arrives
+    }
+}
+
+/// Removes the queries whose callers have stopped waiting for the result,
+/// collecting their IDs into `abandoned_queries`.
+fn remove_abandoned_queries<T>(
+    pending: &mut HashMap<kad::QueryId, oneshot::Sender<T>>,
+    abandoned_queries: &mut Vec<kad::QueryId>,
+) {
+    pending.retain(|query_id, sender| {
+        if sender.is_canceled() {
+            abandoned_queries.push(*query_id);
+            return false;
+        }
+        true
+    });
 }
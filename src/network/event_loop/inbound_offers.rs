@@ -0,0 +1,131 @@
+use std::{collections::HashMap, time::Duration};
+
+use libp2p::PeerId;
+use tokio::time::Instant;
+
+use super::TradeId;
+use crate::network::TradeOffer;
+
+/// The trade offers awaiting our response. Their number is capped, both in
+/// total and per peer, and offers which go unanswered for too long are
+/// dropped so that they don't hold up room for new ones forever.
+pub(super) struct InboundOffers {
+    max_offers: usize,
+    max_offers_per_peer: usize,
+    timeout: Duration,
+    offers: HashMap<TradeId, InboundOffer>,
+}
+
+pub(super) struct InboundOffer {
+    pub peer_id: PeerId,
+    pub offer: TradeOffer,
+    pub offered_file_size: Option<u64>,
+    received: Instant,
+}
+
+impl InboundOffers {
+    pub(super) fn new(max_offers: usize, max_offers_per_peer: usize, timeout: Duration) -> Self {
+        Self {
+            max_offers,
+            max_offers_per_peer,
+            timeout,
+            offers: HashMap::new(),
+        }
+    }
+
+    /// Whether another offer from `peer_id` would fit within the limits.
+    pub(super) fn has_room_for(&self, peer_id: PeerId) -> bool {
+        let from_peer = self
+            .offers
+            .values()
+            .filter(|offer| offer.peer_id == peer_id)
+            .count();
+        self.offers.len() < self.max_offers && from_peer < self.max_offers_per_peer
+    }
+
+    pub(super) fn insert(
+        &mut self,
+        trade_id: TradeId,
+        peer_id: PeerId,
+        offer: TradeOffer,
+        offered_file_size: Option<u64>,
+    ) {
+        self.offers.insert(
+            trade_id,
+            InboundOffer {
+                peer_id,
+                offer,
+                offered_file_size,
+                received: Instant::now(),
+            },
+        );
+    }
+
+    pub(super) fn get(&self, trade_id: TradeId) -> Option<&InboundOffer> {
+        self.offers.get(&trade_id)
+    }
+
+    pub(super) fn remove(&mut self, trade_id: TradeId) -> Option<InboundOffer> {
+        self.offers.remove(&trade_id)
+    }
+
+    /// Drops the offers which have waited longer than the timeout, returning
+    /// their IDs.
+    pub(super) fn expire(&mut self) -> Vec<TradeId> {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        self.offers.retain(|trade_id, offer| {
+            if now.duration_since(offer.received) < self.timeout {
+                return true;
+            }
+            expired.push(*trade_id);
+            false
+        });
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offer() -> TradeOffer {
+        TradeOffer {
+            offered_file_name: "offered.txt".to_owned(),
+            requested_file_name: "requested.txt".to_owned(),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expired_offers_free_up_room() {
+        let mut offers = InboundOffers::new(2, 2, Duration::from_mins(10));
+        let (alice, bob) = (PeerId::random(), PeerId::random());
+        offers.insert(TradeId(1), alice, offer(), None);
+        tokio::time::advance(Duration::from_mins(5)).await;
+        offers.insert(TradeId(2), alice, offer(), Some(1));
+        assert!(!offers.has_room_for(bob));
+
+        tokio::time::advance(Duration::from_mins(5)).await;
+        assert_eq!(offers.expire(), vec![TradeId(1)]);
+        assert!(offers.has_room_for(bob));
+        assert!(offers.get(TradeId(1)).is_none());
+        assert!(offers.get(TradeId(2)).is_some());
+    }
+
+    #[test]
+    fn offers_are_capped_per_peer() {
+        let mut offers = InboundOffers::new(3, 2, Duration::from_mins(10));
+        let (alice, bob) = (PeerId::random(), PeerId::random());
+        offers.insert(TradeId(1), alice, offer(), None);
+        offers.insert(TradeId(2), alice, offer(), None);
+        assert!(!offers.has_room_for(alice));
+        assert!(offers.has_room_for(bob));
+
+        offers.insert(TradeId(3), bob, offer(), None);
+        assert!(!offers.has_room_for(bob));
+
+        assert!(offers.remove(TradeId(1)).is_some());
+        assert!(offers.has_room_for(alice));
+        assert!(offers.has_room_for(bob));
+    }
+}
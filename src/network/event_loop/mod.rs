@@ -1,88 +1,200 @@
 mod behaviour_handlers;
+mod chat;
 mod command;
 mod command_handlers;
+mod inbound_offers;
+mod pending_receipts;
+mod rate_limit;
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap},
+    fmt,
+    num::ParseIntError,
     path::PathBuf,
-    time::Duration,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use futures::{
     channel::{mpsc, oneshot},
-    StreamExt,
+    future::BoxFuture,
+    stream::FuturesUnordered,
+    SinkExt, StreamExt,
 };
 use libp2p::{
-    gossipsub, identify, kad, mdns, rendezvous, request_response,
+    gossipsub, identify, identity, kad, mdns, rendezvous, request_response,
     swarm::{Swarm, SwarmEvent},
-    PeerId,
+    Multiaddr, PeerId,
 };
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
 
-use super::{Behaviour, BehaviourEvent, DirectMessage, TradeOffer, TradeResponse};
+use super::{
+    peer_list::PeerList,
+    receipt::{self, Receipt, SignedReceipt, TradeParty},
+    reputation::{self, Attestation, Ledger, Reputation, TradeOutcome},
+    storage::{Storage, StorageError, StorageTask},
+    transfer::Transfers,
+    Behaviour, BehaviourEvent, DirectMessage, NetworkError, TradeOffer, TradeResponse,
+    TradeResponseResponse,
+};
 
-pub(super) use command::Command;
+use chat::ChatValidator;
+pub(super) use command::{Command, TradeAcceptance};
+use inbound_offers::InboundOffers;
+use pending_receipts::PendingReceipts;
+use rate_limit::RateLimiter;
 
-type DynResult<T> = Result<T, anyhow::Error>;
+type NetworkResult<T> = Result<T, NetworkError>;
 
 const RENDEZVOUS_NAMESPACE: &str = "rendezvous";
 
-pub(crate) struct EventLoop {
+/// The delay before the first attempt to reconnect or re-register with the
+/// rendezvous server. Each consecutive failure doubles the delay.
+const INITIAL_RENDEZVOUS_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RENDEZVOUS_BACKOFF: Duration = Duration::from_mins(5);
+/// How often requests whose callers stopped waiting are forgotten.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(5);
+/// How long to wait for the recipient of a trade we offered to send back its
+/// receipt with their signature.
+const RECEIPT_TIMEOUT: Duration = Duration::from_mins(10);
+
+/// Drives a node's networking. Nothing happens on the network until
+/// [`EventLoop::run`] is polled, usually by spawning it as a task.
+pub struct EventLoop {
     swarm: Swarm<Behaviour>,
     rendezvous_peer_id: Option<PeerId>,
+    rendezvous_address: Option<Multiaddr>,
+    rendezvous_status: RendezvousStatus,
+    rendezvous_backoff: Duration,
+    rendezvous_redial_deadline: Option<Instant>,
+    rendezvous_register_deadline: Option<Instant>,
     command_receiver: mpsc::Receiver<Command>,
     event_sender: mpsc::Sender<Event>,
-    pending_register_username:
-        HashMap<kad::QueryId, oneshot::Sender<Result<(), kad::PutRecordError>>>,
+    pending_register_username: HashMap<kad::QueryId, oneshot::Sender<NetworkResult<()>>>,
     pending_request_message:
-        HashMap<request_response::OutboundRequestId, oneshot::Sender<DynResult<()>>>,
+        HashMap<request_response::OutboundRequestId, oneshot::Sender<NetworkResult<()>>>,
     pending_peer_id_request: HashMap<kad::QueryId, oneshot::Sender<Option<PeerId>>>,
-    pending_username_request: HashMap<kad::QueryId, oneshot::Sender<DynResult<String>>>,
-    pending_trade_offer_request:
-        HashMap<request_response::OutboundRequestId, oneshot::Sender<DynResult<()>>>,
-    pending_trade_response_response:
-        HashMap<request_response::OutboundRequestId, oneshot::Sender<DynResult<Option<Vec<u8>>>>>,
-    outgoing_trade_offers: HashMap<(PeerId, TradeOffer), (Vec<u8>, PathBuf)>,
-    inbound_trade_offers: HashSet<(PeerId, TradeOffer)>,
+    pending_username_request: HashMap<kad::QueryId, oneshot::Sender<NetworkResult<String>>>,
+    pending_reputation_request: HashMap<
+        request_response::OutboundRequestId,
+        (PeerId, oneshot::Sender<NetworkResult<Attestation>>),
+    >,
+    pending_trade_offer_request: HashMap<
+        request_response::OutboundRequestId,
+        (TradeId, oneshot::Sender<NetworkResult<TradeId>>),
+    >,
+    pending_trade_response_response: HashMap<request_response::OutboundRequestId, AcceptedTrade>,
+    pending_trade_file_writes: FuturesUnordered<BoxFuture<'static, StoredTradeFile>>,
+    pending_offered_file_writes: FuturesUnordered<BoxFuture<'static, StoredOfferedFile>>,
+    outgoing_trade_offers: HashMap<(PeerId, TradeOffer), (TradeId, Vec<u8>, PathBuf)>,
+    inbound_trade_offers: InboundOffers,
+    /// Every trade which has been offered and not yet completed or declined,
+    /// in either direction.
+    trades: BTreeMap<TradeId, Trade>,
+    next_trade_id: u64,
     gossipsub_topic: gossipsub::IdentTopic,
     has_registered_username: bool,
     username: String,
     discover_tick: tokio::time::Interval,
+    bootstrap_tick: tokio::time::Interval,
+    random_walk_tick: tokio::time::Interval,
+    prune_tick: tokio::time::Interval,
     cookie: Option<rendezvous::Cookie>,
     rendezvous_namespace: rendezvous::Namespace,
+    storage: Storage,
+    storage_task: Option<StorageTask>,
+    transfers: Transfers,
+    peer_list: PeerList,
+    ledger: Ledger,
+    /// Our own keypair, which attestations and receipts are signed with.
+    keypair: identity::Keypair,
+    pending_receipts: PendingReceipts,
+    data_dir: Option<PathBuf>,
+    request_limiter: RateLimiter,
+    chat_validator: ChatValidator,
+    limits: InboundLimits,
 }
 
 impl EventLoop {
+    #[allow(clippy::too_many_arguments)]
     pub(super) fn new(
         swarm: Swarm<Behaviour>,
         command_receiver: mpsc::Receiver<Command>,
         event_sender: mpsc::Sender<Event>,
         gossipsub_topic: gossipsub::IdentTopic,
         username: String,
-        rendezvous_peer_id: Option<PeerId>,
+        rendezvous_point: Option<(PeerId, Multiaddr)>,
+        (storage, storage_task): (Storage, StorageTask),
+        transfers: Transfers,
+        keypair: identity::Keypair,
+        peer_list: PeerList,
+        ledger: Ledger,
+        data_dir: Option<PathBuf>,
+        intervals: DiscoveryIntervals,
+        limits: InboundLimits,
     ) -> Self {
+        let (rendezvous_peer_id, rendezvous_address) = rendezvous_point.unzip();
         Self {
             swarm,
             rendezvous_peer_id,
+            rendezvous_address,
+            rendezvous_status: RendezvousStatus::Connecting,
+            rendezvous_backoff: INITIAL_RENDEZVOUS_BACKOFF,
+            rendezvous_redial_deadline: None,
+            rendezvous_register_deadline: None,
             command_receiver,
             event_sender,
             pending_register_username: HashMap::default(),
             pending_request_message: HashMap::default(),
             pending_peer_id_request: HashMap::default(),
             pending_username_request: HashMap::default(),
+            pending_reputation_request: HashMap::default(),
             pending_trade_offer_request: HashMap::default(),
             pending_trade_response_response: HashMap::default(),
+            pending_trade_file_writes: FuturesUnordered::new(),
+            pending_offered_file_writes: FuturesUnordered::new(),
             outgoing_trade_offers: HashMap::default(),
-            inbound_trade_offers: HashSet::default(),
+            inbound_trade_offers: InboundOffers::new(
+                limits.offers,
+                limits.offers_per_peer,
+                limits.offer_timeout,
+            ),
+            trades: BTreeMap::default(),
+            next_trade_id: 1,
             gossipsub_topic,
             has_registered_username: false,
             username,
-            discover_tick: tokio::time::interval(Duration::from_secs(30)),
+            discover_tick: tokio::time::interval(intervals.rendezvous_discover),
+            bootstrap_tick: tokio::time::interval(intervals.bootstrap),
+            random_walk_tick: tokio::time::interval(intervals.random_walk),
+            prune_tick: tokio::time::interval(PRUNE_INTERVAL),
             cookie: None,
             rendezvous_namespace: rendezvous::Namespace::from_static(RENDEZVOUS_NAMESPACE),
+            storage,
+            storage_task: Some(storage_task),
+            transfers,
+            peer_list,
+            ledger,
+            keypair,
+            pending_receipts: PendingReceipts::new(RECEIPT_TIMEOUT),
+            data_dir,
+            request_limiter: RateLimiter::new(limits.requests_per_minute),
+            chat_validator: ChatValidator::new(limits.chat_message_size, limits.chat_message_age),
+            limits,
         }
     }
 
-    pub(crate) async fn run(mut self) {
+    /// Runs the node until every [`Client`](super::Client) has been dropped.
+    /// Should the [`EventStream`](super::EventStream) be dropped, the node
+    /// keeps running and its events are discarded.
+    pub async fn run(mut self) {
+        // Received files are written by a task of their own, so that
+        // networking carries on while they are stored
+        if let Some(storage_task) = self.storage_task.take() {
+            tokio::spawn(storage_task.run());
+        }
+
         loop {
             tokio::select! {
                 event = self.swarm.select_next_some() => self.handle_event(event).await,
@@ -91,23 +203,25 @@ impl EventLoop {
                     // Command channel closed, thus shutting down the network event loop.
                     None => return,
                 },
-                _ = self.discover_tick.tick(), if self.rendezvous_peer_id.is_some() && self.cookie.is_some() => {
-                    // If a rendezvous server was specified, connect to it on a regular interval to
-                    // discover new peers.
-                    self.swarm
-                        .behaviour_mut()
-                        .rendezvous
-                        .discover(
-                            Some(self.rendezvous_namespace.clone()),
-                            self.cookie.clone(),
-                            None,
-                            self.rendezvous_peer_id.unwrap(),
-                        );
+                // If a rendezvous server was specified, connect to it on a regular interval to
+                // discover new peers.
+                _ = self.discover_tick.tick(), if self.cookie.is_some() => self.handle_discover_tick(),
+                _ = self.bootstrap_tick.tick() => self.handle_bootstrap_tick(),
+                _ = self.random_walk_tick.tick() => self.handle_random_walk_tick(),
+                _ = self.prune_tick.tick() => self.prune_abandoned_requests(),
+                () = sleep_until(self.rendezvous_redial_deadline) => self.handle_rendezvous_redial().await,
+                () = sleep_until(self.rendezvous_register_deadline) => self.handle_rendezvous_register(),
+                stored = self.pending_trade_file_writes.select_next_some(), if !self.pending_trade_file_writes.is_empty() => {
+                    self.handle_trade_file_stored(stored).await;
+                }
+                stored = self.pending_offered_file_writes.select_next_some(), if !self.pending_offered_file_writes.is_empty() => {
+                    self.handle_offered_file_stored(stored).await;
                 }
             }
         }
     }
 
+    #[allow(clippy::too_many_lines)]
     async fn handle_event(&mut self, event: SwarmEvent<BehaviourEvent>) {
         match event {
             SwarmEvent::Behaviour(BehaviourEvent::Kademlia(
@@ -136,9 +250,12 @@ impl EventLoop {
 
             SwarmEvent::Behaviour(BehaviourEvent::DirectMessaging(
                 request_response::Event::OutboundFailure {
-                    request_id, error, ..
+                    peer,
+                    request_id,
+                    error,
+                    ..
                 },
-            )) => self.handle_direct_messaging_outbound_failure(request_id, error),
+            )) => self.handle_direct_messaging_outbound_failure(request_id, error, peer),
 
             SwarmEvent::Behaviour(BehaviourEvent::TradeOffering(
                 request_response::Event::Message { peer, message, .. },
@@ -146,9 +263,12 @@ impl EventLoop {
 
             SwarmEvent::Behaviour(BehaviourEvent::TradeOffering(
                 request_response::Event::OutboundFailure {
-                    error, request_id, ..
+                    peer,
+                    error,
+                    request_id,
+                    ..
                 },
-            )) => self.handle_trade_offering_outbound_failure(error, request_id),
+            )) => self.handle_trade_offering_outbound_failure(error, request_id, peer),
 
             SwarmEvent::Behaviour(BehaviourEvent::TradeResponse(
                 request_response::Event::Message { peer, message, .. },
@@ -156,9 +276,42 @@ impl EventLoop {
 
             SwarmEvent::Behaviour(BehaviourEvent::TradeResponse(
                 request_response::Event::OutboundFailure {
-                    request_id, error, ..
+                    peer,
+                    request_id,
+                    error,
+                    ..
+                },
+            )) => {
+                self.handle_trade_response_outbound_failure(request_id, error, peer)
+                    .await;
+            }
+
+            SwarmEvent::Behaviour(BehaviourEvent::TradeReceipt(
+                request_response::Event::Message { peer, message, .. },
+            )) => self.handle_trade_receipt_message(message, peer).await,
+
+            SwarmEvent::Behaviour(BehaviourEvent::TradeReceipt(
+                request_response::Event::OutboundFailure { peer, error, .. },
+            )) => {
+                self.send_event(Event::Error(NetworkError::Request {
+                    peer_id: peer,
+                    error,
+                }))
+                .await;
+            }
+
+            SwarmEvent::Behaviour(BehaviourEvent::Reputation(
+                request_response::Event::Message { peer, message, .. },
+            )) => self.handle_reputation_message(message, peer).await,
+
+            SwarmEvent::Behaviour(BehaviourEvent::Reputation(
+                request_response::Event::OutboundFailure {
+                    peer,
+                    request_id,
+                    error,
+                    ..
                 },
-            )) => self.handle_trade_response_outbound_failure(request_id, error),
+            )) => self.handle_reputation_outbound_failure(request_id, error, peer),
 
             SwarmEvent::Behaviour(BehaviourEvent::Mdns(mdns::Event::Discovered(list))) => {
                 self.handle_mdns_discovered(list);
@@ -169,57 +322,422 @@ impl EventLoop {
             }
 
             SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Message {
-                propagation_source: peer_id,
+                propagation_source,
+                message_id,
                 message,
+            })) => {
+                self.handle_gossipsub_message(message, &message_id, propagation_source)
+                    .await;
+            }
+
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                num_established,
+                ..
+            } => {
+                if num_established.get() == 1 {
+                    self.send_event(Event::PeerConnected { peer_id }).await;
+                }
+                if Some(peer_id) == self.rendezvous_peer_id {
+                    self.handle_connected_to_rendezvous_server(peer_id);
+                }
+            }
+
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established: 0,
                 ..
-            })) => self.handle_gossipsub_message(&message, peer_id).await,
+            } => {
+                self.request_limiter.prune();
+                self.send_event(Event::PeerDisconnected { peer_id }).await;
+                if Some(peer_id) == self.rendezvous_peer_id {
+                    self.handle_disconnected_from_rendezvous_server().await;
+                }
+            }
 
-            SwarmEvent::ConnectionEstablished { peer_id, .. }
-                if Some(peer_id) == self.rendezvous_peer_id =>
-            {
-                self.handle_connected_to_rendezvous_server();
+            SwarmEvent::OutgoingConnectionError {
+                peer_id: Some(peer_id),
+                error,
+                ..
+            } if Some(peer_id) == self.rendezvous_peer_id => {
+                tracing::warn!("Failed to connect to rendezvous point: {error}");
+                self.handle_disconnected_from_rendezvous_server().await;
             }
 
+            SwarmEvent::Behaviour(BehaviourEvent::Rendezvous(
+                rendezvous::client::Event::Registered { ttl, .. },
+            )) => self.handle_rendezvous_registered(ttl).await,
+
+            SwarmEvent::Behaviour(BehaviourEvent::Rendezvous(
+                rendezvous::client::Event::RegisterFailed { error, .. },
+            )) => self.handle_rendezvous_register_failed(error).await,
+
             SwarmEvent::Behaviour(BehaviourEvent::Rendezvous(
                 rendezvous::client::Event::Discovered {
                     registrations,
                     cookie,
                     ..
                 },
-            )) => self.handle_rendezvous_discovered(registrations, cookie),
+            )) => {
+                self.handle_rendezvous_discovered(registrations, cookie)
+                    .await;
+            }
 
             SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Received {
+                peer_id,
                 info,
                 ..
-            })) => self.handle_identify_received(info),
+            })) => self.handle_identify_received(info, peer_id),
 
             _event => {}
         }
     }
 }
 
+/// A file received in exchange for one of our trade offers, which has been
+/// written to disk (or failed to be). Only once it has been stored is our
+/// file sent in return.
+struct StoredTradeFile {
+    trade_id: TradeId,
+    peer_id: PeerId,
+    channel: request_response::ResponseChannel<TradeResponseResponse>,
+    offered_file_name: String,
+    requested_file_name: String,
+    offered_file_bytes: Vec<u8>,
+    requested_file_path: PathBuf,
+    /// The hash of the file received, for the trade's receipt.
+    requested_file_hash: String,
+    /// The recipient's username, if they gave one for the receipt.
+    recipient_username: Option<String>,
+    result: Result<(), StorageError>,
+}
+
+/// A file received in a trade we accepted, which has been written to disk
+/// (or failed to be).
+struct StoredOfferedFile {
+    trade_id: TradeId,
+    peer_id: PeerId,
+    offered_file_name: String,
+    requested_file_name: String,
+    offered_file_path: PathBuf,
+    result_sender: oneshot::Sender<NetworkResult<()>>,
+    result: Result<(), StorageError>,
+}
+
+/// A trade offered to us which we have accepted, waiting on the offerer to
+/// send their file.
+struct AcceptedTrade {
+    trade_id: TradeId,
+    offered_file_path: PathBuf,
+    /// The size the offerer claimed their file to be.
+    offered_file_size: Option<u64>,
+    /// The hash of the file we sent, to check the trade's receipt against.
+    requested_file_hash: String,
+    result_sender: oneshot::Sender<NetworkResult<()>>,
+}
+
+/// How often the event loop performs each of its periodic discovery tasks.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct DiscoveryIntervals {
+    pub rendezvous_discover: Duration,
+    pub bootstrap: Duration,
+    pub random_walk: Duration,
+}
+
+/// How much other peers may ask of us.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct InboundLimits {
+    pub requests_per_minute: u32,
+    pub offers: usize,
+    pub offers_per_peer: usize,
+    pub offer_timeout: Duration,
+    pub chat_message_size: usize,
+    pub chat_message_age: Duration,
+    pub share_reputation: bool,
+}
+
+/// Events emitted by the network, received through the
+/// [`EventStream`](super::EventStream).
 #[derive(Debug)]
-pub(crate) enum Event {
+pub enum Event {
+    /// A peer has offered their file `offered_file_name` in exchange for our
+    /// file `requested_file_name`, known to us as `trade_id`. Respond with
+    /// [`Client::accept_trade`](super::Client::accept_trade) or
+    /// [`Client::decline_trade`](super::Client::decline_trade).
+    ///
+    /// `offered_file_size` is the size in bytes the offerer claims their file
+    /// to be, if they said. Should they send a larger file, it is refused.
+    /// `reputation` is how our earlier trades with the offerer have gone.
     InboundTradeOffer {
+        trade_id: TradeId,
         offered_file_name: String,
+        offered_file_size: Option<u64>,
         peer_id: PeerId,
         requested_file_name: String,
+        reputation: Reputation,
     },
+    /// A peer has responded to a trade we offered them. If they accepted, the
+    /// file we requested has been written to the path given when offering.
     InboundTradeResponse {
+        trade_id: TradeId,
         peer_id: PeerId,
         offered_file_name: String,
         requested_file_name: String,
         was_accepted: bool,
     },
-    InboundDirectMessage {
+    /// A trade has completed, in either direction, and the file we received
+    /// has been written to `received_file_path`. For trades we offered this
+    /// follows [`Event::InboundTradeResponse`].
+    TradeCompleted {
+        trade_id: TradeId,
         peer_id: PeerId,
-        message: String,
+        direction: TradeDirection,
+        offered_file_name: String,
+        requested_file_name: String,
+        received_file_path: PathBuf,
     },
-    InboundChat {
+    /// A receipt for a completed trade, signed by both parties, has been
+    /// saved to `path` in the data directory. Trades with peers which don't
+    /// sign receipts, and nodes without a data directory, have none.
+    ReceiptSaved {
+        trade_id: TradeId,
         peer_id: PeerId,
-        message: String,
+        path: PathBuf,
     },
-    RegistrationRequest {
-        username: String,
+    /// A peer has sent us a direct message.
+    InboundDirectMessage { peer_id: PeerId, message: String },
+    /// A peer has sent a message to the global chat room.
+    InboundChat { peer_id: PeerId, message: String },
+    /// We have joined the DHT, and `username` should now be registered with
+    /// [`Client::register_username`](super::Client::register_username).
+    RegistrationRequest { username: String },
+    /// Our registration with the rendezvous server has changed.
+    RendezvousStatusChanged(RendezvousStatus),
+    /// We are now connected to a peer we had no connection with before.
+    PeerConnected { peer_id: PeerId },
+    /// Our last connection to a peer has closed.
+    PeerDisconnected { peer_id: PeerId },
+    /// Something went wrong which no [`Client`](super::Client) call was
+    /// waiting on, such as failing to answer a peer's request.
+    Error(NetworkError),
+}
+
+/// The state of our registration with the rendezvous server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RendezvousStatus {
+    /// A connection to the rendezvous server is being established.
+    Connecting,
+    /// We are registered and discoverable until the registration expires,
+    /// `ttl` seconds after it was made. It is refreshed before then.
+    Registered { ttl: rendezvous::Ttl },
+    /// The rendezvous server refused our registration. Registration will be
+    /// attempted again after `retry_in`.
+    RegistrationFailed {
+        error: rendezvous::ErrorCode,
+        retry_in: Duration,
     },
+    /// The connection to the rendezvous server was lost or could not be made.
+    /// Reconnection will be attempted after `retry_in`.
+    Disconnected { retry_in: Duration },
+}
+
+/// Identifies a trade for as long as it is pending. IDs are assigned by each
+/// node as trades are offered, so the other party knows the same trade by a
+/// different ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TradeId(u64);
+
+impl fmt::Display for TradeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for TradeId {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Self)
+    }
+}
+
+/// A pending trade, as listed by [`Client::trades`](super::Client::trades).
+///
+/// As in [`Event::InboundTradeOffer`], the offered file is always the
+/// offerer's and the requested file the recipient's.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trade {
+    #[serde(rename = "trade_id")]
+    pub id: TradeId,
+    pub peer_id: PeerId,
+    pub direction: TradeDirection,
+    pub offered_file_name: String,
+    pub requested_file_name: String,
+    pub status: TradeStatus,
+}
+
+/// Whether a trade was offered to us or by us.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TradeDirection {
+    Inbound,
+    Outbound,
+}
+
+/// How far along a pending trade is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TradeStatus {
+    /// The recipient has yet to accept or decline the trade.
+    Offered,
+    /// The recipient has accepted the trade, and the files are being
+    /// exchanged.
+    Accepted,
+}
+
+impl EventLoop {
+    /// Registers a newly offered trade, returning its ID.
+    fn insert_trade(
+        &mut self,
+        peer_id: PeerId,
+        direction: TradeDirection,
+        offer: &TradeOffer,
+    ) -> TradeId {
+        let id = TradeId(self.next_trade_id);
+        self.next_trade_id += 1;
+        self.trades.insert(
+            id,
+            Trade {
+                id,
+                peer_id,
+                direction,
+                offered_file_name: offer.offered_file_name.clone(),
+                requested_file_name: offer.requested_file_name.clone(),
+                status: TradeStatus::Offered,
+            },
+        );
+        id
+    }
+
+    fn set_trade_status(&mut self, trade_id: TradeId, status: TradeStatus) {
+        if let Some(trade) = self.trades.get_mut(&trade_id) {
+            trade.status = status;
+        }
+    }
+
+    /// Drops the trade offers which have waited too long for our response.
+    fn expire_inbound_offers(&mut self) {
+        for trade_id in self.inbound_trade_offers.expire() {
+            tracing::debug!(%trade_id, "Dropping trade offer which wasn't responded to in time");
+            self.trades.remove(&trade_id);
+        }
+    }
+
+    /// Drops the receipts the recipient hasn't sent back in time.
+    fn expire_pending_receipts(&mut self) {
+        for trade_id in self.pending_receipts.expire() {
+            tracing::debug!(%trade_id, "Dropping receipt which wasn't countersigned in time");
+        }
+    }
+
+    /// Records how a trade with `peer_id` ended in their reputation, saving
+    /// the ledger to the data directory if there is one.
+    fn record_trade(&mut self, peer_id: PeerId, outcome: TradeOutcome) {
+        self.ledger.record(peer_id, outcome);
+        let Some(data_dir) = &self.data_dir else {
+            return;
+        };
+        let path = data_dir.join(reputation::FILE_NAME);
+        let written = self.storage.queue_write(path, self.ledger.to_bytes());
+        let mut event_sender = self.event_sender.clone();
+        tokio::spawn(async move {
+            if let Err(error) = written.await {
+                let _ = event_sender.send(Event::Error(error.into())).await;
+            }
+        });
+    }
+
+    /// Signs the receipt for a trade we offered, remembering it until the
+    /// recipient sends it back with their signature.
+    fn sign_receipt(
+        &mut self,
+        trade_id: TradeId,
+        peer_id: PeerId,
+        receipt: &Receipt,
+    ) -> Option<SignedReceipt> {
+        match receipt.sign(&self.keypair) {
+            Ok(signed) => {
+                self.pending_receipts
+                    .insert(signed.signed_text().to_owned(), peer_id, trade_id);
+                Some(signed)
+            }
+            Err(error) => {
+                tracing::warn!(%trade_id, "Failed to sign receipt: {error}");
+                None
+            }
+        }
+    }
+
+    /// Saves a receipt signed by both parties to the data directory, if
+    /// there is one.
+    fn save_receipt(&self, trade_id: TradeId, peer_id: PeerId, signed: &SignedReceipt) {
+        let Some(data_dir) = &self.data_dir else {
+            return;
+        };
+        let path = data_dir.join(receipt::DIRECTORY).join(signed.file_name());
+        let written = self.storage.queue_write(path.clone(), signed.to_bytes());
+        let mut event_sender = self.event_sender.clone();
+        tokio::spawn(async move {
+            let event = match written.await {
+                Ok(()) => Event::ReceiptSaved {
+                    trade_id,
+                    peer_id,
+                    path,
+                },
+                Err(error) => Event::Error(error.into()),
+            };
+            let _ = event_sender.send(event).await;
+        });
+    }
+
+    fn is_blocked(&self, peer_id: &PeerId) -> bool {
+        self.peer_list.blocked.contains(peer_id)
+    }
+
+    /// Whether to drop a request from `peer_id`, either because they are
+    /// blocked or have sent too many requests.
+    fn should_drop_request(&mut self, peer_id: PeerId) -> bool {
+        if self.is_blocked(&peer_id) {
+            return true;
+        }
+        if !self.request_limiter.allow(peer_id) {
+            tracing::debug!(%peer_id, "Dropping request from peer over their rate limit");
+            return true;
+        }
+        false
+    }
+
+    /// Emits an event to the [`EventStream`](super::EventStream), discarding
+    /// it if the stream has been dropped.
+    async fn send_event(&mut self, event: Event) {
+        if let Err(error) = self.event_sender.send(event).await {
+            tracing::debug!("Discarding event, the event stream was dropped: {error}");
+        }
+    }
+}
+
+/// The current time in seconds since the Unix epoch.
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+/// Sleep until the given deadline, or forever if there is no deadline.
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
 }
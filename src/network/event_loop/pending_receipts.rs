@@ -0,0 +1,95 @@
+use std::{collections::HashMap, time::Duration};
+
+use libp2p::PeerId;
+use tokio::time::Instant;
+
+use super::TradeId;
+
+/// The receipts we have signed, by their signed text, awaiting the other
+/// party's signature. Receipts which aren't sent back in time are dropped, as
+/// the other party may never do so.
+pub(super) struct PendingReceipts {
+    timeout: Duration,
+    receipts: HashMap<String, PendingReceipt>,
+}
+
+struct PendingReceipt {
+    peer_id: PeerId,
+    trade_id: TradeId,
+    signed: Instant,
+}
+
+impl PendingReceipts {
+    pub(super) fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            receipts: HashMap::new(),
+        }
+    }
+
+    pub(super) fn insert(&mut self, signed_text: String, peer_id: PeerId, trade_id: TradeId) {
+        self.receipts.insert(
+            signed_text,
+            PendingReceipt {
+                peer_id,
+                trade_id,
+                signed: Instant::now(),
+            },
+        );
+    }
+
+    /// Removes the receipt with `signed_text` if it was sent to `peer_id`,
+    /// returning the trade it is for.
+    pub(super) fn take(&mut self, signed_text: &str, peer_id: PeerId) -> Option<TradeId> {
+        if self.receipts.get(signed_text)?.peer_id != peer_id {
+            return None;
+        }
+        self.receipts
+            .remove(signed_text)
+            .map(|receipt| receipt.trade_id)
+    }
+
+    /// Drops the receipts which have waited longer than the timeout,
+    /// returning the IDs of their trades.
+    pub(super) fn expire(&mut self) -> Vec<TradeId> {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        self.receipts.retain(|_, receipt| {
+            if now.duration_since(receipt.signed) < self.timeout {
+                return true;
+            }
+            expired.push(receipt.trade_id);
+            false
+        });
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn receipts_which_are_not_sent_back_expire() {
+        let mut receipts = PendingReceipts::new(Duration::from_mins(10));
+        let (alice, bob) = (PeerId::random(), PeerId::random());
+        receipts.insert("first".to_owned(), alice, TradeId(1));
+        tokio::time::advance(Duration::from_mins(5)).await;
+        receipts.insert("second".to_owned(), bob, TradeId(2));
+
+        tokio::time::advance(Duration::from_mins(5)).await;
+        assert_eq!(receipts.expire(), vec![TradeId(1)]);
+        assert_eq!(receipts.take("first", alice), None);
+        assert_eq!(receipts.take("second", bob), Some(TradeId(2)));
+    }
+
+    #[test]
+    fn receipts_are_only_taken_by_their_recipient() {
+        let mut receipts = PendingReceipts::new(Duration::from_mins(10));
+        let (alice, bob) = (PeerId::random(), PeerId::random());
+        receipts.insert("receipt".to_owned(), alice, TradeId(1));
+        assert_eq!(receipts.take("receipt", bob), None);
+        assert_eq!(receipts.take("receipt", alice), Some(TradeId(1)));
+        assert_eq!(receipts.take("receipt", alice), None);
+    }
+}
@@ -0,0 +1,94 @@
+use std::{collections::HashMap, time::Duration};
+
+use libp2p::PeerId;
+use tokio::time::Instant;
+
+/// Limits how many requests each peer may send per minute. A peer's allowance
+/// refills steadily over the minute, so short bursts are allowed while a
+/// steady flood is not.
+pub(super) struct RateLimiter {
+    per_minute: u32,
+    allowances: HashMap<PeerId, Allowance>,
+}
+
+struct Allowance {
+    requests: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub(super) fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute,
+            allowances: HashMap::new(),
+        }
+    }
+
+    /// Counts a request from `peer_id`, returning whether it is within their
+    /// limit.
+    pub(super) fn allow(&mut self, peer_id: PeerId) -> bool {
+        let limit = f64::from(self.per_minute);
+        let now = Instant::now();
+        let allowance = self.allowances.entry(peer_id).or_insert(Allowance {
+            requests: limit,
+            updated: now,
+        });
+        let refilled = now.duration_since(allowance.updated).as_secs_f64() / 60.0 * limit;
+        allowance.requests = (allowance.requests + refilled).min(limit);
+        allowance.updated = now;
+        if allowance.requests < 1.0 {
+            return false;
+        }
+        allowance.requests -= 1.0;
+        true
+    }
+
+    /// Forgets the peers who haven't sent a request for a minute. Their
+    /// allowance has fully refilled, so this is the same as remembering them.
+    pub(super) fn prune(&mut self) {
+        let now = Instant::now();
+        self.allowances
+            .retain(|_, allowance| now.duration_since(allowance.updated) < Duration::from_mins(1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn allowance_refills_over_a_minute() {
+        let mut limiter = RateLimiter::new(6);
+        let (alice, bob) = (PeerId::random(), PeerId::random());
+        for _ in 0..6 {
+            assert!(limiter.allow(alice));
+        }
+        assert!(!limiter.allow(alice));
+        assert!(limiter.allow(bob));
+
+        // One request's worth refills every ten seconds
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(limiter.allow(alice));
+        assert!(!limiter.allow(alice));
+
+        tokio::time::advance(Duration::from_mins(5)).await;
+        for _ in 0..6 {
+            assert!(limiter.allow(alice));
+        }
+        assert!(!limiter.allow(alice));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn prune_forgets_idle_peers() {
+        let mut limiter = RateLimiter::new(6);
+        let (alice, bob) = (PeerId::random(), PeerId::random());
+        assert!(limiter.allow(alice));
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert!(limiter.allow(bob));
+        tokio::time::advance(Duration::from_secs(30)).await;
+
+        limiter.prune();
+        assert!(!limiter.allowances.contains_key(&alice));
+        assert!(limiter.allowances.contains_key(&bob));
+    }
+}
@@ -0,0 +1,28 @@
+use std::{io::Write, path::Path};
+
+use libp2p::identity::Keypair;
+
+/// The name of the file in the data directory the node's keypair is kept in.
+const FILE_NAME: &str = "identity.key";
+
+/// Reads the node's keypair from `data_dir`, generating and saving one the
+/// first time round. Keeping it means the node keeps its peer ID between
+/// runs, so others can recognise it.
+pub(super) fn read_or_generate(data_dir: &Path) -> Result<Keypair, anyhow::Error> {
+    let path = data_dir.join(FILE_NAME);
+    if path.exists() {
+        return Ok(Keypair::from_protobuf_encoding(&std::fs::read(&path)?)?);
+    }
+
+    let keypair = Keypair::generate_ed25519();
+    std::fs::create_dir_all(data_dir)?;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    // Only we may read our private key
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(&path)?
+        .write_all(&keypair.to_protobuf_encoding()?)?;
+    Ok(keypair)
+}
@@ -1,35 +1,76 @@
+mod builder;
 mod client;
+mod config;
+mod error;
 mod event_loop;
+mod keypair;
+mod peer_list;
+mod receipt;
+mod reputation;
+mod storage;
+mod transfer;
+mod transport;
 mod username_store;
 
-use std::{hash::Hash, sync::Arc, time::Duration};
+use std::{
+    hash::Hash,
+    net::IpAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
 
-use futures::{channel::mpsc, Stream};
+use anyhow::bail;
+use futures::{channel::mpsc, stream::FusedStream, Stream, StreamExt};
 use libp2p::{
-    gossipsub, identify, identity, kad, mdns, noise, rendezvous,
+    allow_block_list, connection_limits, gossipsub, identify, identity, kad, mdns, multiaddr, pnet,
+    rendezvous,
     request_response::{self, ProtocolSupport},
-    swarm::NetworkBehaviour,
-    tcp, yamux, Multiaddr, StreamProtocol,
+    swarm::{behaviour::toggle::Toggle, NetworkBehaviour},
+    Multiaddr, PeerId, StreamProtocol,
 };
 use serde::{Deserialize, Serialize};
-use tokio::io::{Error as TokioError, ErrorKind as TokioErrorKind};
+use tokio::io::Error as TokioError;
 
-pub(crate) use client::Client;
-pub(crate) use event_loop::{Event, EventLoop};
+pub use builder::NodeBuilder;
+pub use client::Client;
+pub use config::{
+    ChatConfig, DiscoveryConfig, NodeConfig, RequestConfig, StorageConfig, TransportConfig,
+};
+pub use error::{ClientError, NetworkError};
+use event_loop::{DiscoveryIntervals, InboundLimits};
+pub use event_loop::{
+    Event, EventLoop, RendezvousStatus, Trade, TradeDirection, TradeId, TradeStatus,
+};
+pub use peer_list::PeerList;
+pub use receipt::{Receipt, ReceiptError, SignedReceipt, TradeParty};
+pub use reputation::{Attestation, Reputation};
+use reputation::{Ledger, SignedAttestation};
+pub use storage::StorageError;
+pub use transfer::TransferProgress;
+use transfer::{TradeCodec, Transfer, Transfers};
+pub use transport::{build_transport, read_swarm_key};
 
-const RENDEZVOUS_POINT_PORT_NUMBER: u16 = 62649;
+/// The TCP port the rendezvous server listens on.
+pub const RENDEZVOUS_POINT_PORT_NUMBER: u16 = 62649;
+/// The peer ID of the rendezvous server, derived from its fixed keypair.
 pub const RENDEZVOUS_POINT_PEER_ID: &str = "12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN";
 
 #[derive(NetworkBehaviour)]
 struct Behaviour {
-    trade_offering: request_response::cbor::Behaviour<TradeOffer, NoResponse>,
-    trade_response: request_response::cbor::Behaviour<TradeResponse, TradeResponseResponse>,
+    trade_offering: request_response::cbor::Behaviour<TradeOfferRequest, NoResponse>,
+    trade_response: request_response::Behaviour<TradeCodec>,
+    trade_receipt: request_response::cbor::Behaviour<SignedReceipt, NoResponse>,
     direct_messaging: request_response::cbor::Behaviour<DirectMessage, NoResponse>,
+    reputation: request_response::cbor::Behaviour<ReputationRequest, ReputationResponse>,
     kademlia: kad::Behaviour<kad::store::MemoryStore>,
     gossipsub: gossipsub::Behaviour,
     rendezvous: rendezvous::client::Behaviour,
     identify: identify::Behaviour,
-    mdns: mdns::tokio::Behaviour,
+    mdns: Toggle<mdns::tokio::Behaviour>,
+    block_list: allow_block_list::Behaviour<allow_block_list::BlockedPeers>,
+    connection_limits: connection_limits::Behaviour,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
@@ -38,18 +79,42 @@ pub(crate) struct TradeOffer {
     requested_file_name: String,
 }
 
+/// A trade offer as sent to the recipient. The size of the offered file is
+/// only the offerer's claim, and is not given by older peers.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct TradeOfferRequest {
+    #[serde(flatten)]
+    offer: TradeOffer,
+    #[serde(default)]
+    offered_file_size: Option<u64>,
+}
+
+/// The recipient's response to a trade offer. They give their `username` so
+/// that it can be included in the trade's receipt, which older peers don't.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct TradeResponse {
     requested_file_name: String,
     offered_file_name: String,
     requested_file_bytes: Option<Vec<u8>>,
+    #[serde(default)]
+    username: Option<String>,
+    /// Counts the bytes of the files as they are sent, see [`TradeCodec`].
+    #[serde(skip)]
+    transfer: Option<Arc<Transfer>>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// The offerer's file, sent once the recipient's has been received, along
+/// with the trade's receipt signed by the offerer. The recipient countersigns
+/// the receipt and sends it back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct TradeResponseResponse {
     offered_file_name: String,
     requested_file_name: String,
     offered_file_bytes: Option<Vec<u8>>,
+    #[serde(default)]
+    receipt: Option<SignedReceipt>,
+    #[serde(skip)]
+    transfer: Option<Arc<Transfer>>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -58,6 +123,33 @@ struct DirectMessage(String);
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub(crate) struct NoResponse();
 
+/// Asks a peer what they know of the trades of `subject`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ReputationRequest {
+    subject: PeerId,
+}
+
+/// `None` when the peer doesn't share reputations.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ReputationResponse(Option<SignedAttestation>);
+
+/// The stream of [`Event`]s emitted by a node's [`EventLoop`].
+pub struct EventStream(mpsc::Receiver<Event>);
+
+impl Stream for EventStream {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.poll_next_unpin(cx)
+    }
+}
+
+impl FusedStream for EventStream {
+    fn is_terminated(&self) -> bool {
+        self.0.is_terminated()
+    }
+}
+
 /// Creates the network components, namely:
 ///
 /// - The network client to interact with the network layer from anywhere within your application.
@@ -65,49 +157,116 @@ pub(crate) struct NoResponse();
 /// - The network event stream, e.g. for incoming requests.
 ///
 /// - The network task driving the network itself.
-pub(crate) fn new(
-    username: String,
-    rendezvous_ip_address: Option<String>,
-) -> Result<(Client, impl Stream<Item = Event>, EventLoop), anyhow::Error> {
+#[allow(clippy::too_many_lines)]
+fn new(
+    config: NodeConfig,
+    pre_shared_key: Option<pnet::PreSharedKey>,
+) -> Result<(Client, EventStream, EventLoop), anyhow::Error> {
+    let NodeConfig {
+        username,
+        data_dir,
+        transport: transport_config,
+        discovery: discovery_config,
+        chat: chat_config,
+        requests: request_config,
+        storage: storage_config,
+        ..
+    } = config;
+
     // Set a custom gossipsub configuration
     let gossipsub_config = gossipsub::ConfigBuilder::default()
-        // This is set to aid debugging by not cluttering the log space
-        .heartbeat_interval(Duration::from_secs(10))
+        .heartbeat_interval(chat_config.heartbeat_interval)
+        .max_transmit_size(chat_config.max_message_size)
         // This sets the kind of message validation. The default is Strict (enforce message signing)
         .validation_mode(gossipsub::ValidationMode::Strict)
+        // Messages are only forwarded once the event loop has checked them,
+        // see `EventLoop::handle_gossipsub_message`
+        .validate_messages()
         .build()
         // Temporary hack because `build` does not return a proper `std::error::Error`.
-        .map_err(|msg| TokioError::new(TokioErrorKind::Other, msg))?;
+        .map_err(TokioError::other)?;
 
-    let mut swarm = libp2p::SwarmBuilder::with_new_identity()
+    let request_response_config =
+        request_response::Config::default().with_request_timeout(request_config.timeout);
+    // File contents are CBOR encoded as an array of integers, taking up to two
+    // bytes per byte of the file, plus a little extra for the file names.
+    let max_file_message_size = request_config
+        .max_file_size
+        .saturating_mul(2)
+        .saturating_add(1024);
+
+    let keypair = match &data_dir {
+        Some(data_dir) => keypair::read_or_generate(data_dir)?,
+        None => identity::Keypair::generate_ed25519(),
+    };
+    // Attestations and receipts are signed with the node's own keypair, so
+    // that they can be checked against its peer ID
+    let mut swarm = libp2p::SwarmBuilder::with_existing_identity(keypair.clone())
         .with_tokio()
-        .with_tcp(
-            tcp::Config::default(),
-            noise::Config::new,
-            yamux::Config::default,
-        )?
-        .with_quic()
+        .with_other_transport(|keypair| {
+            transport::build_transport(
+                keypair,
+                pre_shared_key,
+                transport_config.quic,
+                transport_config.websocket,
+            )
+        })?
         .with_behaviour(|keypair: &identity::Keypair| {
             let peer_id = keypair.public().to_peer_id();
+            let mut kademlia_config = kad::Config::new(kad::PROTOCOL_NAME);
+            // Bootstrapping is driven by the event loop
+            kademlia_config.set_periodic_bootstrap_interval(None);
             Ok(Behaviour {
-                kademlia: kad::Behaviour::new(peer_id, kad::store::MemoryStore::new(peer_id)),
-                trade_offering: request_response::cbor::Behaviour::new(
+                kademlia: kad::Behaviour::with_config(
+                    peer_id,
+                    kad::store::MemoryStore::new(peer_id),
+                    kademlia_config,
+                ),
+                trade_offering: request_response::Behaviour::with_codec(
+                    cbor_codec(
+                        request_config.max_message_size,
+                        request_config.max_message_size,
+                    ),
                     [(StreamProtocol::new("/trade-offer/1"), ProtocolSupport::Full)],
-                    request_response::Config::default(),
+                    request_response_config.clone(),
                 ),
-                trade_response: request_response::cbor::Behaviour::new(
+                trade_response: request_response::Behaviour::with_codec(
+                    TradeCodec::new(cbor_codec(max_file_message_size, max_file_message_size)),
                     [(
                         StreamProtocol::new("/trade-response/1"),
                         ProtocolSupport::Full,
                     )],
-                    request_response::Config::default(),
+                    request_response_config.clone(),
+                ),
+                trade_receipt: request_response::Behaviour::with_codec(
+                    cbor_codec(
+                        request_config.max_message_size,
+                        request_config.max_message_size,
+                    ),
+                    [(
+                        StreamProtocol::new("/trade-receipt/1"),
+                        ProtocolSupport::Full,
+                    )],
+                    request_response_config.clone(),
                 ),
-                direct_messaging: request_response::cbor::Behaviour::new(
+                direct_messaging: request_response::Behaviour::with_codec(
+                    cbor_codec(
+                        request_config.max_message_size,
+                        request_config.max_message_size,
+                    ),
                     [(
                         StreamProtocol::new("/direct-message/1"),
                         ProtocolSupport::Full,
                     )],
-                    request_response::Config::default(),
+                    request_response_config.clone(),
+                ),
+                reputation: request_response::Behaviour::with_codec(
+                    cbor_codec(
+                        request_config.max_message_size,
+                        request_config.max_message_size,
+                    ),
+                    [(StreamProtocol::new("/reputation/1"), ProtocolSupport::Full)],
+                    request_response_config,
                 ),
                 gossipsub: gossipsub::Behaviour::new(
                     gossipsub::MessageAuthenticity::Signed(keypair.clone()),
@@ -118,13 +277,27 @@ pub(crate) fn new(
                     "rendezvous-identify/1.0.0".to_string(),
                     keypair.public(),
                 )),
-                mdns: mdns::tokio::Behaviour::new(
-                    mdns::Config::default(),
-                    keypair.public().to_peer_id(),
-                )?,
+                mdns: discovery_config
+                    .mdns
+                    .then(|| mdns::tokio::Behaviour::new(mdns::Config::default(), peer_id))
+                    .transpose()?
+                    .into(),
+                block_list: allow_block_list::Behaviour::default(),
+                connection_limits: connection_limits::Behaviour::new(
+                    connection_limits::ConnectionLimits::default()
+                        .with_max_established(Some(transport_config.max_connections))
+                        .with_max_established_per_peer(Some(
+                            transport_config.max_connections_per_peer,
+                        ))
+                        .with_max_pending_incoming(Some(
+                            transport_config.max_pending_incoming_connections,
+                        )),
+                ),
             })
         })?
-        .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+        .with_swarm_config(|c| {
+            c.with_idle_connection_timeout(transport_config.idle_connection_timeout)
+        })
         .build();
 
     // Set the DHT to serve records to incoming queries
@@ -133,42 +306,274 @@ pub(crate) fn new(
         .kademlia
         .set_mode(Some(kad::Mode::Server));
 
+    let peer_list = match &data_dir {
+        Some(data_dir) => PeerList::read(data_dir)?,
+        None => PeerList::default(),
+    };
+    for peer_id in &peer_list.blocked {
+        swarm.behaviour_mut().block_list.block_peer(*peer_id);
+    }
+    let ledger = match &data_dir {
+        Some(data_dir) => Ledger::read(data_dir)?,
+        None => Ledger::default(),
+    };
+
+    let transfers = Transfers::default();
+
     // Initialise inter thread communication
     let (command_sender, command_receiver) = mpsc::channel(0);
     let (event_sender, event_receiver) = mpsc::channel(0);
 
     // Globbal chat room
-    let topic = gossipsub::IdentTopic::new("chat-room");
+    let topic = gossipsub::IdentTopic::new(chat_config.topic);
     swarm.behaviour_mut().gossipsub.subscribe(&topic)?;
+    // With the default thresholds, gossip is no longer exchanged with peers
+    // scoring below -10, nothing is published to those below -50, and
+    // everything from those below -80 is ignored
+    swarm
+        .behaviour_mut()
+        .gossipsub
+        .with_peer_score(
+            peer_score_params(&topic),
+            gossipsub::PeerScoreThresholds::default(),
+        )
+        .map_err(TokioError::other)?;
 
-    // Listen on all interfaces and whatever port the OS assigns
-    swarm.listen_on("/ip4/0.0.0.0/udp/0/quic-v1".parse()?)?;
-    swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
+    if transport_config.listen_addresses.is_empty() {
+        // Not every host supports IPv6, so the default addresses are only
+        // bound where possible, as long as at least one of them is
+        let mut listen_error = None;
+        let mut is_listening = false;
+        for listen_address in default_listen_addresses(&transport_config) {
+            match swarm.listen_on(listen_address.clone()) {
+                Ok(_) => is_listening = true,
+                Err(error) => {
+                    tracing::warn!(%listen_address, "Failed to listen: {error}");
+                    listen_error = Some(error);
+                }
+            }
+        }
+        if let (false, Some(error)) = (is_listening, listen_error) {
+            return Err(error.into());
+        }
+    } else {
+        for listen_address in transport_config.listen_addresses {
+            swarm.listen_on(listen_address)?;
+        }
+    }
 
     // Connect to rendezvous server is specified on command line
-    let mut rendezvous_peer_id = None;
-    if let Some(rendezvous_ip_address) = rendezvous_ip_address {
-        rendezvous_peer_id = Some(RENDEZVOUS_POINT_PEER_ID.parse()?);
+    let mut rendezvous_point = None;
+    if let Some(rendezvous_address) = discovery_config.rendezvous_address {
+        let rendezvous_peer_id: PeerId = RENDEZVOUS_POINT_PEER_ID.parse()?;
+        let rendezvous_multi_address = parse_rendezvous_address(&rendezvous_address)?;
 
-        let rendezvous_multi_address: Multiaddr =
-            format!("/ip4/{rendezvous_ip_address}/tcp/{RENDEZVOUS_POINT_PORT_NUMBER}").parse()?;
+        // The rendezvous server also acts as a DHT server
+        swarm
+            .behaviour_mut()
+            .kademlia
+            .add_address(&rendezvous_peer_id, rendezvous_multi_address.clone());
 
-        swarm.dial(rendezvous_multi_address)?;
+        // Include the peer ID in the address so that failed dials can be
+        // attributed to the rendezvous server and retried.
+        let rendezvous_multi_address =
+            rendezvous_multi_address.with(multiaddr::Protocol::P2p(rendezvous_peer_id));
+
+        swarm.dial(rendezvous_multi_address.clone())?;
+        rendezvous_point = Some((rendezvous_peer_id, rendezvous_multi_address));
+    }
+
+    // Seed the DHT routing table with the given bootstrap nodes
+    for bootstrap_address in discovery_config.bootstrap_addresses {
+        let Some(multiaddr::Protocol::P2p(peer_id)) = bootstrap_address.iter().last() else {
+            bail!("Bootstrap address '{bootstrap_address}' must end with /p2p/<peer_id>");
+        };
+        swarm
+            .behaviour_mut()
+            .kademlia
+            .add_address(&peer_id, bootstrap_address);
     }
 
     Ok((
         Client {
             command_sender,
             username_store: Arc::default(),
+            local_peer_id: *swarm.local_peer_id(),
+            timeout: request_config.call_timeout,
+            transfers: transfers.clone(),
         },
-        event_receiver,
+        EventStream(event_receiver),
         EventLoop::new(
             swarm,
             command_receiver,
             event_sender,
             topic,
             username,
-            rendezvous_peer_id,
+            rendezvous_point,
+            storage::new(storage_config),
+            transfers,
+            keypair,
+            peer_list,
+            ledger,
+            data_dir,
+            DiscoveryIntervals {
+                rendezvous_discover: discovery_config.rendezvous_discover_interval,
+                bootstrap: discovery_config.bootstrap_interval,
+                random_walk: discovery_config.random_walk_interval,
+            },
+            InboundLimits {
+                requests_per_minute: request_config.max_requests_per_minute,
+                offers: request_config.max_inbound_offers,
+                offers_per_peer: request_config.max_inbound_offers_per_peer,
+                offer_timeout: request_config.inbound_offer_timeout,
+                chat_message_size: chat_config.max_message_size,
+                chat_message_age: chat_config.max_message_age,
+                share_reputation: request_config.share_reputation,
+            },
         ),
     ))
 }
+
+/// How gossipsub scores the peers we exchange chat messages with. Peers lose
+/// score for each message they forward which we reject, i.e. one which is
+/// malformed or too large, and a few such messages are enough to be ignored
+/// for a while. Messages by authors we have blocked are ignored rather than
+/// rejected, as the peers forwarding them can't know. Chat can be quiet for
+/// long stretches, so peers are not penalised for delivering few messages.
+fn peer_score_params(topic: &gossipsub::IdentTopic) -> gossipsub::PeerScoreParams {
+    let topic_params = gossipsub::TopicScoreParams {
+        topic_weight: 1.0,
+        time_in_mesh_weight: 0.01,
+        time_in_mesh_quantum: Duration::from_secs(1),
+        time_in_mesh_cap: 3600.0,
+        first_message_deliveries_weight: 1.0,
+        first_message_deliveries_cap: 20.0,
+        mesh_message_deliveries_weight: 0.0,
+        mesh_failure_penalty_weight: 0.0,
+        invalid_message_deliveries_weight: -10.0,
+        invalid_message_deliveries_decay: gossipsub::score_parameter_decay(Duration::from_hours(1)),
+        ..gossipsub::TopicScoreParams::default()
+    };
+    let mut params = gossipsub::PeerScoreParams::default();
+    params.topics.insert(topic.hash(), topic_params);
+    params
+}
+
+/// The codec of a CBOR request-response behaviour. libp2p only exports the
+/// behaviour, so the codec type is named through it.
+type CborCodec<Req, Resp> = <request_response::cbor::Behaviour<Req, Resp> as WithCodec>::Codec;
+
+trait WithCodec {
+    type Codec;
+}
+
+impl<C> WithCodec for request_response::Behaviour<C>
+where
+    C: request_response::Codec + Clone + Send + 'static,
+{
+    type Codec = C;
+}
+
+/// Creates a CBOR codec accepting requests and responses of at most the given
+/// number of bytes.
+fn cbor_codec<Req, Resp>(max_request_size: u64, max_response_size: u64) -> CborCodec<Req, Resp>
+where
+    Req: Send + Serialize + for<'de> Deserialize<'de> + 'static,
+    Resp: Send + Serialize + for<'de> Deserialize<'de> + 'static,
+{
+    CborCodec::<Req, Resp>::default()
+        .set_request_size_maximum(max_request_size)
+        .set_response_size_maximum(max_response_size)
+}
+
+/// The addresses to listen on when none are given explicitly: all IPv4 and
+/// IPv6 interfaces, over TCP and (if enabled) WebSocket and QUIC.
+fn default_listen_addresses(transport_config: &TransportConfig) -> Vec<Multiaddr> {
+    let mut listen_addresses = Vec::new();
+    for host in ["/ip4/0.0.0.0", "/ip6/::"] {
+        let host: Multiaddr = host.parse().expect("Host is a valid multiaddress");
+        listen_addresses.push(
+            host.clone()
+                .with(multiaddr::Protocol::Tcp(transport_config.port)),
+        );
+        if transport_config.websocket {
+            listen_addresses.push(
+                host.clone()
+                    .with(multiaddr::Protocol::Tcp(transport_config.websocket_port))
+                    .with(multiaddr::Protocol::Ws("/".into())),
+            );
+        }
+        if transport_config.quic {
+            listen_addresses.push(
+                host.with(multiaddr::Protocol::Udp(transport_config.port))
+                    .with(multiaddr::Protocol::QuicV1),
+            );
+        }
+    }
+    listen_addresses
+}
+
+/// Interprets the user supplied address of the rendezvous server. This can be
+/// a full multiaddress (e.g. `/dns4/example.com/tcp/443/wss`), or an IP address
+/// or hostname, which is assumed to be listening on the default TCP port.
+fn parse_rendezvous_address(address: &str) -> Result<Multiaddr, anyhow::Error> {
+    if address.starts_with('/') {
+        let mut multi_address: Multiaddr = address.parse()?;
+        // The peer ID of the rendezvous server is fixed, and added later on
+        if let Some(multiaddr::Protocol::P2p(_)) = multi_address.iter().last() {
+            multi_address.pop();
+        }
+        return Ok(multi_address);
+    }
+
+    let host = match address.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip_address)) => multiaddr::Protocol::Ip4(ip_address),
+        Ok(IpAddr::V6(ip_address)) => multiaddr::Protocol::Ip6(ip_address),
+        Err(_) => multiaddr::Protocol::Dns(address.to_owned().into()),
+    };
+
+    Ok(Multiaddr::empty()
+        .with(host)
+        .with(multiaddr::Protocol::Tcp(RENDEZVOUS_POINT_PORT_NUMBER)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rendezvous_address_accepts_hosts() {
+        assert_eq!(
+            parse_rendezvous_address("192.168.1.20").unwrap(),
+            "/ip4/192.168.1.20/tcp/62649".parse::<Multiaddr>().unwrap()
+        );
+        assert_eq!(
+            parse_rendezvous_address("::1").unwrap(),
+            "/ip6/::1/tcp/62649".parse::<Multiaddr>().unwrap()
+        );
+        assert_eq!(
+            parse_rendezvous_address("rendezvous.example.com").unwrap(),
+            "/dns/rendezvous.example.com/tcp/62649"
+                .parse::<Multiaddr>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_rendezvous_address_strips_peer_id() {
+        let address = format!("/ip4/10.0.0.1/tcp/4001/p2p/{RENDEZVOUS_POINT_PEER_ID}");
+        assert_eq!(
+            parse_rendezvous_address(&address).unwrap(),
+            "/ip4/10.0.0.1/tcp/4001".parse::<Multiaddr>().unwrap()
+        );
+        assert_eq!(
+            parse_rendezvous_address("/dns/example.com/tcp/4001").unwrap(),
+            "/dns/example.com/tcp/4001".parse::<Multiaddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_rendezvous_address_rejects_malformed_multiaddrs() {
+        assert!(parse_rendezvous_address("/ip4/not-an-address/tcp/4001").is_err());
+    }
+}
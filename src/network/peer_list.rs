@@ -0,0 +1,37 @@
+use std::{collections::BTreeSet, io, path::Path};
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+/// The name of the file in the data directory the peer list is kept in.
+pub(super) const FILE_NAME: &str = "peers.json";
+
+/// The peers we have blocked or muted, as listed by
+/// [`Client::peer_list`](super::Client::peer_list).
+///
+/// Blocked peers can not connect to us, and their requests and chat messages
+/// are dropped. Muted peers stay connected and may still offer trades, but
+/// their chat and direct messages are not reported.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeerList {
+    pub blocked: BTreeSet<PeerId>,
+    pub muted: BTreeSet<PeerId>,
+}
+
+impl PeerList {
+    /// Reads the peer list kept in `data_dir`, which is empty if none has
+    /// been saved yet.
+    pub(super) fn read(data_dir: &Path) -> Result<Self, anyhow::Error> {
+        match std::fs::read(data_dir.join(FILE_NAME)) {
+            Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// The contents of the file the peer list is kept in.
+    pub(super) fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).expect("Peer IDs serialize as strings")
+    }
+}
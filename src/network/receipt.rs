@@ -0,0 +1,228 @@
+use libp2p::{identity, PeerId};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The folder in the data directory receipts are kept in.
+pub(super) const DIRECTORY: &str = "receipts";
+
+/// The record of a completed trade, agreed on by both parties.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Receipt {
+    /// The party who offered the trade.
+    pub offerer: TradeParty,
+    /// The party who accepted it.
+    pub recipient: TradeParty,
+    /// When the trade completed, in seconds since the Unix epoch.
+    pub completed_at: u64,
+}
+
+/// One side of a trade, and the file they gave.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TradeParty {
+    pub peer_id: PeerId,
+    pub username: String,
+    pub file_name: String,
+    /// The SHA-256 hash of the file's contents, in hex.
+    pub file_hash: String,
+}
+
+/// A [`Receipt`] along with the signatures of the parties to the trade, as
+/// saved in the data directory. The receipt is kept exactly as it was
+/// signed, as JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SignedReceipt {
+    receipt: String,
+    signatures: Vec<ReceiptSignature>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReceiptSignature {
+    /// The signer's public key in its protobuf encoding, in hex.
+    public_key: String,
+    /// In hex.
+    signature: String,
+}
+
+/// Why a [`SignedReceipt`] could not be verified.
+#[derive(Debug, thiserror::Error)]
+pub enum ReceiptError {
+    /// The signed receipt is not a valid receipt.
+    #[error("The receipt is malformed: {0}")]
+    Malformed(#[from] serde_json::Error),
+    /// One of the parties to the trade has not signed the receipt, or their
+    /// signature is invalid.
+    #[error("The receipt is not signed by {peer_id}")]
+    Unsigned { peer_id: PeerId },
+}
+
+/// The SHA-256 hash of `contents`, in hex, as given in receipts.
+pub(super) fn hash(contents: &[u8]) -> String {
+    hex::encode(Sha256::digest(contents))
+}
+
+impl Receipt {
+    /// Signs the receipt with our `keypair`, for the other party to
+    /// countersign.
+    pub(super) fn sign(
+        &self,
+        keypair: &identity::Keypair,
+    ) -> Result<SignedReceipt, identity::SigningError> {
+        let mut signed = SignedReceipt {
+            receipt: serde_json::to_string(self).expect("Receipts serialize as JSON"),
+            signatures: Vec::new(),
+        };
+        signed.countersign(keypair)?;
+        Ok(signed)
+    }
+}
+
+impl SignedReceipt {
+    /// Adds our signature to the receipt.
+    pub(super) fn countersign(
+        &mut self,
+        keypair: &identity::Keypair,
+    ) -> Result<(), identity::SigningError> {
+        let signature = keypair.sign(self.receipt.as_bytes())?;
+        self.signatures.push(ReceiptSignature {
+            public_key: hex::encode(keypair.public().encode_protobuf()),
+            signature: hex::encode(signature),
+        });
+        Ok(())
+    }
+
+    /// The receipt, whether or not it has been signed.
+    pub(super) fn receipt(&self) -> Result<Receipt, ReceiptError> {
+        Ok(serde_json::from_str(&self.receipt)?)
+    }
+
+    /// The receipt, as signed, which identifies it.
+    pub(super) fn signed_text(&self) -> &str {
+        &self.receipt
+    }
+
+    /// The name of the file the receipt is saved in, unique to the receipt.
+    pub(super) fn file_name(&self) -> String {
+        format!("{}.json", &hash(self.receipt.as_bytes())[..16])
+    }
+
+    /// The contents of the file the receipt is saved in.
+    pub(super) fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).expect("Receipts serialize as JSON")
+    }
+
+    /// Whether `peer_id` has validly signed the receipt.
+    pub(super) fn is_signed_by(&self, peer_id: PeerId) -> bool {
+        self.signatures.iter().any(|signature| {
+            let (Ok(public_key), Ok(signature)) = (
+                hex::decode(&signature.public_key),
+                hex::decode(&signature.signature),
+            ) else {
+                return false;
+            };
+            identity::PublicKey::try_decode_protobuf(&public_key).is_ok_and(|public_key| {
+                public_key.to_peer_id() == peer_id
+                    && public_key.verify(self.receipt.as_bytes(), &signature)
+            })
+        })
+    }
+
+    /// The receipt, if it has been signed by both parties to the trade.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the receipt is malformed, or either party's signature is
+    /// missing or invalid.
+    pub fn verify(&self) -> Result<Receipt, ReceiptError> {
+        let receipt = self.receipt()?;
+        for peer_id in [receipt.offerer.peer_id, receipt.recipient.peer_id] {
+            if !self.is_signed_by(peer_id) {
+                return Err(ReceiptError::Unsigned { peer_id });
+            }
+        }
+        Ok(receipt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn party(keypair: &identity::Keypair, username: &str, contents: &[u8]) -> TradeParty {
+        TradeParty {
+            peer_id: keypair.public().to_peer_id(),
+            username: username.to_owned(),
+            file_name: format!("{username}.txt"),
+            file_hash: hash(contents),
+        }
+    }
+
+    fn receipt(offerer: &identity::Keypair, recipient: &identity::Keypair) -> Receipt {
+        Receipt {
+            offerer: party(offerer, "alice", b"alice's file"),
+            recipient: party(recipient, "bob", b"bob's file"),
+            completed_at: 1_700_000_000,
+        }
+    }
+
+    #[test]
+    fn receipts_signed_by_both_parties_verify() {
+        let (offerer, recipient) = (
+            identity::Keypair::generate_ed25519(),
+            identity::Keypair::generate_ed25519(),
+        );
+        let mut signed = receipt(&offerer, &recipient).sign(&offerer).unwrap();
+        assert!(matches!(
+            signed.verify(),
+            Err(ReceiptError::Unsigned { peer_id }) if peer_id == recipient.public().to_peer_id()
+        ));
+
+        signed.countersign(&recipient).unwrap();
+        assert_eq!(signed.verify().unwrap(), receipt(&offerer, &recipient));
+
+        // Saved receipts verify the same once read back
+        let saved: SignedReceipt = serde_json::from_slice(&signed.to_bytes()).unwrap();
+        assert_eq!(saved.verify().unwrap(), receipt(&offerer, &recipient));
+        assert_eq!(saved.file_name(), signed.file_name());
+    }
+
+    #[test]
+    fn signatures_by_other_peers_do_not_count() {
+        let (offerer, recipient) = (
+            identity::Keypair::generate_ed25519(),
+            identity::Keypair::generate_ed25519(),
+        );
+        let mut signed = receipt(&offerer, &recipient).sign(&offerer).unwrap();
+        signed
+            .countersign(&identity::Keypair::generate_ed25519())
+            .unwrap();
+        assert!(matches!(
+            signed.verify(),
+            Err(ReceiptError::Unsigned { .. })
+        ));
+    }
+
+    #[test]
+    fn tampered_receipts_are_refused() {
+        let (offerer, recipient) = (
+            identity::Keypair::generate_ed25519(),
+            identity::Keypair::generate_ed25519(),
+        );
+        let mut signed = receipt(&offerer, &recipient).sign(&offerer).unwrap();
+        signed.countersign(&recipient).unwrap();
+
+        let mut tampered = receipt(&offerer, &recipient);
+        tampered.recipient.file_hash = hash(b"something else");
+        signed.receipt = serde_json::to_string(&tampered).unwrap();
+        assert!(matches!(
+            signed.verify(),
+            Err(ReceiptError::Unsigned { .. })
+        ));
+
+        signed.receipt = "not a receipt".to_owned();
+        assert!(matches!(signed.verify(), Err(ReceiptError::Malformed(_))));
+    }
+}
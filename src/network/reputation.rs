@@ -0,0 +1,224 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use libp2p::{identity, PeerId};
+use serde::{Deserialize, Serialize};
+
+/// The name of the file in the data directory the reputation ledger is kept
+/// in.
+pub(super) const FILE_NAME: &str = "reputation.json";
+
+/// How the trades with a peer have gone, as far as our node has seen.
+///
+/// Only the peer's own part in a trade counts towards it, so trades we
+/// declined, or which failed because their file could not be stored, are
+/// not recorded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Reputation {
+    /// Trades in which the peer sent the file they were meant to.
+    pub completed: u32,
+    /// Trades we offered which the peer declined.
+    pub declined: u32,
+    /// Trades the peer agreed to which then fell through, e.g. because they
+    /// withheld their file or could no longer be reached.
+    pub failed: u32,
+    /// Trades in which the peer sent a file other than the one agreed on,
+    /// such as one larger than they claimed.
+    pub verification_failed: u32,
+}
+
+/// How a trade with a peer ended, as recorded in their [`Reputation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum TradeOutcome {
+    Completed,
+    Declined,
+    Failed,
+    VerificationFailed,
+}
+
+impl Reputation {
+    fn record(&mut self, outcome: TradeOutcome) {
+        let count = match outcome {
+            TradeOutcome::Completed => &mut self.completed,
+            TradeOutcome::Declined => &mut self.declined,
+            TradeOutcome::Failed => &mut self.failed,
+            TradeOutcome::VerificationFailed => &mut self.verification_failed,
+        };
+        *count = count.saturating_add(1);
+    }
+}
+
+/// The reputation of every peer we have traded with.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(super) struct Ledger(BTreeMap<PeerId, Reputation>);
+
+impl Ledger {
+    /// Reads the ledger kept in `data_dir`, which is empty if none has been
+    /// saved yet.
+    pub(super) fn read(data_dir: &Path) -> Result<Self, anyhow::Error> {
+        match std::fs::read(data_dir.join(FILE_NAME)) {
+            Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// The contents of the file the ledger is kept in.
+    pub(super) fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).expect("Peer IDs serialize as strings")
+    }
+
+    pub(super) fn get(&self, peer_id: &PeerId) -> Reputation {
+        self.0.get(peer_id).copied().unwrap_or_default()
+    }
+
+    pub(super) fn record(&mut self, peer_id: PeerId, outcome: TradeOutcome) {
+        self.0.entry(peer_id).or_default().record(outcome);
+    }
+}
+
+/// A peer's account of the reputation of another, as returned by
+/// [`Client::query_reputation`](super::Client::query_reputation). It has been
+/// checked to be signed by `issuer`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attestation {
+    /// The peer vouching for the reputation.
+    pub issuer: PeerId,
+    /// The peer the reputation is of.
+    pub subject: PeerId,
+    pub reputation: Reputation,
+    /// When the attestation was made, in seconds since the Unix epoch.
+    pub issued_at: u64,
+}
+
+/// An [`Attestation`] as sent between peers. The attestation is kept in its
+/// encoded form, so that the signature is checked against the exact bytes
+/// which were signed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SignedAttestation {
+    attestation: Vec<u8>,
+    /// The issuer's public key, in its protobuf encoding.
+    public_key: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl Attestation {
+    /// Attests that `subject` has `reputation`, signed with our `keypair`.
+    pub(super) fn sign(
+        keypair: &identity::Keypair,
+        subject: PeerId,
+        reputation: Reputation,
+    ) -> Result<SignedAttestation, identity::SigningError> {
+        let attestation = Self {
+            issuer: keypair.public().to_peer_id(),
+            subject,
+            reputation,
+            issued_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+        };
+        let attestation = serde_json::to_vec(&attestation).expect("Attestations serialize as JSON");
+        Ok(SignedAttestation {
+            signature: keypair.sign(&attestation)?,
+            attestation,
+            public_key: keypair.public().encode_protobuf(),
+        })
+    }
+}
+
+impl SignedAttestation {
+    /// The attestation, if it was signed by `issuer` and is about `subject`.
+    pub(super) fn verify(&self, issuer: PeerId, subject: PeerId) -> Option<Attestation> {
+        let public_key = identity::PublicKey::try_decode_protobuf(&self.public_key).ok()?;
+        if public_key.to_peer_id() != issuer
+            || !public_key.verify(&self.attestation, &self.signature)
+        {
+            return None;
+        }
+        let attestation: Attestation = serde_json::from_slice(&self.attestation).ok()?;
+        (attestation.issuer == issuer && attestation.subject == subject).then_some(attestation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reputation() -> Reputation {
+        Reputation {
+            completed: 3,
+            declined: 1,
+            failed: 0,
+            verification_failed: 0,
+        }
+    }
+
+    #[test]
+    fn signed_attestations_verify() {
+        let keypair = identity::Keypair::generate_ed25519();
+        let issuer = keypair.public().to_peer_id();
+        let subject = PeerId::random();
+
+        let signed = Attestation::sign(&keypair, subject, reputation()).unwrap();
+        let attestation = signed.verify(issuer, subject).unwrap();
+        assert_eq!(attestation.issuer, issuer);
+        assert_eq!(attestation.subject, subject);
+        assert_eq!(attestation.reputation, reputation());
+    }
+
+    #[test]
+    fn attestations_from_or_about_other_peers_are_refused() {
+        let keypair = identity::Keypair::generate_ed25519();
+        let issuer = keypair.public().to_peer_id();
+        let subject = PeerId::random();
+        let signed = Attestation::sign(&keypair, subject, reputation()).unwrap();
+
+        assert!(signed.verify(PeerId::random(), subject).is_none());
+        assert!(signed.verify(issuer, PeerId::random()).is_none());
+
+        // Signed by someone else, but claiming to be from the issuer
+        let impostor = identity::Keypair::generate_ed25519();
+        let mut forged = Attestation::sign(&impostor, subject, reputation()).unwrap();
+        forged.public_key = keypair.public().encode_protobuf();
+        assert!(forged.verify(issuer, subject).is_none());
+    }
+
+    #[test]
+    fn tampered_attestations_are_refused() {
+        let keypair = identity::Keypair::generate_ed25519();
+        let issuer = keypair.public().to_peer_id();
+        let subject = PeerId::random();
+        let mut signed = Attestation::sign(&keypair, subject, reputation()).unwrap();
+
+        let mut attestation: Attestation = serde_json::from_slice(&signed.attestation).unwrap();
+        attestation.reputation.completed = 100;
+        signed.attestation = serde_json::to_vec(&attestation).unwrap();
+        assert!(signed.verify(issuer, subject).is_none());
+    }
+
+    #[test]
+    fn ledger_counts_outcomes() {
+        let mut ledger = Ledger::default();
+        let peer_id = PeerId::random();
+        ledger.record(peer_id, TradeOutcome::Completed);
+        ledger.record(peer_id, TradeOutcome::Completed);
+        ledger.record(peer_id, TradeOutcome::VerificationFailed);
+
+        let ledger: Ledger = serde_json::from_slice(&ledger.to_bytes()).unwrap();
+        assert_eq!(
+            ledger.get(&peer_id),
+            Reputation {
+                completed: 2,
+                verification_failed: 1,
+                ..Reputation::default()
+            }
+        );
+        assert_eq!(ledger.get(&PeerId::random()), Reputation::default());
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+};
+
+use futures::{
+    channel::{mpsc, oneshot},
+    StreamExt,
+};
+use tokio::io::AsyncWriteExt;
+
+use super::StorageConfig;
+
+/// A handle to the storage task, which writes received files to disk so that
+/// the event loop never waits on the file system.
+#[derive(Clone)]
+pub(super) struct Storage {
+    write_sender: mpsc::UnboundedSender<WriteRequest>,
+    reserved_space: u64,
+}
+
+/// The storage task itself, see [`StorageTask::run`].
+pub(super) struct StorageTask {
+    config: StorageConfig,
+    write_receiver: mpsc::UnboundedReceiver<WriteRequest>,
+    writes: u64,
+}
+
+struct WriteRequest {
+    path: PathBuf,
+    contents: Vec<u8>,
+    result_sender: oneshot::Sender<Result<(), StorageError>>,
+}
+
+/// Failures to store a file received in a trade.
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    /// There is not enough free space to write the file, while leaving the
+    /// configured amount of space free.
+    #[error("Not enough space to write '{}', {required} bytes are required but only {available} are available", path.display())]
+    InsufficientSpace {
+        path: PathBuf,
+        required: u64,
+        available: u64,
+    },
+    /// The file could not be written.
+    #[error("Failed to write '{}': {error}", path.display())]
+    Io { path: PathBuf, error: io::Error },
+    /// The storage task is no longer running.
+    #[error("Storage is no longer running")]
+    Stopped,
+}
+
+/// Creates the storage handle and the task serving it.
+pub(super) fn new(config: StorageConfig) -> (Storage, StorageTask) {
+    let (write_sender, write_receiver) = mpsc::unbounded();
+    (
+        Storage {
+            write_sender,
+            reserved_space: config.reserved_space,
+        },
+        StorageTask {
+            config,
+            write_receiver,
+            writes: 0,
+        },
+    )
+}
+
+impl Storage {
+    /// Writes `contents` to `path`, replacing the file atomically once it has
+    /// been written in full. Any missing parent directories are created.
+    pub(super) async fn write(&self, path: PathBuf, contents: Vec<u8>) -> Result<(), StorageError> {
+        self.queue_write(path, contents).await
+    }
+
+    /// Queues a [`write`](Self::write) straight away, returning a future
+    /// which resolves once it is done. Writes happen in the order they are
+    /// queued, so later snapshots of a file are never overwritten by earlier
+    /// ones.
+    pub(super) fn queue_write(
+        &self,
+        path: PathBuf,
+        contents: Vec<u8>,
+    ) -> impl Future<Output = Result<(), StorageError>> + Send + 'static {
+        let (result_sender, result_receiver) = oneshot::channel();
+        let queued = self.write_sender.unbounded_send(WriteRequest {
+            path,
+            contents,
+            result_sender,
+        });
+        async move {
+            queued.map_err(|_| StorageError::Stopped)?;
+            result_receiver.await.map_err(|_| StorageError::Stopped)?
+        }
+    }
+
+    /// Checks whether a file of `size` bytes would currently fit at `path`,
+    /// so that a trade isn't accepted only for its file to be refused.
+    pub(super) fn check_space(&self, path: &Path, size: u64) -> Result<(), StorageError> {
+        check_space(path, size, self.reserved_space)
+    }
+}
+
+impl StorageTask {
+    /// Writes files one at a time until every [`Storage`] handle is dropped.
+    pub(super) async fn run(mut self) {
+        while let Some(request) = self.write_receiver.next().await {
+            let result = self.write(&request.path, &request.contents).await;
+            let _ = request.result_sender.send(result);
+        }
+    }
+
+    async fn write(&mut self, path: &Path, contents: &[u8]) -> Result<(), StorageError> {
+        let io_error = |error| StorageError::Io {
+            path: path.to_owned(),
+            error,
+        };
+
+        check_space(path, contents.len() as u64, self.config.reserved_space)?;
+
+        let Some(file_name) = path.file_name() else {
+            return Err(io_error(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "path does not name a file",
+            )));
+        };
+        let parent_directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        tokio::fs::create_dir_all(parent_directory)
+            .await
+            .map_err(io_error)?;
+
+        // Write to a temporary file next to the destination first, so that
+        // the destination is never left holding a partial file
+        self.writes += 1;
+        let mut temporary_name = file_name.to_owned();
+        temporary_name.push(format!(".{}-{}.part", std::process::id(), self.writes));
+        let temporary_path = parent_directory.join(temporary_name);
+
+        let result = self
+            .write_temporary_file(&temporary_path, contents)
+            .await
+            .and(tokio::fs::rename(&temporary_path, path).await);
+        if let Err(error) = result {
+            let _ = tokio::fs::remove_file(&temporary_path).await;
+            return Err(io_error(error));
+        }
+
+        if self.config.fsync {
+            // Persist the rename itself
+            sync_directory(parent_directory).await.map_err(io_error)?;
+        }
+        Ok(())
+    }
+
+    async fn write_temporary_file(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .await?;
+        file.write_all(contents).await?;
+        if self.config.fsync {
+            file.sync_all().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Fails if writing `size` bytes to `path` would leave less than
+/// `reserved_space` bytes free.
+fn check_space(path: &Path, size: u64, reserved_space: u64) -> Result<(), StorageError> {
+    let required = size.saturating_add(reserved_space);
+    match available_space(path) {
+        Some(available) if available < required => Err(StorageError::InsufficientSpace {
+            path: path.to_owned(),
+            required,
+            available,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// The number of bytes available to us on the file system `path` would be
+/// written to, if it can be determined.
+#[cfg(unix)]
+fn available_space(path: &Path) -> Option<u64> {
+    // The path itself may not exist yet, so check the closest ancestor
+    let existing_ancestor = path
+        .ancestors()
+        .skip(1)
+        .find(|ancestor| ancestor.as_os_str().is_empty() || ancestor.exists())?;
+    let existing_ancestor = if existing_ancestor.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        existing_ancestor
+    };
+    let stats = rustix::fs::statvfs(existing_ancestor).ok()?;
+    Some(stats.f_bavail.saturating_mul(stats.f_frsize))
+}
+
+#[cfg(not(unix))]
+fn available_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+#[cfg(unix)]
+async fn sync_directory(path: &Path) -> io::Result<()> {
+    tokio::fs::File::open(path).await?.sync_all().await
+}
+
+#[cfg(not(unix))]
+async fn sync_directory(_path: &Path) -> io::Result<()> {
+    // Directories can not be opened as files on other platforms
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_space_refuses_files_which_do_not_fit() {
+        let path = std::env::temp_dir().join("decent-share-test").join("file");
+        assert!(check_space(&path, 1, 0).is_ok());
+        assert!(matches!(
+            check_space(&path, u64::MAX, 0),
+            Err(StorageError::InsufficientSpace { .. })
+        ));
+        assert!(matches!(
+            check_space(&path, 1, u64::MAX),
+            Err(StorageError::InsufficientSpace { .. })
+        ));
+    }
+}
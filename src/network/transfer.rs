@@ -0,0 +1,336 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError, Weak,
+    },
+    task::{Context, Poll},
+};
+
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use libp2p::{request_response, PeerId, StreamProtocol};
+
+use super::{CborCodec, TradeId, TradeResponse, TradeResponseResponse};
+
+/// How many bytes are written at a time, and thus how often the progress of
+/// a file being sent is updated.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// How far the files of a trade have been transferred. Sizes are those of the
+/// files as encoded on the wire, which is up to twice their size on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferProgress {
+    pub trade_id: TradeId,
+    pub peer_id: PeerId,
+    /// The bytes of our file sent so far, out of `send_total`.
+    pub sent: u64,
+    pub send_total: u64,
+    /// The bytes of their file received so far, out of `receive_total`. The
+    /// total is estimated from the size they claimed for their file, and is
+    /// unknown if they didn't claim one.
+    pub received: u64,
+    pub receive_total: Option<u64>,
+}
+
+impl TransferProgress {
+    /// The share of the trade's bytes transferred so far, from 0 to 1, if the
+    /// total is known.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn ratio(&self) -> Option<f64> {
+        let total = self.send_total + self.receive_total?;
+        if total == 0 {
+            return Some(0.0);
+        }
+        let transferred = self.sent + self.received.min(self.receive_total?);
+        Some(transferred as f64 / total as f64)
+    }
+}
+
+/// The transfers in progress, shared by the node's codec and its clients. A
+/// transfer is listed until the stream it is sent over has closed.
+#[derive(Clone, Default)]
+pub(crate) struct Transfers {
+    transfers: Arc<Mutex<Vec<Weak<Transfer>>>>,
+}
+
+impl Transfers {
+    /// Starts tracking the files of `trade_id`, whose bytes are counted as
+    /// the returned [`Transfer`] is sent. `offered_file_size` is the size the
+    /// peer claimed for the file we are to receive, if any.
+    pub(crate) fn start(
+        &self,
+        trade_id: TradeId,
+        peer_id: PeerId,
+        offered_file_size: Option<u64>,
+    ) -> Arc<Transfer> {
+        let transfer = Arc::new(Transfer {
+            trade_id,
+            peer_id,
+            sent: AtomicU64::new(0),
+            send_total: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+            // File contents are CBOR encoded as an array of integers, taking
+            // up to two bytes per byte of the file
+            receive_total: offered_file_size.map(|size| size.saturating_mul(2)),
+        });
+        self.lock().push(Arc::downgrade(&transfer));
+        transfer
+    }
+
+    pub(crate) fn progress(&self) -> Vec<TransferProgress> {
+        let mut transfers = self.lock();
+        transfers.retain(|transfer| transfer.strong_count() > 0);
+        transfers
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|transfer| transfer.progress())
+            .collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Weak<Transfer>>> {
+        self.transfers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// The byte counts of a single trade's transfer, see [`Transfers::start`].
+#[derive(Debug)]
+pub(crate) struct Transfer {
+    trade_id: TradeId,
+    peer_id: PeerId,
+    sent: AtomicU64,
+    send_total: AtomicU64,
+    received: AtomicU64,
+    receive_total: Option<u64>,
+}
+
+impl Transfer {
+    fn progress(&self) -> TransferProgress {
+        TransferProgress {
+            trade_id: self.trade_id,
+            peer_id: self.peer_id,
+            sent: self.sent.load(Ordering::Relaxed),
+            send_total: self.send_total.load(Ordering::Relaxed),
+            received: self.received.load(Ordering::Relaxed),
+            receive_total: self.receive_total,
+        }
+    }
+
+    /// Writes the encoded message `data`, counting its bytes as they are sent.
+    async fn send<T>(&self, io: &mut T, data: &[u8]) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        self.send_total.store(data.len() as u64, Ordering::Relaxed);
+        for chunk in data.chunks(CHUNK_SIZE) {
+            io.write_all(chunk).await?;
+            self.sent.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+/// The CBOR codec of the trade response protocol, counting the bytes of the
+/// files exchanged. The recipient of an offer tracks both its request and the
+/// response to it, while the offerer only tracks its response, as the request
+/// can't be told apart from others until it has fully arrived.
+#[derive(Clone)]
+pub(crate) struct TradeCodec {
+    inner: CborCodec<TradeResponse, TradeResponseResponse>,
+    /// The transfer of the request sent over this codec's stream, which the
+    /// response is counted towards.
+    transfer: Option<Arc<Transfer>>,
+}
+
+impl TradeCodec {
+    pub(crate) fn new(inner: CborCodec<TradeResponse, TradeResponseResponse>) -> Self {
+        Self {
+            inner,
+            transfer: None,
+        }
+    }
+}
+
+#[async_trait]
+impl request_response::Codec for TradeCodec {
+    type Protocol = StreamProtocol;
+    type Request = TradeResponse;
+    type Response = TradeResponseResponse;
+
+    async fn read_request<T>(
+        &mut self,
+        protocol: &Self::Protocol,
+        io: &mut T,
+    ) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        self.inner.read_request(protocol, io).await
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        protocol: &Self::Protocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        match self.transfer.take() {
+            Some(transfer) => {
+                let mut io = CountingReader { io, transfer };
+                self.inner.read_response(protocol, &mut io).await
+            }
+            None => self.inner.read_response(protocol, io).await,
+        }
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        protocol: &Self::Protocol,
+        io: &mut T,
+        mut request: Self::Request,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let Some(transfer) = request.transfer.take() else {
+            return self.inner.write_request(protocol, io, request).await;
+        };
+        let mut data = Vec::new();
+        self.inner
+            .write_request(protocol, &mut data, request)
+            .await?;
+        transfer.send(io, &data).await?;
+        self.transfer = Some(transfer);
+        Ok(())
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        protocol: &Self::Protocol,
+        io: &mut T,
+        mut response: Self::Response,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let Some(transfer) = response.transfer.take() else {
+            return self.inner.write_response(protocol, io, response).await;
+        };
+        let mut data = Vec::new();
+        self.inner
+            .write_response(protocol, &mut data, response)
+            .await?;
+        transfer.send(io, &data).await
+    }
+}
+
+/// Counts the bytes read from `io` towards the transfer they belong to.
+struct CountingReader<'a, T> {
+    io: &'a mut T,
+    transfer: Arc<Transfer>,
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for CountingReader<'_, T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut *self.io).poll_read(cx, buf);
+        if let Poll::Ready(Ok(read)) = poll {
+            self.transfer
+                .received
+                .fetch_add(read as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::request_response::Codec;
+
+    use super::*;
+
+    fn codec() -> TradeCodec {
+        TradeCodec::new(CborCodec::default())
+    }
+
+    #[tokio::test]
+    async fn transfers_count_the_bytes_of_the_request_and_its_response() {
+        let transfers = Transfers::default();
+        let peer_id = PeerId::random();
+        let transfer = transfers.start("1".parse().unwrap(), peer_id, Some(3));
+        let protocol = StreamProtocol::new("/trade-response/1");
+        let mut recipient_codec = codec();
+
+        let request = TradeResponse {
+            requested_file_name: "requested.txt".to_owned(),
+            offered_file_name: "offered.txt".to_owned(),
+            requested_file_bytes: Some(vec![1, 2, 255]),
+            username: None,
+            transfer: Some(Arc::clone(&transfer)),
+        };
+        let mut sent = Vec::new();
+        recipient_codec
+            .write_request(&protocol, &mut sent, request)
+            .await
+            .unwrap();
+
+        let response = TradeResponseResponse {
+            offered_file_name: "offered.txt".to_owned(),
+            requested_file_name: "requested.txt".to_owned(),
+            offered_file_bytes: Some(vec![4, 5, 6]),
+            receipt: None,
+            transfer: None,
+        };
+        let mut received = Vec::new();
+        codec()
+            .write_response(&protocol, &mut received, response)
+            .await
+            .unwrap();
+        let [progress] = transfers.progress()[..] else {
+            panic!("The transfer should be listed");
+        };
+        assert_eq!(progress.sent, sent.len() as u64);
+        assert_eq!(progress.send_total, sent.len() as u64);
+        assert_eq!(progress.received, 0);
+        assert_eq!(progress.receive_total, Some(6));
+
+        recipient_codec
+            .read_response(&protocol, &mut received.as_slice())
+            .await
+            .unwrap();
+        assert_eq!(transfer.progress().received, received.len() as u64);
+        drop(transfer);
+        assert!(transfers.progress().is_empty());
+    }
+
+    #[test]
+    fn the_ratio_includes_both_files() {
+        let progress = TransferProgress {
+            trade_id: "1".parse().unwrap(),
+            peer_id: PeerId::random(),
+            sent: 10,
+            send_total: 10,
+            received: 40,
+            receive_total: Some(30),
+        };
+        assert_eq!(progress.ratio(), Some(1.0));
+        let progress = TransferProgress {
+            received: 5,
+            ..progress
+        };
+        assert_eq!(progress.ratio(), Some(0.375));
+        let progress = TransferProgress {
+            receive_total: None,
+            ..progress
+        };
+        assert_eq!(progress.ratio(), None);
+    }
+}
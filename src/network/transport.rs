@@ -0,0 +1,81 @@
+use std::{error::Error, path::Path, time::Duration};
+
+use either::Either;
+use libp2p::{
+    core::{muxing::StreamMuxerBox, transport::Boxed, upgrade},
+    dns, identity, noise,
+    pnet::{PnetConfig, PreSharedKey},
+    quic, tcp, websocket, yamux, PeerId, Transport,
+};
+
+/// Builds the transport used to reach other peers. TCP connections are always
+/// supported, WebSocket and QUIC connections are supported if enabled. TCP
+/// and WebSocket are able to resolve `/dns`, `/dns4` and `/dns6` addresses.
+///
+/// When a pre-shared key is given, every TCP and WebSocket connection is
+/// encrypted with it before anything else is exchanged, so that only peers
+/// holding the same key can connect. QUIC cannot be used together with a
+/// pre-shared key.
+///
+/// # Errors
+///
+/// Fails if the system's DNS configuration can not be read, or if the
+/// keypair can not be used for authentication.
+pub fn build_transport(
+    keypair: &identity::Keypair,
+    pre_shared_key: Option<PreSharedKey>,
+    enable_quic: bool,
+    enable_websocket: bool,
+) -> Result<Boxed<(PeerId, StreamMuxerBox)>, Box<dyn Error + Send + Sync>> {
+    let dns_tcp =
+        || dns::tokio::Transport::system(tcp::tokio::Transport::new(tcp::Config::default()));
+
+    let base_transport = if enable_websocket {
+        Either::Left(websocket::WsConfig::new(dns_tcp()?).or_transport(dns_tcp()?))
+    } else {
+        Either::Right(dns_tcp()?)
+    };
+    let base_transport = match pre_shared_key {
+        Some(pre_shared_key) => Either::Left(
+            base_transport
+                .and_then(move |socket, _| PnetConfig::new(pre_shared_key).handshake(socket)),
+        ),
+        None => Either::Right(base_transport),
+    };
+
+    let tcp_transport = base_transport
+        .upgrade(upgrade::Version::V1Lazy)
+        .authenticate(noise::Config::new(keypair)?)
+        .multiplex(yamux::Config::default())
+        .timeout(Duration::from_secs(20))
+        .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)));
+
+    if !enable_quic || pre_shared_key.is_some() {
+        return Ok(tcp_transport.boxed());
+    }
+
+    let quic_transport = quic::tokio::Transport::new(quic::Config::new(keypair))
+        .map(|(peer_id, connection), _| (peer_id, StreamMuxerBox::new(connection)));
+
+    Ok(quic_transport
+        .or_transport(tcp_transport)
+        .map(|either, _| either.into_inner())
+        .boxed())
+}
+
+/// Reads a pre-shared key from a swarm key file, in the format used by other
+/// libp2p implementations:
+///
+/// ```text
+/// /key/swarm/psk/1.0.0/
+/// /base16/
+/// <64 hexadecimal digits>
+/// ```
+///
+/// # Errors
+///
+/// Fails if the file can not be read or does not contain a valid key.
+pub fn read_swarm_key(path: &Path) -> Result<PreSharedKey, anyhow::Error> {
+    let swarm_key = std::fs::read_to_string(path)?;
+    Ok(swarm_key.trim().parse()?)
+}
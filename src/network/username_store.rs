@@ -17,6 +17,10 @@ impl UsernameStore {
         self.username_peer_id_map.get(username)
     }
 
+    pub fn usernames(&self) -> impl Iterator<Item = &String> {
+        self.username_peer_id_map.keys()
+    }
+
     pub fn insert(&mut self, username: String, peer_id: PeerId) {
         self.username_peer_id_map.insert(username.clone(), peer_id);
         self.peer_id_username_map.insert(peer_id, username);
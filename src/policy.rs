@@ -0,0 +1,373 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use decent_share::{Client, Event, PeerId, TradeId};
+use futures::{channel::mpsc, SinkExt, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+/// Rules for answering trade offers without waiting for a human, as given in
+/// the `trade_policy` table of the config file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct TradePolicyConfig {
+    /// Where requested files are looked up by name. Accept rules never match
+    /// when it isn't set.
+    pub shared_folder: Option<PathBuf>,
+    /// Where files received in automatically accepted trades are written,
+    /// under the name they were offered with. Defaults to the current
+    /// directory.
+    pub download_folder: Option<PathBuf>,
+    /// A file each decision is appended to, one JSON object per line.
+    pub log_file: Option<PathBuf>,
+    /// Checked in order, with the first matching rule deciding. Offers no rule
+    /// matches are left for a human.
+    pub rules: Vec<TradeRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TradeRule {
+    pub action: TradeAction,
+    /// The usernames the rule applies to. It applies to everyone when not
+    /// given.
+    pub users: Option<Vec<String>>,
+    /// Whether the rule only applies to users not named by any rule's
+    /// `users`, including peers whose username can't be verified.
+    #[serde(default)]
+    pub unknown_users: bool,
+    /// The rule only applies to offers of files of at most this many bytes.
+    /// Offers from peers which don't say how large their file is never match.
+    pub max_offered_file_size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum TradeAction {
+    Accept,
+    Decline,
+}
+
+/// A trade offer as seen by the rules.
+struct Offer {
+    trade_id: TradeId,
+    peer_id: PeerId,
+    /// The offerer's username, if they are the peer it is registered to.
+    username: Option<String>,
+    offered_file_name: String,
+    offered_file_size: Option<u64>,
+    requested_file_name: String,
+}
+
+/// An automated decision, as written to the log file.
+#[derive(Serialize)]
+struct Decision<'a> {
+    time: u64,
+    trade_id: TradeId,
+    peer_id: PeerId,
+    username: Option<&'a str>,
+    offered_file_name: &'a str,
+    requested_file_name: &'a str,
+    action: TradeAction,
+    rule: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Answers the trade offers from `network_events` which a rule in `config`
+/// matches. Every event, including answered offers, is passed on through the
+/// returned stream.
+pub(crate) fn spawn(
+    config: TradePolicyConfig,
+    network_client: Client,
+    mut network_events: impl Stream<Item = Event> + Unpin + Send + 'static,
+) -> mpsc::Receiver<Event> {
+    let (mut event_sender, event_receiver) = mpsc::channel(0);
+    let config = Arc::new(config);
+    tokio::task::spawn(async move {
+        while let Some(event) = network_events.next().await {
+            if let Event::InboundTradeOffer {
+                trade_id,
+                peer_id,
+                offered_file_name,
+                offered_file_size,
+                requested_file_name,
+                ..
+            } = &event
+            {
+                if !config.rules.is_empty() {
+                    let offer = Offer {
+                        trade_id: *trade_id,
+                        peer_id: *peer_id,
+                        username: None,
+                        offered_file_name: offered_file_name.clone(),
+                        offered_file_size: *offered_file_size,
+                        requested_file_name: requested_file_name.clone(),
+                    };
+                    tokio::task::spawn(answer(config.clone(), network_client.clone(), offer));
+                }
+            }
+            if event_sender.send(event).await.is_err() {
+                break;
+            }
+        }
+    });
+    event_receiver
+}
+
+async fn answer(config: Arc<TradePolicyConfig>, mut network_client: Client, mut offer: Offer) {
+    offer.username = verified_username(&mut network_client, offer.peer_id).await;
+    let Some((rule, action)) = config
+        .rules
+        .iter()
+        .enumerate()
+        .find(|(_, rule)| rule.matches(&config, &offer))
+        .map(|(index, rule)| (index, rule.action))
+    else {
+        return;
+    };
+
+    let result = match action {
+        TradeAction::Accept => accept(&config, &mut network_client, &offer).await,
+        TradeAction::Decline => network_client
+            .decline_trade(offer.trade_id)
+            .await
+            .map_err(anyhow::Error::from),
+    };
+    let username = offer.username.as_deref();
+    // Logged rather than printed, as printing would draw over the terminal UI
+    match &result {
+        Ok(()) => tracing::info!(
+            "Automatically {} trade {} from {} by rule {}",
+            match action {
+                TradeAction::Accept => "accepted",
+                TradeAction::Decline => "declined",
+            },
+            offer.trade_id,
+            username.map_or_else(|| offer.peer_id.to_string(), str::to_owned),
+            rule + 1,
+        ),
+        Err(error) => tracing::warn!(
+            "Failed to automatically answer trade {} by rule {}: {error}",
+            offer.trade_id,
+            rule + 1,
+        ),
+    }
+
+    if let Some(log_file) = &config.log_file {
+        let decision = Decision {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+            trade_id: offer.trade_id,
+            peer_id: offer.peer_id,
+            username,
+            offered_file_name: &offer.offered_file_name,
+            requested_file_name: &offer.requested_file_name,
+            action,
+            rule: rule + 1,
+            error: result.err().map(|error| error.to_string()),
+        };
+        if let Err(error) = log(log_file, &decision).await {
+            tracing::warn!(
+                "Failed to log trade decision to {}: {error}",
+                log_file.display()
+            );
+        }
+    }
+}
+
+/// The username of `peer_id`, if it is the peer the username is registered
+/// to. Any peer can claim any username as its own, so the claim is checked
+/// against the registration.
+async fn verified_username(network_client: &mut Client, peer_id: PeerId) -> Option<String> {
+    let username = network_client.get_username(peer_id).await.ok()?;
+    let registered_peer_id = network_client.get_peer_id(username.clone()).await.ok()?;
+    (registered_peer_id == peer_id).then_some(username)
+}
+
+impl TradeRule {
+    fn matches(&self, config: &TradePolicyConfig, offer: &Offer) -> bool {
+        if let Some(users) = &self.users {
+            match &offer.username {
+                Some(username) if users.contains(username) => {}
+                _ => return false,
+            }
+        }
+        if self.unknown_users
+            && offer
+                .username
+                .as_ref()
+                .is_some_and(|username| config.names(username))
+        {
+            return false;
+        }
+        if let Some(max_size) = self.max_offered_file_size {
+            match offer.offered_file_size {
+                Some(size) if size <= max_size => {}
+                _ => return false,
+            }
+        }
+        // Only accept trades which can be carried out, leaving the rest to
+        // later rules or a human
+        match self.action {
+            TradeAction::Accept => {
+                config.shared_file(&offer.requested_file_name).is_some()
+                    && config
+                        .download_path(&offer.offered_file_name)
+                        .is_some_and(|path| !path.exists())
+            }
+            TradeAction::Decline => true,
+        }
+    }
+}
+
+impl TradePolicyConfig {
+    /// Whether any rule names `username` among its `users`.
+    fn names(&self, username: &String) -> bool {
+        self.rules
+            .iter()
+            .filter_map(|rule| rule.users.as_ref())
+            .any(|users| users.contains(username))
+    }
+
+    fn shared_file(&self, file_name: &str) -> Option<PathBuf> {
+        let path = self
+            .shared_folder
+            .as_ref()?
+            .join(plain_file_name(file_name)?);
+        path.is_file().then_some(path)
+    }
+
+    fn download_path(&self, file_name: &str) -> Option<PathBuf> {
+        let folder = self.download_folder.as_deref().unwrap_or(Path::new("."));
+        Some(folder.join(plain_file_name(file_name)?))
+    }
+}
+
+/// `file_name`, if it names a file rather than a path which could lead out of
+/// a folder.
+fn plain_file_name(file_name: &str) -> Option<&Path> {
+    let path = Path::new(file_name);
+    let mut components = path.components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Some(path),
+        _ => None,
+    }
+}
+
+async fn accept(
+    config: &TradePolicyConfig,
+    network_client: &mut Client,
+    offer: &Offer,
+) -> Result<(), anyhow::Error> {
+    let (Some(requested_file_path), Some(offered_file_path)) = (
+        config.shared_file(&offer.requested_file_name),
+        config.download_path(&offer.offered_file_name),
+    ) else {
+        anyhow::bail!("The trade's files are no longer available");
+    };
+    let requested_file_bytes = tokio::fs::read(requested_file_path).await?;
+    network_client
+        .accept_trade(offer.trade_id, requested_file_bytes, offered_file_path)
+        .await?;
+    Ok(())
+}
+
+async fn log(log_file: &Path, decision: &Decision<'_>) -> Result<(), anyhow::Error> {
+    let mut line = serde_json::to_vec(decision)?;
+    line.push(b'\n');
+    tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)
+        .await?
+        .write_all(&line)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offer(username: Option<&str>, offered_file_size: Option<u64>) -> Offer {
+        Offer {
+            trade_id: serde_json::from_str("1").unwrap(),
+            peer_id: PeerId::random(),
+            username: username.map(str::to_owned),
+            offered_file_name: "offered.txt".to_owned(),
+            offered_file_size,
+            requested_file_name: "requested.txt".to_owned(),
+        }
+    }
+
+    #[test]
+    fn plain_file_name_rejects_paths() {
+        assert_eq!(plain_file_name("file.txt"), Some(Path::new("file.txt")));
+        assert_eq!(plain_file_name("..file"), Some(Path::new("..file")));
+        assert_eq!(plain_file_name(""), None);
+        assert_eq!(plain_file_name("."), None);
+        assert_eq!(plain_file_name(".."), None);
+        assert_eq!(plain_file_name("../file.txt"), None);
+        assert_eq!(plain_file_name("folder/file.txt"), None);
+        assert_eq!(plain_file_name("/etc/passwd"), None);
+    }
+
+    #[test]
+    fn rules_match_users_and_sizes() {
+        let config = TradePolicyConfig::default();
+        let rule = TradeRule {
+            action: TradeAction::Decline,
+            users: Some(vec!["mallory".to_owned()]),
+            unknown_users: false,
+            max_offered_file_size: Some(100),
+        };
+
+        assert!(rule.matches(&config, &offer(Some("mallory"), Some(100))));
+        assert!(!rule.matches(&config, &offer(Some("mallory"), Some(101))));
+        assert!(!rule.matches(&config, &offer(Some("mallory"), None)));
+        assert!(!rule.matches(&config, &offer(Some("alice"), Some(1))));
+        assert!(!rule.matches(&config, &offer(None, Some(1))));
+    }
+
+    #[test]
+    fn accept_rules_need_a_shared_file() {
+        let rule = TradeRule {
+            action: TradeAction::Accept,
+            users: None,
+            unknown_users: false,
+            max_offered_file_size: None,
+        };
+        assert!(!rule.matches(&TradePolicyConfig::default(), &offer(None, None)));
+    }
+
+    #[test]
+    fn unknown_user_rules_skip_users_named_by_any_rule() {
+        let config = TradePolicyConfig {
+            rules: vec![
+                TradeRule {
+                    action: TradeAction::Accept,
+                    users: Some(vec!["alice".to_owned()]),
+                    unknown_users: false,
+                    max_offered_file_size: None,
+                },
+                TradeRule {
+                    action: TradeAction::Decline,
+                    users: None,
+                    unknown_users: true,
+                    max_offered_file_size: None,
+                },
+            ],
+            ..TradePolicyConfig::default()
+        };
+        let rule = &config.rules[1];
+
+        assert!(!rule.matches(&config, &offer(Some("alice"), Some(1))));
+        assert!(rule.matches(&config, &offer(Some("mallory"), Some(1))));
+        assert!(rule.matches(&config, &offer(None, Some(1))));
+    }
+}
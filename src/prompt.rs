@@ -0,0 +1,186 @@
+use std::path::PathBuf;
+
+use decent_share::{Client, TradeDirection, TradeStatus};
+use rustyline::{
+    completion::{Completer, FilenameCompleter, Pair},
+    config::{CompletionType, Config},
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    history::DefaultHistory,
+    validate::Validator,
+    Context, Editor, Helper,
+};
+use tokio::runtime::Handle;
+
+const ACTIONS: [&str; 14] = [
+    "send",
+    "dm",
+    "trade",
+    "accept",
+    "decline",
+    "trades",
+    "block",
+    "unblock",
+    "mute",
+    "unmute",
+    "blocked",
+    "reputation",
+    "verify-receipt",
+    "jobs",
+];
+const MAX_HISTORY_SIZE: usize = 1000;
+
+/// Reads actions from the terminal with line editing, history and tab
+/// completion, passing each to `on_command`, until the user presses Ctrl-D.
+///
+/// History is kept between sessions in `~/.decent_share_history`. This blocks
+/// the calling thread while waiting for input.
+pub(crate) fn read_commands(
+    helper: CommandHelper,
+    mut on_command: impl FnMut(String),
+) -> Result<(), ReadlineError> {
+    let config = Config::builder()
+        .auto_add_history(true)
+        .completion_type(CompletionType::List)
+        .max_history_size(MAX_HISTORY_SIZE)?
+        .build();
+    let mut editor: Editor<CommandHelper, DefaultHistory> = Editor::with_config(config)?;
+    editor.set_helper(Some(helper));
+
+    let history_path =
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".decent_share_history"));
+    if let Some(history_path) = &history_path {
+        // There is no history yet the first time round
+        let _ = editor.load_history(history_path);
+    }
+
+    loop {
+        match editor.readline("> ") {
+            Ok(command) => {
+                if let Some(history_path) = &history_path {
+                    if let Err(error) = editor.save_history(history_path) {
+                        eprintln!("Error saving command history: {error}");
+                    }
+                }
+                on_command(command);
+            }
+            // Ctrl-C only discards the line being typed
+            Err(ReadlineError::Interrupted) => {}
+            Err(ReadlineError::Eof) => return Ok(()),
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Completes action names, usernames, pending trade offers and file paths,
+/// depending on which argument of which action is being typed.
+pub(crate) struct CommandHelper {
+    network_client: Client,
+    runtime: Handle,
+    filenames: FilenameCompleter,
+}
+
+impl CommandHelper {
+    /// `runtime` is used to ask the node for its pending trades, as
+    /// completion happens outside of any task.
+    pub(crate) fn new(network_client: Client, runtime: Handle) -> Self {
+        Self {
+            network_client,
+            runtime,
+            filenames: FilenameCompleter::new(),
+        }
+    }
+
+    /// The candidates for argument number `index` of `action`.
+    fn candidates(&self, action: Option<&str>, index: usize) -> Vec<Pair> {
+        match (action, index) {
+            (_, 0) => ACTIONS.iter().map(|action| candidate(action)).collect(),
+            (Some("dm" | "block" | "unblock" | "mute" | "unmute" | "reputation"), 1)
+            | (Some("reputation"), 2)
+            | (Some("trade"), 3) => self
+                .network_client
+                .known_usernames()
+                .iter()
+                .map(|username| candidate(username))
+                .collect(),
+            (Some("accept" | "decline"), 1) => {
+                let mut network_client = self.network_client.clone();
+                let trades = self
+                    .runtime
+                    .block_on(network_client.trades())
+                    .unwrap_or_default();
+                trades
+                    .into_iter()
+                    .filter(|trade| {
+                        trade.direction == TradeDirection::Inbound
+                            && trade.status == TradeStatus::Offered
+                    })
+                    .map(|trade| Pair {
+                        display: format!(
+                            "{} (their '{}' for your '{}')",
+                            trade.id, trade.offered_file_name, trade.requested_file_name
+                        ),
+                        replacement: format!("{} ", trade.id),
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Completer for CommandHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let before_cursor = &line[..pos];
+        let start = before_cursor
+            .rfind(char::is_whitespace)
+            .map_or(0, |index| index + 1);
+        let arguments: Vec<&str> = before_cursor[..start].split_whitespace().collect();
+        let index = arguments.len();
+
+        let action = arguments.first().map(|action| action.to_lowercase());
+        let is_path = matches!(
+            (action.as_deref(), index),
+            (Some("trade"), 2 | 5) | (Some("accept"), 2 | 3) | (Some("verify-receipt"), 1)
+        );
+        if is_path {
+            return self.filenames.complete(line, pos, ctx);
+        }
+
+        let word = &before_cursor[start..];
+        let mut candidates: Vec<Pair> = self
+            .candidates(action.as_deref(), index)
+            .into_iter()
+            .filter(|candidate| candidate.replacement.starts_with(word))
+            .collect();
+        candidates.sort_by(|a, b| a.display.cmp(&b.display));
+        candidates.dedup_by(|a, b| a.display == b.display);
+        Ok((start, candidates))
+    }
+}
+
+/// Completes `word`, followed by a space ready for the next argument.
+fn candidate(word: &str) -> Pair {
+    Pair {
+        display: word.to_owned(),
+        replacement: format!("{word} "),
+    }
+}
+
+impl Hinter for CommandHelper {
+    type Hint = String;
+}
+
+impl Highlighter for CommandHelper {}
+
+impl Validator for CommandHelper {}
+
+impl Helper for CommandHelper {}
@@ -0,0 +1,847 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    time::Duration,
+};
+
+use crossterm::event::{
+    Event as TerminalEvent, EventStream as TerminalEventStream, KeyCode, KeyEvent, KeyEventKind,
+    KeyModifiers,
+};
+use decent_share::{
+    Client, ClientError, Event, PeerId, RendezvousStatus, TradeDirection, TradeId, TradeStatus,
+};
+use futures::{channel::mpsc, Stream, StreamExt};
+use libp2p::gossipsub;
+use ratatui::{
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Gauge, List, ListItem, ListState, Paragraph},
+    DefaultTerminal, Frame,
+};
+
+use crate::{
+    action::{
+        handle_accept_trade, handle_decline_trade, handle_peer_action, handle_peer_list,
+        handle_reputation, handle_send, handle_trade, handle_verify_receipt, PeerAction,
+    },
+    interface::{
+        count_trades, describe_peer_list, describe_receipt, describe_reputation, describe_trade,
+        split_string, ACCEPT_USAGE, DECLINE_USAGE, DM_USAGE, PEER_ACTION_USAGE, REPUTATION_USAGE,
+        SEND_USAGE, TRADE_USAGE, VERIFY_RECEIPT_USAGE,
+    },
+    jobs::Jobs,
+};
+
+/// How many lines each scrolling pane keeps before dropping the oldest.
+const MAX_LINES: usize = 1000;
+/// How often the running transfers are redrawn.
+const TICK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Runs the full-screen interface until the user quits or the network stops.
+pub(crate) async fn run(
+    network_client: Client,
+    network_events: impl Stream<Item = Event> + Unpin,
+) -> Result<(), anyhow::Error> {
+    let terminal = ratatui::init();
+    let result = App::new(network_client).run(terminal, network_events).await;
+    ratatui::restore();
+
+    match result? {
+        Some(message) => Err(anyhow::anyhow!(message)),
+        None => Ok(()),
+    }
+}
+
+/// Sent to the interface by the tasks it spawns to carry out user actions.
+enum Update {
+    Status(String),
+    /// The result of looking up a peer's username, which is retried on the
+    /// peer's next event if it failed.
+    Username {
+        peer_id: PeerId,
+        username: Option<String>,
+    },
+    OwnChat(String),
+    OwnDirectMessage {
+        username: String,
+        message: String,
+    },
+    /// The inbound trades which are still awaiting our response.
+    PendingTrades(Vec<TradeId>),
+    /// Quits the interface, printing the message once the terminal is
+    /// restored.
+    Fatal(String),
+}
+
+/// Who wrote a chat or direct message.
+enum Author {
+    Me,
+    Peer(PeerId),
+}
+
+struct PendingTrade {
+    trade_id: TradeId,
+    peer_id: PeerId,
+    offered_file_name: String,
+    requested_file_name: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Input,
+    Trades,
+}
+
+/// The two paths needed to accept the selected trade, asked for after
+/// pressing `a` in the trades pane.
+struct AcceptPrompt {
+    trade_id: TradeId,
+    offered_file_name: String,
+    requested_file_name: String,
+}
+
+struct App {
+    network_client: Client,
+    jobs: Jobs,
+    update_sender: mpsc::UnboundedSender<Update>,
+    update_receiver: mpsc::UnboundedReceiver<Update>,
+
+    usernames: HashMap<PeerId, String>,
+    resolving: HashSet<PeerId>,
+    peers: BTreeSet<PeerId>,
+    chat: Vec<(Author, String)>,
+    direct_messages: Vec<(Author, String)>,
+    trades: Vec<PendingTrade>,
+    trade_list: ListState,
+    status: Vec<String>,
+    rendezvous: Option<String>,
+
+    input: String,
+    focus: Focus,
+    accept_prompt: Option<AcceptPrompt>,
+    quit: bool,
+    /// Printed once the terminal is restored, after quitting because of an
+    /// error.
+    fatal: Option<String>,
+}
+
+impl App {
+    fn new(network_client: Client) -> Self {
+        let (update_sender, update_receiver) = mpsc::unbounded();
+        Self {
+            network_client,
+            jobs: Jobs::default(),
+            update_sender,
+            update_receiver,
+            usernames: HashMap::new(),
+            resolving: HashSet::new(),
+            peers: BTreeSet::new(),
+            chat: Vec::new(),
+            direct_messages: Vec::new(),
+            trades: Vec::new(),
+            trade_list: ListState::default(),
+            status: vec![
+                "Type an action below, Tab to switch to the trades pane, Esc to quit".to_owned(),
+            ],
+            rendezvous: None,
+            input: String::new(),
+            focus: Focus::Input,
+            accept_prompt: None,
+            quit: false,
+            fatal: None,
+        }
+    }
+
+    /// Returns the message to exit with, if the interface had to quit because
+    /// of an error.
+    async fn run(
+        mut self,
+        mut terminal: DefaultTerminal,
+        mut network_events: impl Stream<Item = Event> + Unpin,
+    ) -> Result<Option<String>, anyhow::Error> {
+        let mut terminal_events = TerminalEventStream::new();
+        let mut tick = tokio::time::interval(TICK_INTERVAL);
+
+        while !self.quit {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            tokio::select! {
+                terminal_event = terminal_events.next() => match terminal_event {
+                    Some(Ok(TerminalEvent::Key(key))) if key.kind == KeyEventKind::Press => {
+                        self.handle_key(key);
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(error)) => return Err(error.into()),
+                    None => break,
+                },
+                network_event = network_events.next() => match network_event {
+                    Some(event) => self.handle_network_event(event),
+                    None => break,
+                },
+                update = self.update_receiver.next() => {
+                    if let Some(update) = update {
+                        self.handle_update(update);
+                    }
+                }
+                // Redraws the progress of running transfers
+                _ = tick.tick() => {}
+            }
+        }
+
+        Ok(self.fatal)
+    }
+
+    fn name(&self, peer_id: &PeerId) -> String {
+        if let Some(username) = self.usernames.get(peer_id) {
+            username.clone()
+        } else {
+            let peer_id = peer_id.to_base58();
+            format!("…{}", &peer_id[peer_id.len().saturating_sub(8)..])
+        }
+    }
+
+    /// Looks up the username of `peer_id` in the background, if it isn't
+    /// already known.
+    fn resolve(&mut self, peer_id: PeerId) {
+        if self.usernames.contains_key(&peer_id) || !self.resolving.insert(peer_id) {
+            return;
+        }
+        let mut network_client = self.network_client.clone();
+        let update_sender = self.update_sender.clone();
+        tokio::task::spawn(async move {
+            let username = network_client.get_username(peer_id).await.ok();
+            let _ = update_sender.unbounded_send(Update::Username { peer_id, username });
+        });
+    }
+
+    fn log(&mut self, line: String) {
+        push_line(&mut self.status, line);
+    }
+
+    fn handle_network_event(&mut self, event: Event) {
+        match event {
+            Event::InboundTradeOffer {
+                trade_id,
+                peer_id,
+                offered_file_name,
+                requested_file_name,
+                reputation,
+                ..
+            } => {
+                self.resolve(peer_id);
+                self.log(format!(
+                    "Trade offer {trade_id} from {} ({}): receive '{offered_file_name}', provide '{requested_file_name}'",
+                    self.name(&peer_id),
+                    count_trades(&reputation)
+                ));
+                self.trades.push(PendingTrade {
+                    trade_id,
+                    peer_id,
+                    offered_file_name,
+                    requested_file_name,
+                });
+                if self.trade_list.selected().is_none() {
+                    self.trade_list.select(Some(0));
+                }
+            }
+            Event::InboundTradeResponse {
+                trade_id,
+                peer_id,
+                offered_file_name,
+                requested_file_name,
+                was_accepted,
+            } => {
+                self.resolve(peer_id);
+                let username = self.name(&peer_id);
+                if was_accepted {
+                    self.log(format!(
+                        "{username} has accepted your trade {trade_id} for '{offered_file_name}', '{requested_file_name}' is now available at the path you specified"
+                    ));
+                } else {
+                    self.log(format!(
+                        "{username} has declined your trade {trade_id} for '{offered_file_name}'"
+                    ));
+                }
+            }
+            Event::InboundDirectMessage { peer_id, message } => {
+                self.resolve(peer_id);
+                push_line(&mut self.direct_messages, (Author::Peer(peer_id), message));
+            }
+            Event::InboundChat { peer_id, message } => {
+                self.resolve(peer_id);
+                push_line(&mut self.chat, (Author::Peer(peer_id), message));
+            }
+            Event::RegistrationRequest { username } => self.register(username),
+            Event::RendezvousStatusChanged(status) => {
+                self.rendezvous = Some(match status {
+                    RendezvousStatus::Connecting => "connecting".to_owned(),
+                    RendezvousStatus::Registered { .. } => "registered".to_owned(),
+                    RendezvousStatus::RegistrationFailed { retry_in, .. } => {
+                        format!("rejected, retrying in {}s", retry_in.as_secs())
+                    }
+                    RendezvousStatus::Disconnected { retry_in } => {
+                        format!("disconnected, retrying in {}s", retry_in.as_secs())
+                    }
+                });
+            }
+            Event::ReceiptSaved { trade_id, path, .. } => self.log(format!(
+                "The receipt for trade {trade_id} was saved to {}",
+                path.display()
+            )),
+            Event::Error(error) => self.log(format!("Network error: {error}")),
+            Event::PeerConnected { peer_id } => {
+                self.peers.insert(peer_id);
+                self.resolve(peer_id);
+            }
+            Event::PeerDisconnected { peer_id } => {
+                self.peers.remove(&peer_id);
+            }
+            // Completion is already reported by the response to our offer, or
+            // by the accept action
+            Event::TradeCompleted { .. } => {}
+        }
+    }
+
+    fn register(&mut self, username: String) {
+        let mut network_client = self.network_client.clone();
+        let update_sender = self.update_sender.clone();
+        tokio::task::spawn(async move {
+            let update = match network_client.get_peer_id(username.clone()).await {
+                // We may have registered it ourselves in an earlier run
+                Ok(peer_id) if peer_id != network_client.local_peer_id() => Update::Fatal(format!("The username '{username}' is already registered on the network, try using a different one")),
+                Ok(_) | Err(ClientError::UnknownUser(_)) => {
+                    match network_client.register_username(username.clone()).await {
+                        Ok(()) => Update::Status(format!("Successfully registered as {username}")),
+                        Err(error) => Update::Status(format!(
+                            "Failed to register username, will try again soon: {error}"
+                        )),
+                    }
+                }
+                Err(error) => Update::Status(format!(
+                    "Failed to check whether the username is taken: {error}"
+                )),
+            };
+            let _ = update_sender.unbounded_send(update);
+        });
+    }
+
+    fn handle_update(&mut self, update: Update) {
+        match update {
+            Update::Status(line) => self.log(line),
+            Update::Username { peer_id, username } => {
+                self.resolving.remove(&peer_id);
+                if let Some(username) = username {
+                    self.usernames.insert(peer_id, username);
+                }
+            }
+            Update::OwnChat(message) => push_line(&mut self.chat, (Author::Me, message)),
+            Update::OwnDirectMessage { username, message } => {
+                push_line(
+                    &mut self.direct_messages,
+                    (Author::Me, format!("(to {username}) {message}")),
+                );
+            }
+            Update::PendingTrades(trade_ids) => {
+                self.trades
+                    .retain(|trade| trade_ids.contains(&trade.trade_id));
+                self.select_trade(0);
+            }
+            Update::Fatal(message) => {
+                self.fatal = Some(message);
+                self.quit = true;
+            }
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        if key.code == KeyCode::Esc
+            || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL))
+        {
+            if self.accept_prompt.take().is_some() {
+                self.input.clear();
+            } else {
+                self.quit = true;
+            }
+            return;
+        }
+        if key.code == KeyCode::Tab {
+            self.focus = match self.focus {
+                Focus::Input => Focus::Trades,
+                Focus::Trades => Focus::Input,
+            };
+            return;
+        }
+
+        match self.focus {
+            Focus::Input => match key.code {
+                KeyCode::Char(character) => self.input.push(character),
+                KeyCode::Backspace => {
+                    self.input.pop();
+                }
+                KeyCode::Enter => {
+                    let input = std::mem::take(&mut self.input);
+                    match self.accept_prompt.take() {
+                        Some(prompt) => self.submit_accept(prompt, &input),
+                        None => self.submit(&input),
+                    }
+                }
+                _ => {}
+            },
+            Focus::Trades => match key.code {
+                KeyCode::Up => self.select_trade(-1),
+                KeyCode::Down => self.select_trade(1),
+                KeyCode::Char('a') => {
+                    if let Some(trade) = self.selected_trade() {
+                        self.accept_prompt = Some(AcceptPrompt {
+                            trade_id: trade.trade_id,
+                            offered_file_name: trade.offered_file_name.clone(),
+                            requested_file_name: trade.requested_file_name.clone(),
+                        });
+                        self.input.clear();
+                        self.focus = Focus::Input;
+                    }
+                }
+                KeyCode::Char('d') => {
+                    if let Some(trade) = self.selected_trade() {
+                        let command = format!("decline {}", trade.trade_id);
+                        self.submit(&command);
+                    }
+                }
+                _ => {}
+            },
+        }
+    }
+
+    fn selected_trade(&self) -> Option<&PendingTrade> {
+        self.trade_list
+            .selected()
+            .and_then(|index| self.trades.get(index))
+    }
+
+    /// Moves the trade selection by `offset`, keeping it within the list.
+    fn select_trade(&mut self, offset: isize) {
+        if self.trades.is_empty() {
+            self.trade_list.select(None);
+            return;
+        }
+        let selected = self
+            .trade_list
+            .selected()
+            .unwrap_or(0)
+            .saturating_add_signed(offset)
+            .min(self.trades.len() - 1);
+        self.trade_list.select(Some(selected));
+    }
+
+    /// Completes an `accept` started from the trades pane, where `input`
+    /// holds the path to place the offered file at and the path of the
+    /// requested file.
+    fn submit_accept(&mut self, prompt: AcceptPrompt, input: &str) {
+        let paths = split_string(input);
+        let [offered_file_path, requested_file_path] = paths.as_slice() else {
+            self.log("Two paths are needed to accept a trade".to_owned());
+            self.accept_prompt = Some(prompt);
+            return;
+        };
+        let command = format!(
+            "accept {} {offered_file_path} {requested_file_path}",
+            prompt.trade_id
+        );
+        self.submit(&command);
+    }
+
+    /// Carries out an action typed by the user in the background, the same
+    /// actions as are read from stdin without the interface.
+    #[allow(clippy::too_many_lines)]
+    fn submit(&mut self, command: &str) {
+        let arguments = split_string(command);
+        let Some(action) = arguments.first() else {
+            return;
+        };
+        let action = action.to_lowercase();
+        if action == "jobs" {
+            self.log("Running actions are shown in the transfers pane".to_owned());
+            return;
+        }
+        let usage = match action.as_str() {
+            "send" => SEND_USAGE,
+            "dm" => DM_USAGE,
+            "trade" => TRADE_USAGE,
+            "accept" => ACCEPT_USAGE,
+            "decline" => DECLINE_USAGE,
+            "block" | "unblock" | "mute" | "unmute" => PEER_ACTION_USAGE,
+            "reputation" => REPUTATION_USAGE,
+            "verify-receipt" => VERIFY_RECEIPT_USAGE,
+            "trades" | "blocked" => "",
+            action => {
+                self.log(format!("Unknown action '{action}'"));
+                return;
+            }
+        };
+        let argument_count = match action.as_str() {
+            "trades" | "blocked" => 1,
+            "send" | "decline" | "block" | "unblock" | "mute" | "unmute" | "reputation"
+            | "verify-receipt" => 2,
+            "dm" => 3,
+            "accept" => 4,
+            _ => 6,
+        };
+        if arguments.len() < argument_count {
+            self.log(usage.to_owned());
+            return;
+        }
+
+        let job = self.jobs.start(command);
+        let mut network_client = self.network_client.clone();
+        let update_sender = self.update_sender.clone();
+        tokio::task::spawn(async move {
+            let network_client = &mut network_client;
+            let job_id = job.id;
+            let updates = match action.as_str() {
+                "send" => match handle_send(&arguments[1], network_client).await {
+                    Ok(()) => vec![Update::OwnChat(arguments[1].clone())],
+                    Err(ClientError::Publish(gossipsub::PublishError::InsufficientPeers)) => {
+                        vec![Update::Status(
+                            "No peers are connected, unable to publish chat!".to_owned(),
+                        )]
+                    }
+                    Err(error) => vec![Update::Status(format!("Error sending chat: {error}"))],
+                },
+                "dm" => match network_client
+                    .direct_message(arguments[1].clone(), arguments[2].clone())
+                    .await
+                {
+                    Ok(()) => vec![Update::OwnDirectMessage {
+                        username: arguments[1].clone(),
+                        message: arguments[2].clone(),
+                    }],
+                    Err(error) => vec![Update::Status(format!(
+                        "Error sending direct message: {error}"
+                    ))],
+                },
+                "trade" => {
+                    let _ = update_sender.unbounded_send(Update::Status(format!(
+                        "[job {job_id}] Offering '{}' to {}...",
+                        arguments[1], arguments[3]
+                    )));
+                    match handle_trade(
+                        &arguments[1],
+                        &arguments[2],
+                        &arguments[3],
+                        &arguments[4],
+                        &arguments[5],
+                        network_client,
+                    )
+                    .await
+                    {
+                        Ok(trade_id) => vec![Update::Status(format!(
+                            "[job {job_id}] Trade offer {trade_id} sent to {}",
+                            arguments[3]
+                        ))],
+                        Err(error) => vec![Update::Status(format!(
+                            "[job {job_id}] Error offering trade: {error}"
+                        ))],
+                    }
+                }
+                "accept" => {
+                    let _ = update_sender.unbounded_send(Update::Status(format!(
+                        "[job {job_id}] Accepting trade {}, waiting for the offered file...",
+                        arguments[1]
+                    )));
+                    match handle_accept_trade(
+                        &arguments[1],
+                        &arguments[2],
+                        &arguments[3],
+                        network_client,
+                    )
+                    .await
+                    {
+                        Ok(()) => vec![Update::Status(format!(
+                            "[job {job_id}] Trade {} complete, the offered file is now available at '{}'",
+                            arguments[1], arguments[2]
+                        ))],
+                        Err(error) => vec![Update::Status(format!(
+                            "[job {job_id}] Error accepting trade: {error}"
+                        ))],
+                    }
+                }
+                "decline" => match handle_decline_trade(&arguments[1], network_client).await {
+                    Ok(()) => vec![Update::Status(format!("Declined trade {}", arguments[1]))],
+                    Err(error) => {
+                        vec![Update::Status(format!("Error declining trade: {error}"))]
+                    }
+                },
+                "block" | "unblock" | "mute" | "unmute" => {
+                    let Some(peer_action) = PeerAction::parse(&action) else {
+                        return;
+                    };
+                    let username = &arguments[1];
+                    match handle_peer_action(peer_action, username, network_client).await {
+                        Ok(()) => vec![Update::Status(format!(
+                            "{username} has been {}",
+                            peer_action.outcome()
+                        ))],
+                        Err(error) => {
+                            vec![Update::Status(format!(
+                                "Error updating {username}: {error}"
+                            ))]
+                        }
+                    }
+                }
+                "blocked" => match handle_peer_list(network_client).await {
+                    Ok((blocked, muted)) => describe_peer_list(&blocked, &muted)
+                        .into_iter()
+                        .map(Update::Status)
+                        .collect(),
+                    Err(error) => vec![Update::Status(format!(
+                        "Error listing blocked users: {error}"
+                    ))],
+                },
+                "reputation" => {
+                    let username = &arguments[1];
+                    let from = arguments.get(2).map(String::as_str);
+                    match handle_reputation(username, from, network_client).await {
+                        Ok(reputation) => vec![Update::Status(describe_reputation(
+                            username,
+                            from,
+                            &reputation,
+                        ))],
+                        Err(error) => vec![Update::Status(format!(
+                            "Error fetching the reputation of {username}: {error}"
+                        ))],
+                    }
+                }
+                "verify-receipt" => match handle_verify_receipt(&arguments[1]) {
+                    Ok(receipt) => describe_receipt(&receipt)
+                        .into_iter()
+                        .map(Update::Status)
+                        .collect(),
+                    Err(error) => vec![Update::Status(format!("Invalid receipt: {error}"))],
+                },
+                _ => match network_client.trades().await {
+                    Ok(trades) if trades.is_empty() => {
+                        vec![Update::Status("No pending trades".to_owned())]
+                    }
+                    Ok(trades) => {
+                        let mut updates = Vec::new();
+                        for trade in trades {
+                            let username = network_client
+                                .get_username(trade.peer_id)
+                                .await
+                                .unwrap_or_else(|_| trade.peer_id.to_string());
+                            updates.push(Update::Status(describe_trade(&trade, &username)));
+                        }
+                        updates
+                    }
+                    Err(error) => vec![Update::Status(format!("Error listing trades: {error}"))],
+                },
+            };
+            if matches!(action.as_str(), "accept" | "decline") {
+                if let Ok(trades) = network_client.trades().await {
+                    let trade_ids = trades
+                        .into_iter()
+                        .filter(|trade| {
+                            trade.direction == TradeDirection::Inbound
+                                && trade.status == TradeStatus::Offered
+                        })
+                        .map(|trade| trade.id)
+                        .collect();
+                    let _ = update_sender.unbounded_send(Update::PendingTrades(trade_ids));
+                }
+            }
+            drop(job);
+            for update in updates {
+                let _ = update_sender.unbounded_send(update);
+            }
+        });
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn draw(&mut self, frame: &mut Frame) {
+        let transfers = self.network_client.transfers();
+        let transfers_height = u16::try_from(transfers.len())
+            .unwrap_or(u16::MAX)
+            .clamp(1, 5)
+            + 2;
+
+        let [main_area, transfers_area, status_area, input_area] = Layout::vertical([
+            Constraint::Min(8),
+            Constraint::Length(transfers_height),
+            Constraint::Length(6),
+            Constraint::Length(3),
+        ])
+        .areas(frame.area());
+        let [messages_area, side_area] =
+            Layout::horizontal([Constraint::Percentage(70), Constraint::Percentage(30)])
+                .areas(main_area);
+        let [chat_area, direct_messages_area] =
+            Layout::vertical([Constraint::Percentage(60), Constraint::Percentage(40)])
+                .areas(messages_area);
+        let [peers_area, trades_area] =
+            Layout::vertical([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(side_area);
+
+        let chat = self.message_lines(&self.chat, chat_area);
+        frame.render_widget(
+            Paragraph::new(chat).block(Block::bordered().title(" Chat ")),
+            chat_area,
+        );
+        let direct_messages = self.message_lines(&self.direct_messages, direct_messages_area);
+        frame.render_widget(
+            Paragraph::new(direct_messages).block(Block::bordered().title(" Direct messages ")),
+            direct_messages_area,
+        );
+
+        let peers_title = match &self.rendezvous {
+            Some(rendezvous) => format!(" Peers ({}) · rendezvous {rendezvous} ", self.peers.len()),
+            None => format!(" Peers ({}) ", self.peers.len()),
+        };
+        let peers: Vec<ListItem> = self
+            .peers
+            .iter()
+            .map(|peer_id| ListItem::new(self.name(peer_id)))
+            .collect();
+        frame.render_widget(
+            List::new(peers).block(Block::bordered().title(peers_title)),
+            peers_area,
+        );
+
+        let trades: Vec<ListItem> = self
+            .trades
+            .iter()
+            .map(|trade| {
+                ListItem::new(format!(
+                    "[{}] {}: '{}' for '{}'",
+                    trade.trade_id,
+                    self.name(&trade.peer_id),
+                    trade.offered_file_name,
+                    trade.requested_file_name
+                ))
+            })
+            .collect();
+        let trades_title = if self.focus == Focus::Trades {
+            " Trades · a accept · d decline "
+        } else {
+            " Trades "
+        };
+        frame.render_stateful_widget(
+            List::new(trades)
+                .block(focused_block(trades_title, self.focus == Focus::Trades))
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED)),
+            trades_area,
+            &mut self.trade_list,
+        );
+
+        let transfers_block = Block::bordered().title(" Transfers ");
+        let inner = transfers_block.inner(transfers_area);
+        frame.render_widget(transfers_block, transfers_area);
+        let rows = Layout::vertical(vec![Constraint::Length(1); transfers.len()]).split(inner);
+        for (transfer, row) in transfers.iter().zip(rows.iter()) {
+            let received = match transfer.receive_total {
+                Some(total) => format!(
+                    "{} of ~{}",
+                    format_bytes(transfer.received),
+                    format_bytes(total)
+                ),
+                None => format_bytes(transfer.received),
+            };
+            let label = format!(
+                "[trade {}] {}: {} of {} sent, {received} received",
+                transfer.trade_id,
+                self.name(&transfer.peer_id),
+                format_bytes(transfer.sent),
+                format_bytes(transfer.send_total)
+            );
+            let gauge = Gauge::default()
+                .gauge_style(Style::default().fg(Color::Cyan))
+                .ratio(transfer.ratio().unwrap_or(0.0).clamp(0.0, 1.0))
+                .label(label);
+            frame.render_widget(gauge, *row);
+        }
+
+        let status = tail(&self.status, status_area)
+            .iter()
+            .map(|line| Line::raw(line.as_str()))
+            .collect::<Vec<_>>();
+        frame.render_widget(
+            Paragraph::new(status).block(Block::bordered().title(" Status ")),
+            status_area,
+        );
+
+        let input_title = match &self.accept_prompt {
+            Some(prompt) => format!(
+                " Accept trade {}: <path_to_place_'{}'> <path_to_source_'{}'> ",
+                prompt.trade_id, prompt.offered_file_name, prompt.requested_file_name
+            ),
+            None => " Action ".to_owned(),
+        };
+        frame.render_widget(
+            Paragraph::new(self.input.as_str())
+                .block(focused_block(&input_title, self.focus == Focus::Input)),
+            input_area,
+        );
+        if self.focus == Focus::Input {
+            let cursor = u16::try_from(self.input.chars().count()).unwrap_or(u16::MAX);
+            frame.set_cursor_position((
+                input_area.x.saturating_add(1).saturating_add(cursor),
+                input_area.y + 1,
+            ));
+        }
+    }
+
+    /// The most recent messages which fit into `area`, prefixed with their
+    /// author.
+    fn message_lines<'a>(&self, messages: &'a [(Author, String)], area: Rect) -> Vec<Line<'a>> {
+        tail(messages, area)
+            .iter()
+            .map(|(author, message)| {
+                let author = match author {
+                    Author::Me => Span::styled("you", Style::default().fg(Color::Green)),
+                    Author::Peer(peer_id) => {
+                        Span::styled(self.name(peer_id), Style::default().fg(Color::Yellow))
+                    }
+                };
+                Line::from(vec![author, Span::raw(": "), Span::raw(message.as_str())])
+            })
+            .collect()
+    }
+}
+
+fn focused_block(title: &str, focused: bool) -> Block<'static> {
+    let block = Block::bordered().title(title.to_owned());
+    if focused {
+        block.border_style(Style::default().fg(Color::Cyan))
+    } else {
+        block
+    }
+}
+
+fn push_line<T>(lines: &mut Vec<T>, line: T) {
+    if lines.len() == MAX_LINES {
+        lines.remove(0);
+    }
+    lines.push(line);
+}
+
+/// A byte count in the largest unit it fills, e.g. "1.5 MiB".
+#[allow(clippy::cast_precision_loss)]
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+/// The last lines of `lines` which fit inside the borders of `area`.
+fn tail<T>(lines: &[T], area: Rect) -> &[T] {
+    let height = usize::from(area.height.saturating_sub(2));
+    &lines[lines.len().saturating_sub(height)..]
+}